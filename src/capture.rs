@@ -0,0 +1,214 @@
+//Screenshots and recordings of the emulated display, for bug reports and regression tests.
+//
+//Frames are saved as PNG, or as PPM if the path ends in .ppm. Text screens are also saved as UTF-8 text, one line per row.
+//A recording to a .y4m path is YUV4MPEG2 video, which ffmpeg and most players read. A Y4M file has one frame rate,
+//so it is the adapter's refresh rate when the recording starts. If the mode changes the refresh rate later on,
+//frames are repeated or dropped to keep the video in time with the emulator.
+//A recording to any other path is a directory of numbered PNG frames.
+//
+//Golden files hold what the screen should show at the end of a headless run. Images are compared byte for byte,
+//so golden images have to come from this encoder, by running with --update-golden.
+
+use crate::chips::framebuffer::Framebuffer;
+use crate::chips::crtc::TextScreen;
+use crate::clock::MASTER_HZ;
+use crate::terminal::cp437_to_char;
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use log::debug;
+
+enum Output {
+  Y4M {
+    file: BufWriter<File>,
+    size: Option<(usize, usize)>,  //Set from the first frame. Video can't change size, so later frames are cropped or padded to it.
+    frame_time: u64,  //Master clock cycles per video frame, set from the first frame.
+  },
+  Frames(PathBuf),
+}
+
+pub struct Recorder {
+  output: Output,
+  frames: u64,    //Frames written.
+  elapsed: u64,   //Master clock cycles of screen time recorded.
+}
+
+/// Save a frame as PNG, or as PPM if the path ends in .ppm.
+pub fn save_frame(frame: &Framebuffer, path: &Path) -> io::Result<()> {
+  fs::write(path, encode_frame(frame, path)?)
+}
+
+/// A frame as PNG, or as PPM if the path ends in .ppm.
+pub fn encode_frame(frame: &Framebuffer, path: &Path) -> io::Result<Vec<u8>> {
+  let mut result = Vec::new();
+  match path.extension().and_then(|extension| extension.to_str()) {
+    Some("ppm") => frame.write_ppm(&mut result)?,
+    _ => frame.write_png(&mut result)?,
+  }
+  Ok(result)
+}
+
+/// Compare against a golden file, or replace it if updating.
+/// A mismatch is saved alongside as NAME.actual.EXT, to look at, or to copy over the golden file once it is right.
+pub fn check_golden(actual: &[u8], path: &Path, update: bool) -> io::Result<()> {
+  let expected = if update {
+    None
+  } else {
+    match fs::read(path) {
+      Ok(expected) => Some(expected),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+      Err(err) => return Err(err),
+    }
+  };
+  match expected {
+    Some(expected) if expected == actual => Ok(()),
+    Some(_) => {
+      let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+      let actual_path = path.with_extension(format!("actual.{}", extension));
+      fs::write(&actual_path, actual)?;
+      Err(io::Error::other(format!("The screen does not match {}. What it showed is in {}", path.display(), actual_path.display())))
+    },
+    None => {
+      if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+      }
+      fs::write(path, actual)?;
+      if update {
+        debug!("Updated golden file {}", path.display());
+        return Ok(());
+      }
+      Err(io::Error::other(format!("There was no golden file {}. It has been written from this run, so check it before running again.", path.display())))
+    },
+  }
+}
+
+/// The characters on a text screen, one line per row. Trailing spaces are left off.
+pub fn screen_text(screen: &TextScreen) -> String {
+  let mut result = String::new();
+  for row in screen.cells.chunks(screen.columns) {
+    let line: String = row.iter().map(|&(character, _)| cp437_to_char(character)).collect();
+    result.push_str(line.trim_end_matches(' '));
+    result.push('\n');
+  }
+  result
+}
+
+/// Start a recording. A path ending in .y4m is a video file. Anything else is a directory for numbered PNG frames.
+pub fn start(path: &str) -> io::Result<Recorder> {
+  let output = if path.ends_with(".y4m") {
+    Output::Y4M {
+      file: BufWriter::new(File::create(path)?),
+      size: None,
+      frame_time: 0,
+    }
+  } else {
+    fs::create_dir_all(path)?;
+    Output::Frames(PathBuf::from(path))
+  };
+  debug!("Recording the screen to {}", path);
+  Ok(Recorder {
+    output,
+    frames: 0,
+    elapsed: 0,
+  })
+}
+
+impl Recorder {
+  /// Add a frame, which is on screen for frame_time master clock cycles.
+  pub fn add_frame(&mut self, frame: &Framebuffer, frame_time: u64) -> io::Result<()> {
+    match &mut self.output {
+      Output::Y4M{file, size, frame_time: video_frame_time} => {
+        let (width, height) = *size.get_or_insert((frame.width, frame.height));
+        if self.frames == 0 {
+          //The frame rate is taken from the first frame, as a fraction of the master clock.
+          *video_frame_time = frame_time.max(1);
+          writeln!(file, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, MASTER_HZ, video_frame_time)?;
+        }
+        //Write this frame as many times as it takes to catch the video up to the end of its screen time, rounded.
+        //That is once when the refresh rate hasn't changed since the start.
+        self.elapsed += frame_time;
+        let due = (self.elapsed + *video_frame_time / 2) / *video_frame_time;
+        while self.frames < due.max(1) {
+          write_y4m_frame(file, frame, width, height)?;
+          self.frames += 1;
+        }
+      },
+      Output::Frames(directory) => {
+        save_frame(frame, &directory.join(format!("frame-{:06}.png", self.frames)))?;
+        self.frames += 1;
+      },
+    }
+    Ok(())
+  }
+}
+
+/// Y, then Cb, then Cr, each at full resolution. BT.601 studio range.
+fn write_y4m_frame(out: &mut impl Write, frame: &Framebuffer, width: usize, height: usize) -> io::Result<()> {
+  let mut planes = [Vec::with_capacity(width * height), Vec::with_capacity(width * height), Vec::with_capacity(width * height)];
+  for y in 0..height {
+    for x in 0..width {
+      let [red, green, blue] = if x < frame.width && y < frame.height { frame.pixel(x, y) } else { [0, 0, 0] };
+      let (red, green, blue) = (red as f32, green as f32, blue as f32);
+      planes[0].push((16.0 + 0.257 * red + 0.504 * green + 0.098 * blue).round() as u8);
+      planes[1].push((128.0 - 0.148 * red - 0.291 * green + 0.439 * blue).round() as u8);
+      planes[2].push((128.0 + 0.439 * red - 0.368 * green - 0.071 * blue).round() as u8);
+    }
+  }
+  out.write_all(b"FRAME\n")?;
+  for plane in &planes {
+    out.write_all(plane)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chips::framebuffer;
+
+  const CGA_FRAME_TIME: u64 = 238_944;  //912 x 262 dots
+
+  fn record_y4m(name: &str, frame_times: &[u64]) -> Vec<u8> {
+    let path = std::env::temp_dir().join(format!("remu-{}-{}.y4m", name, std::process::id()));
+    let mut recorder = start(path.to_str().unwrap()).unwrap();
+    for &frame_time in frame_times {
+      recorder.add_frame(&framebuffer::new(4, 2), frame_time).unwrap();
+    }
+    drop(recorder);
+    let video = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    video
+  }
+
+  fn frames_in(video: &[u8]) -> usize {
+    video.windows(6).filter(|window| window == b"FRAME\n").count()
+  }
+
+  #[test]
+  fn y4m_header_has_the_first_frame_rate() {
+    let video = record_y4m("header", &[CGA_FRAME_TIME; 3]);
+    let header = format!("YUV4MPEG2 W4 H2 F{}:{} Ip A1:1 C444\n", MASTER_HZ, CGA_FRAME_TIME);
+    assert!(video.starts_with(header.as_bytes()));
+    assert_eq!(frames_in(&video), 3);
+    assert_eq!(video.len(), header.len() + 3 * (6 + 4 * 2 * 3));
+  }
+
+  #[test]
+  fn y4m_keeps_time_when_the_refresh_rate_changes() {
+    let video = record_y4m("faster", &[CGA_FRAME_TIME, CGA_FRAME_TIME / 2, CGA_FRAME_TIME / 2, CGA_FRAME_TIME / 2, CGA_FRAME_TIME / 2]);
+    assert_eq!(frames_in(&video), 3, "Twice the refresh rate drops every other frame.");
+    let video = record_y4m("slower", &[CGA_FRAME_TIME, CGA_FRAME_TIME * 2, CGA_FRAME_TIME * 2]);
+    assert_eq!(frames_in(&video), 5, "Half the refresh rate shows each frame twice.");
+  }
+
+  #[test]
+  fn y4m_is_studio_range() {
+    let mut frame = framebuffer::new(2, 1);
+    frame.set_pixel(1, 0, [0xFF, 0xFF, 0xFF]);
+    let mut out = Vec::new();
+    write_y4m_frame(&mut out, &frame, 3, 1).unwrap();
+    assert_eq!(out, b"FRAME\n\x10\xEB\x10\x80\x80\x80\x80\x80\x80", "Black, white, then padding.");
+  }
+}
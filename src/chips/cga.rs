@@ -0,0 +1,342 @@
+//IBM Color Graphics Adapter (CGA)
+//http://www.seasip.info/VintagePC/cga.html
+//
+//40x25 or 80x25 text, 320x200 in 4 colors or 640x200 in 2, from 16KB of memory at B8000, which repeats through BFFFF.
+//3D0-3D7: 6845 (even ports select the register, odd ports hold its data). 3D8: mode. 3D9: color select. 3DA: status.
+//3DB clears the light pen latch, and 3DC sets it.
+
+use super::crtc::{self, Crtc, TextScreen};
+use super::framebuffer::{self, Framebuffer};
+use super::video_memory::{self, VideoMemory};
+use super::font;
+
+use log::debug;
+
+pub const MEMORY_START: usize = 0xB_8000;
+pub const MEMORY_WINDOW: usize = 0x8000;
+const MEMORY_SIZE: usize = 0x4000;
+
+pub const PALETTE: [[u8; 3]; 16] = [
+  [0x00, 0x00, 0x00], [0x00, 0x00, 0xAA], [0x00, 0xAA, 0x00], [0x00, 0xAA, 0xAA],
+  [0xAA, 0x00, 0x00], [0xAA, 0x00, 0xAA], [0xAA, 0x55, 0x00], [0xAA, 0xAA, 0xAA],
+  [0x55, 0x55, 0x55], [0x55, 0x55, 0xFF], [0x55, 0xFF, 0x55], [0x55, 0xFF, 0xFF],
+  [0xFF, 0x55, 0x55], [0xFF, 0x55, 0xFF], [0xFF, 0xFF, 0x55], [0xFF, 0xFF, 0xFF],
+];
+
+#[derive(Debug, Default)]
+struct Mode {
+  text_80x25: bool,
+  graphics: bool,
+  black_white: bool,
+  enabled: bool,
+  high_resolution: bool,  //640x200
+  blink: bool,
+}
+
+pub struct Cga {
+  crtc: Crtc,
+  memory: VideoMemory,
+  mode: Mode,
+  color_select: u8,
+  composite: bool,   //Show the artifact colors a composite monitor would.
+  light_pen_triggered: bool,
+}
+
+pub fn start() -> Cga {
+  Cga {
+    crtc: crtc::start(),
+    memory: video_memory::new(MEMORY_SIZE),
+    mode: Default::default(),
+    color_select: 0,
+    composite: false,
+    light_pen_triggered: false,
+  }
+}
+
+impl Cga {
+  /// To be mapped into the memory map at MEMORY_START.
+  pub fn video_memory(&self) -> VideoMemory {
+    self.memory.clone()
+  }
+
+  pub fn set_composite(&mut self, composite: bool) {
+    self.composite = composite;
+  }
+
+  pub fn out_byte(&mut self, port: u16, value: u8, now: u64) {
+    match port {
+      0x3D0..=0x3D7 if port & 1 == 0 => self.crtc.choose_register(value),
+      0x3D0..=0x3D7 => self.crtc.set_register_data(value),
+      0x3D8 => self.set_mode(value),
+      0x3D9 => self.set_color_select(value),
+      0x3DB => self.light_pen_triggered = false,
+      0x3DC => {
+        self.crtc.strobe_light_pen(self.character_clocks(now));
+        self.light_pen_triggered = true;
+      },
+      _ => debug!("CGA port {:X} got {:X}. Nothing is there.", port, value),
+    }
+  }
+
+  pub fn in_byte(&mut self, port: u16, now: u64) -> u8 {
+    match port {
+      0x3D0..=0x3D7 if port & 1 == 1 => self.crtc.get_register_data(),
+      0x3DA => self.get_status(now),
+      _ => 0xFF,
+    }
+  }
+
+  /// Bit 0: 80x25 text. Bit 1: Graphics. Bit 2: Black and white. Bit 3: Video enabled. Bit 4: 640x200. Bit 5: Blink.
+  fn set_mode(&mut self, register: u8) {
+    self.mode.text_80x25 = matches!(register & 0b1, 0b1);
+    self.mode.graphics = matches!(register & 0b10, 0b10);
+    self.mode.black_white = matches!(register & 0b100, 0b100);
+    self.mode.enabled = matches!(register & 0b1000, 0b1000);
+    self.mode.high_resolution = matches!(register & 0b1_0000, 0b1_0000);
+    self.mode.blink = matches!(register & 0b10_0000, 0b10_0000);
+    debug!("{:?}", self.mode);
+  }
+
+  /// Bits 0-3: Border color in text modes, background in 320x200, foreground in 640x200.
+  /// Bit 4: Intense palette colors in 320x200. Bit 5: Palette 1 (cyan, magenta, white) instead of 0 (green, red, brown).
+  fn set_color_select(&mut self, register: u8) {
+    self.color_select = register;
+    debug!("Color select {:06b}", register);
+  }
+
+  /// Characters clocked out since power on, at the given master clock cycle.
+  /// The dot clock is the master clock itself. A character is 8 dots in 80 column text, and 16 otherwise.
+  fn character_clocks(&self, now: u64) -> u64 {
+    now / self.dots_per_character()
+  }
+
+  fn dots_per_character(&self) -> u64 {
+    if self.mode.text_80x25 { 8 } else { 16 }
+  }
+
+  /// Length of a frame in master clock cycles, which is about 60 Hz.
+  pub fn frame_time(&self) -> u64 {
+    self.crtc.frame_clocks() * self.dots_per_character()
+  }
+
+  /// Port 3DA.
+  /// Bit 0: Not in the display area, so memory can be accessed without snow. Bit 1: Light pen triggered.
+  /// Bit 2: Light pen switch open. Bit 3: Vertical retrace.
+  fn get_status(&self, now: u64) -> u8 {
+    let (display_enable, _, vertical_sync) = self.crtc.raster_status(self.character_clocks(now));
+    let mut result = 0xF0 | 0b100;  //There is no light pen, so its switch is always open.
+    if !display_enable { result |= 0b1 }
+    if self.light_pen_triggered { result |= 0b10 }
+    if vertical_sync { result |= 0b1000 }
+    result
+  }
+
+  /// None when the display is off, or in a graphics mode.
+  pub fn text_screen(&self) -> Option<TextScreen> {
+    if !self.mode.enabled || self.mode.graphics {
+      return None;
+    }
+    let default_columns = if self.mode.text_80x25 { 80 } else { 40 };
+    Some(self.crtc.text_screen(&self.memory.lock(), default_columns, true, self.mode.blink))
+  }
+
+  /// Draw the screen as the monitor would show it at the given master clock cycle, which sets the blink phase.
+  pub fn render(&self, time: u64) -> Framebuffer {
+    match self.text_screen() {
+      Some(screen) => {
+        let frame = self.crtc.frame_number(self.character_clocks(time));
+        crtc::render_text(&screen, frame, 8, font::glyph_8x8, colors)
+      },
+      None if self.mode.enabled => self.render_graphics(),
+      None => framebuffer::new(640, 200),
+    }
+  }
+
+  /// Each character position is 2 bytes, read from B8000 for even scan lines and BA000 for odd ones.
+  fn render_graphics(&self) -> Framebuffer {
+    let columns = self.crtc.columns(40);
+    let rows = self.crtc.rows(100);
+    let lines_per_row = self.crtc.lines_per_row();
+    let width = columns * if self.mode.high_resolution { 16 } else { 8 };
+    let mut image = framebuffer::new(width, rows * lines_per_row);
+    let memory = self.memory.lock();
+
+    let mut line_bytes = Vec::with_capacity(columns * 2);
+    for row in 0..rows {
+      for line in 0..lines_per_row {
+        line_bytes.clear();
+        for index in 0..columns * 2 {
+          let offset = ((self.crtc.start_address() + row * columns) * 2 + index) & 0x1FFF;
+          line_bytes.push(memory[offset | ((line & 1) << 13)]);
+        }
+        let y = row * lines_per_row + line;
+        if self.mode.high_resolution {
+          self.draw_line_640(&mut image, y, &line_bytes);
+        } else {
+          self.draw_line_320(&mut image, y, &line_bytes);
+        }
+      }
+    }
+    image
+  }
+
+  /// 2 bits per pixel. Color 0 is the background from the color select register.
+  fn draw_line_320(&self, image: &mut Framebuffer, y: usize, line_bytes: &[u8]) {
+    let intensity = if self.color_select & 0b1_0000 != 0 { 8 } else { 0 };
+    //Mode register bit 2 swaps in a third palette on an RGB monitor.
+    let palette = if self.mode.black_white {
+      [3, 4, 7]
+    } else if self.color_select & 0b10_0000 != 0 {
+      [3, 5, 7]
+    } else {
+      [2, 4, 6]
+    };
+    for (index, byte) in line_bytes.iter().enumerate() {
+      for pixel in 0..4 {
+        let color = match (byte >> (6 - pixel * 2)) & 0b11 {
+          0 => self.color_select & 0x0F,
+          color => palette[color as usize - 1] + intensity,
+        };
+        image.set_pixel(index * 4 + pixel, y, PALETTE[color as usize]);
+      }
+    }
+  }
+
+  /// 1 bit per pixel, in the color from the color select register.
+  fn draw_line_640(&self, image: &mut Framebuffer, y: usize, line_bytes: &[u8]) {
+    let foreground = PALETTE[(self.color_select & 0x0F) as usize];
+    let dots: Vec<bool> = line_bytes.iter().flat_map(|byte| (0..8).map(move |bit| byte & (0x80 >> bit) != 0)).collect();
+    if self.composite {
+      for (group, group_dots) in dots.chunks(4).enumerate() {
+        let color = artifact_color(group_dots, &foreground);
+        for x in 0..group_dots.len() {
+          image.set_pixel(group * 4 + x, y, color);
+        }
+      }
+      return;
+    }
+    for (x, &dot) in dots.iter().enumerate() {
+      image.set_pixel(x, y, if dot { foreground } else { PALETTE[0] });
+    }
+  }
+}
+
+/// A composite monitor decodes color from the signal at the 3.58 MHz NTSC color subcarrier, which is 4 dots at 640x200.
+/// So each group of 4 dots comes out as one of 16 colors, which is how 160x200 16 color games work.
+fn artifact_color(dots: &[bool], foreground: &[u8; 3]) -> [u8; 3] {
+  let level = (foreground[0] as f32 * 0.299 + foreground[1] as f32 * 0.587 + foreground[2] as f32 * 0.114) / 255.0;
+  let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+  for (x, &dot) in dots.iter().enumerate() {
+    if !dot { continue; }
+    //Each dot is a quarter of a subcarrier cycle. The phase is chosen so 1100 comes out orange, and 0011 blue.
+    let phase = std::f32::consts::FRAC_PI_2 * x as f32 - std::f32::consts::FRAC_PI_4;
+    y += level / 4.0;
+    i += level * phase.cos() / 4.0;
+    q += level * phase.sin() / 4.0;
+  }
+  let to_byte = |value: f32| (value.clamp(0.0, 1.0) * 255.0) as u8;
+  [
+    to_byte(y + 0.956 * i + 0.621 * q),
+    to_byte(y - 0.272 * i - 0.647 * q),
+    to_byte(y - 1.106 * i + 1.703 * q),
+  ]
+}
+
+/// Bits 0-3 foreground, 4-6 background, 7 blink or bright background.
+fn colors(attribute: u8, blink: bool) -> ([u8; 3], [u8; 3], bool) {
+  let background = if blink { (attribute >> 4) & 0x07 } else { attribute >> 4 };
+  (PALETTE[(attribute & 0x0F) as usize], PALETTE[background as usize], false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chips::memory1mb::MemoryDevice;
+
+  #[test]
+  fn attributes() {
+    assert_eq!(colors(0x1E, true), (PALETTE[14], PALETTE[1], false));
+    assert_eq!(colors(0x9E, true), (PALETTE[14], PALETTE[1], false), "Bit 7 blinks.");
+    assert_eq!(colors(0x9E, false), (PALETTE[14], PALETTE[9], false), "Or brightens the background.");
+  }
+
+  const GRAPHICS_320: u8 = 0b1010;
+  const GRAPHICS_640: u8 = 0b1_1010;
+
+  //One character clock across (2 bytes), one row of 2 scan lines.
+  fn small_screen(mode: u8, color_select: u8) -> (Cga, VideoMemory) {
+    let mut cga = start();
+    for (register, value) in [(1, 1), (6, 1), (9, 1)] {
+      cga.out_byte(0x3D4, register, 0);
+      cga.out_byte(0x3D5, value, 0);
+    }
+    cga.out_byte(0x3D8, mode, 0);
+    cga.out_byte(0x3D9, color_select, 0);
+    let memory = cga.video_memory();
+    (cga, memory)
+  }
+
+  fn row(image: &Framebuffer, y: usize) -> Vec<[u8; 3]> {
+    (0..image.width).map(|x| image.pixel(x, y)).collect()
+  }
+
+  #[test]
+  fn graphics_320_is_2_bits_per_pixel() {
+    let (cga, mut memory) = small_screen(GRAPHICS_320, 0x01);
+    memory.write_byte(MEMORY_START, 0b00_01_10_11);
+    memory.write_byte(MEMORY_START + 0x2000, 0b11_00_00_00);  //Odd scan lines
+    let image = cga.render(0);
+    assert_eq!((image.width, image.height), (8, 2));
+    assert_eq!(row(&image, 0)[..4], [PALETTE[1], PALETTE[2], PALETTE[4], PALETTE[6]], "Blue background, then palette 0.");
+    assert_eq!(row(&image, 1)[..2], [PALETTE[6], PALETTE[1]]);
+  }
+
+  #[test]
+  fn graphics_320_palette_1_and_intensity() {
+    let (cga, mut memory) = small_screen(GRAPHICS_320, 0b11_0000);
+    memory.write_byte(MEMORY_START, 0b00_01_10_11);
+    let image = cga.render(0);
+    assert_eq!(row(&image, 0)[..4], [PALETTE[0], PALETTE[11], PALETTE[13], PALETTE[15]]);
+  }
+
+  #[test]
+  fn graphics_640_is_1_bit_per_pixel_in_the_color_select_color() {
+    let (cga, mut memory) = small_screen(GRAPHICS_640, 0x0E);
+    memory.write_byte(MEMORY_START, 0b1010_0000);
+    memory.write_byte(MEMORY_START + 1, 0b0000_0001);
+    let image = cga.render(0);
+    assert_eq!((image.width, image.height), (16, 2));
+    let line = row(&image, 0);
+    assert_eq!(line[..3], [PALETTE[14], PALETTE[0], PALETTE[14]]);
+    assert_eq!(line[15], PALETTE[14]);
+    assert_eq!(line[14], PALETTE[0]);
+  }
+
+  #[test]
+  fn composite_640_groups_4_dots_into_a_color() {
+    let (mut cga, mut memory) = small_screen(GRAPHICS_640, 0x0F);
+    cga.set_composite(true);
+    memory.write_byte(MEMORY_START, 0b1111_0000);
+    let image = cga.render(0);
+    let line = row(&image, 0);
+    assert_eq!(line[0], [0xFF, 0xFF, 0xFF], "All 4 dots on is white.");
+    assert_eq!(line[4], [0x00, 0x00, 0x00]);
+    assert_eq!(line[0], line[3]);
+  }
+
+  #[test]
+  fn status_register_retrace_bits() {
+    let mut cga = start();
+    //Same timing as the 6845 test: 10 clocks per line, 8 displayed, 5 lines displayed, vertical sync from line 7.
+    for (register, value) in [9, 8, 8, 2, 29, 0, 5, 7, 0, 0].into_iter().enumerate() {
+      cga.out_byte(0x3D4, register as u8, 0);
+      cga.out_byte(0x3D5, value, 0);
+    }
+    let status = |cga: &mut Cga, character_clocks: u64| cga.in_byte(0x3DA, character_clocks * 16);  //40 columns: 16 dots a character.
+    assert_eq!(status(&mut cga, 0), 0xF4);
+    assert_eq!(status(&mut cga, 8), 0xF5, "Horizontal retrace");
+    assert_eq!(status(&mut cga, 50), 0xF5, "Below the display area");
+    assert_eq!(status(&mut cga, 155), 0xFD, "Vertical retrace");
+    assert_eq!(status(&mut cga, 255), 0xF5);
+  }
+}
@@ -1,7 +1,7 @@
 use crate::CPUMsg;
 
 use std::sync::{mpsc, Arc};
-use std::sync::atomic::{AtomicI16,Ordering};
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
 
 use std::thread;
 
@@ -15,20 +15,20 @@ use definitions::flag::Flags;
 
 use log::debug;
 
-use simplelog::*;
   
 pub struct CPU {
   pub memory: Memory,
   pub regs: Registers,
   pub flags: Flags,
   pub current_address: usize,
+  pub halted: bool,  //After HLT, until an interrupt.
   pub messenger: mpsc::Sender<crate::Msg>,
 }
 
-use std::fs::File;
-
-pub fn start(messenger: mpsc::Sender<crate::Msg>, from_clock: mpsc::Receiver<()>) -> CPUController {
-  let interrupt_arc = Arc::new(AtomicI16::new(-1));
+pub fn start(messenger: mpsc::Sender<crate::Msg>, mut clock: crate::clock::CPUClock) -> CPUController {
+  let intr_arc = Arc::new(AtomicBool::new(false));
+  let nmi_arc = Arc::new(AtomicBool::new(false));
+  let stolen_cycles_arc = Arc::new(AtomicUsize::new(0));
   
   let memory = Memory {
     cs: 0xF000,
@@ -37,6 +37,7 @@ pub fn start(messenger: mpsc::Sender<crate::Msg>, from_clock: mpsc::Receiver<()>
     es: 0,
     ip: 0xFFF0,
     current_segment: Segment::DS,
+    segment_override: false,
     messenger: messenger.clone(),
     current_instruction: 0,
   };
@@ -46,65 +47,65 @@ pub fn start(messenger: mpsc::Sender<crate::Msg>, from_clock: mpsc::Receiver<()>
   let mut cpu = CPU {
     memory,
     current_address,
+    halted: false,
     messenger: messenger.clone(),
     regs: Default::default(),
     flags: Default::default(),
   };
   
-  let interrupt = Arc::clone(&interrupt_arc);
-  
-  let mut logging = false;
+  let intr = Arc::clone(&intr_arc);
+  let nmi = Arc::clone(&nmi_arc);
+  let stolen_cycles = Arc::clone(&stolen_cycles_arc);
   
   thread::spawn(move || {
     loop {
-      cpu.memory.current_instruction = cpu.get_full_instruction();
-      if cpu.memory.cs == 0xF000 && cpu.memory.ip == 0xE3C6 {
-        logging = true;
-      }
-      if false {
-//      if cpu.memory.current_instruction & 0xFF == 0xE8 {
-        if !logging {
-          logging = true;
-          CombinedLogger::init(
-            vec![
-                TermLogger::new(LevelFilter::Debug, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-                WriteLogger::new(LevelFilter::Trace, Config::default(), File::create("trace.log").unwrap()),
-            ]
-          ).unwrap();
-        }
-      }
-      if logging {
-        cpu.print_registers();
-      }
-
-      let cycles = instructions::lookup::run_next_instruction(&mut cpu);
-      for _ in 0..cycles {
-        from_clock.recv().unwrap();
-      }
+      let mut cycles = stolen_cycles.swap(0, Ordering::Relaxed);
+      cycles += if cpu.halted {
+        4  //Nothing runs, but time goes on until an interrupt.
+      } else {
+        cpu.memory.current_instruction = cpu.get_full_instruction();
+        instructions::lookup::run_next_instruction(&mut cpu)
+      };
 
-      //-1 = no interrupt. Positive 8 bit = interrupt.
-      let int_index = interrupt.load(Ordering::Relaxed);
-      if int_index >= 0 {
-        interrupt.store(-1, Ordering::Relaxed);
-        instructions::jump::hardware_int(&mut cpu, int_index as u8);
+      //NMI takes priority over the maskable interrupts.
+      //The PIC holds INTR high until it is acknowledged. Only listen to it with the interrupt flag set.
+      if nmi.swap(false, Ordering::Relaxed) {
+        cpu.halted = false;
+        cycles += instructions::jump::hardware_int(&mut cpu, 2);
+      } else if cpu.flags.interrupt && intr.load(Ordering::Relaxed) {
+        cpu.halted = false;
+        let int_index = cpu.acknowledge_interrupt();
+        cycles += instructions::jump::hardware_int(&mut cpu, int_index);
       }
+      clock.add_cycles(cycles);
     }
   });
   
   CPUController {
-    interrupt: Arc::clone(&interrupt_arc),
+    intr: Arc::clone(&intr_arc),
+    nmi: Arc::clone(&nmi_arc),
+    stolen_cycles: Arc::clone(&stolen_cycles_arc),
   }
 }
 
 pub struct CPUController {
-  interrupt: Arc<AtomicI16>,  //-1 = no interrupt. Positive 8 bit = interrupt.
+  intr: Arc<AtomicBool>,
+  nmi: Arc<AtomicBool>,
+  stolen_cycles: Arc<AtomicUsize>,
 }
 
 impl CPUController {
   pub fn process_msg(&mut self, msg: CPUMsg) {
     match msg {
-      CPUMsg::Interrupt(index) => {
-        self.interrupt.store(index as i16, Ordering::Relaxed);  //  //-1 = no interrupt. Positive 8 bit = interrupt.
+      CPUMsg::INTR(intr) => {
+        self.intr.store(intr, Ordering::Relaxed);
+      },
+      CPUMsg::StealCycles(cycles) => {
+        self.stolen_cycles.fetch_add(cycles, Ordering::Relaxed);
+      },
+      CPUMsg::NMI => {
+        debug!("NMI");
+        self.nmi.store(true, Ordering::Relaxed);
       },
     }
   }
@@ -129,6 +130,14 @@ impl CPU {
     rx.recv().unwrap()
   }
 
+  /// INTA bus cycle. The PIC replies with the interrupt vector.
+  pub fn acknowledge_interrupt(&self) -> u8 {
+    let (socket, rx) = mpsc::channel();
+    let msg = crate::Msg::PIC(crate::PICMsg::Acknowledge{socket});
+    self.messenger.send(msg).unwrap();
+    rx.recv().unwrap()
+  }
+
   pub fn read_byte(&mut self, op: &operand::Byte) -> u8 {
     match op {
       operand::Byte::Mem{addr, ..} => self.memory.get_byte(*addr),
@@ -162,3 +171,199 @@ impl CPU {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::MemoryMsg;
+
+  const PROGRAM: usize = 0x100;
+
+  /// A CPU at 0000:0100, with 1MB of RAM answering its bus messages from another thread.
+  fn cpu_with_program(program: &[u8]) -> CPU {
+    let (messenger, bus) = mpsc::channel();
+    let mut ram = vec![0u8; 0x10_0000];
+    ram[PROGRAM..PROGRAM + program.len()].copy_from_slice(program);
+    thread::spawn(move || {
+      for msg in bus {
+        if let crate::Msg::Memory(msg) = msg {
+          match msg {
+            MemoryMsg::SetByte{addr, value} => ram[addr] = value,
+            MemoryMsg::SetWord{addr, value} => ram[addr..addr + 2].copy_from_slice(&value.to_le_bytes()),
+            MemoryMsg::GetByte{addr, socket} => socket.send(ram[addr]).unwrap(),
+            MemoryMsg::GetWord{addr, socket} => socket.send(u16::from_le_bytes([ram[addr], ram[addr + 1]])).unwrap(),
+            MemoryMsg::GetBytes8{addr, socket} => socket.send(u64::from_le_bytes(ram[addr..addr + 8].try_into().unwrap())).unwrap(),
+            MemoryMsg::InjectParityError{..} => (),
+          }
+        }
+      }
+    });
+
+    let memory = Memory {
+      cs: 0,
+      ds: 0,
+      ss: 0,
+      es: 0,
+      ip: PROGRAM as u16,
+      current_segment: Segment::DS,
+      segment_override: false,
+      messenger: messenger.clone(),
+      current_instruction: 0,
+    };
+    let mut cpu = CPU {
+      memory,
+      current_address: PROGRAM,
+      halted: false,
+      messenger,
+      regs: Default::default(),
+      flags: Default::default(),
+    };
+    cpu.regs.sp = 0x1000;
+    cpu
+  }
+
+  fn step(cpu: &mut CPU) -> usize {
+    cpu.memory.current_instruction = cpu.get_full_instruction();
+    instructions::lookup::run_next_instruction(cpu)
+  }
+
+  /// Step a REP instruction until it moves on, which is how the thread loop runs it.
+  fn run_rep(cpu: &mut CPU) {
+    let start = cpu.memory.ip;
+    for _ in 0..0x100 {
+      step(cpu);
+      if cpu.memory.ip != start {
+        return;
+      }
+    }
+    panic!("REP never finished");
+  }
+
+  fn set_bytes(cpu: &mut CPU, addr: usize, bytes: &[u8]) {
+    for (i, byte) in bytes.iter().enumerate() {
+      cpu.memory.set_byte_msg(addr + i, *byte);
+    }
+  }
+
+  fn pushed_ip(cpu: &CPU) -> u16 {
+    cpu.memory.get_word_seg(Segment::SS, cpu.regs.sp)
+  }
+
+  #[test]
+  fn rep_with_cx_zero_skips_the_string_instruction() {
+    let mut cpu = cpu_with_program(&[0xF3, 0xA4]);  //REP MOVSB
+    cpu.regs.si = 0x200;
+    cpu.regs.di = 0x300;
+    set_bytes(&mut cpu, 0x200, b"A");
+    assert_eq!(step(&mut cpu), 9);
+    assert_eq!(cpu.memory.ip, 0x102);
+    assert_eq!((cpu.regs.si, cpu.regs.di), (0x200, 0x300));
+    assert_eq!(cpu.memory.get_byte_msg(0x300), 0);
+  }
+
+  #[test]
+  fn rep_movsb_copies_cx_bytes() {
+    let mut cpu = cpu_with_program(&[0xF3, 0xA4]);  //REP MOVSB
+    cpu.regs.cx = 3;
+    cpu.regs.si = 0x200;
+    cpu.regs.di = 0x300;
+    set_bytes(&mut cpu, 0x200, b"ABCD");
+    run_rep(&mut cpu);
+    assert_eq!(cpu.regs.cx, 0);
+    assert_eq!((cpu.regs.si, cpu.regs.di), (0x203, 0x303));
+    let copied: Vec<u8> = (0x300..0x304).map(|addr| cpu.memory.get_byte_msg(addr)).collect();
+    assert_eq!(copied, b"ABC\0");
+  }
+
+  #[test]
+  fn repe_cmpsb_stops_at_the_first_difference() {
+    let mut cpu = cpu_with_program(&[0xF3, 0xA6]);  //REPE CMPSB
+    cpu.regs.cx = 4;
+    cpu.regs.si = 0x200;
+    cpu.regs.di = 0x300;
+    set_bytes(&mut cpu, 0x200, b"ABXD");
+    set_bytes(&mut cpu, 0x300, b"ABYD");
+    run_rep(&mut cpu);
+    assert_eq!(cpu.regs.cx, 1);
+    assert_eq!(cpu.regs.si, 0x203);
+    assert!(!cpu.flags.zero);
+  }
+
+  #[test]
+  fn repne_scasb_stops_at_a_match() {
+    let mut cpu = cpu_with_program(&[0xF2, 0xAE]);  //REPNE SCASB
+    cpu.regs.ax = b'C' as u16;
+    cpu.regs.cx = 4;
+    cpu.regs.di = 0x300;
+    set_bytes(&mut cpu, 0x300, b"ABCD");
+    run_rep(&mut cpu);
+    assert_eq!(cpu.regs.cx, 1);
+    assert_eq!(cpu.regs.di, 0x303);
+    assert!(cpu.flags.zero);
+  }
+
+  #[test]
+  fn into_only_interrupts_on_overflow() {
+    let mut cpu = cpu_with_program(&[0xCE, 0xCE]);  //INTO, INTO
+    cpu.memory.set_word_msg(4 * 4, 0x0400);
+    assert_eq!(step(&mut cpu), 4);
+    assert_eq!(cpu.memory.ip, 0x101);
+
+    cpu.flags.overflow = true;
+    step(&mut cpu);
+    assert_eq!((cpu.memory.cs, cpu.memory.ip), (0, 0x400));
+    assert_eq!(pushed_ip(&cpu), 0x102);
+  }
+
+  #[test]
+  fn hlt_stops_after_the_instruction() {
+    let mut cpu = cpu_with_program(&[0xF4]);  //HLT
+    step(&mut cpu);
+    assert!(cpu.halted);
+    assert_eq!(cpu.memory.ip, 0x101);
+  }
+
+  #[test]
+  fn div_by_zero_raises_interrupt_0() {
+    let mut cpu = cpu_with_program(&[0xF6, 0xF3]);  //DIV BL
+    cpu.memory.set_word_msg(0, 0x0500);
+    cpu.regs.ax = 0x1234;
+    step(&mut cpu);
+    assert_eq!(cpu.memory.ip, 0x500);
+    assert_eq!(pushed_ip(&cpu), 0x102);
+    assert_eq!(cpu.regs.ax, 0x1234);
+  }
+
+  #[test]
+  fn div_overflow_raises_interrupt_0() {
+    let mut cpu = cpu_with_program(&[0xF6, 0xF3, 0xF6, 0xF3]);  //DIV BL, DIV BL
+    cpu.memory.set_word_msg(0, 0x0500);
+    cpu.regs.ax = 0x01FF;
+    cpu.regs.bx = 2;
+    step(&mut cpu);
+    assert_eq!(cpu.regs.ax, 0x01FF);  //0xFF remainder 1 fits.
+    assert_eq!(cpu.memory.ip, 0x102);
+
+    cpu.regs.ax = 0x0200;
+    step(&mut cpu);
+    assert_eq!(cpu.memory.ip, 0x500);
+  }
+
+  #[test]
+  fn idiv_rejects_the_most_negative_quotient() {
+    let mut cpu = cpu_with_program(&[0xF6, 0xFB]);  //IDIV BL
+    cpu.memory.set_word_msg(0, 0x0500);
+    cpu.regs.ax = (-256i16) as u16;
+    cpu.regs.bx = 2;
+    step(&mut cpu);
+    assert_eq!(cpu.memory.ip, 0x500);
+  }
+
+  #[test]
+  fn daa_adjusts_both_digits() {
+    let mut cpu = cpu_with_program(&[0x27]);  //DAA
+    cpu.regs.ax = 0x9A;
+    step(&mut cpu);
+    assert_eq!(cpu.regs.ax, 0x00);
+    assert!(cpu.flags.carry && cpu.flags.adjust && cpu.flags.zero);
+  }
+}
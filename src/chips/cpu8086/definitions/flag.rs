@@ -1,3 +1,7 @@
+pub enum Shift {
+  Rol, Ror, Rcl, Rcr, Shl, Shr, Sar,
+}
+
 #[derive(Default)]
 pub struct Flags {
   pub carry: bool,
@@ -56,185 +60,111 @@ impl Flags {
     result
   }
 
-  pub fn shr_ror_rcr_byte(&mut self, set_val: u8, get_val: u8) -> u8 {
-    if get_val == 0 {
-      return set_val;
-    }
-
-    let prev_sign = set_val & 0b1000_0000;
-    
-    let mut result = set_val >> (get_val - 1);
-    self.carry = result & 1 != 0;
-    result >>= 1;
-    
-    let new_sign = result & 0b1000_0000;
-    self.overflow = prev_sign != new_sign;
-    result
+  /// Shift or rotate one bit at a time, like the 8086 does. The count isn't masked.
+  /// Rotates only change the carry and overflow flags. Overflow is from the last step.
+  pub fn shift_byte(&mut self, shift: Shift, value: u8, count: u8) -> u8 {
+    self.shift(shift, value as u16, count, 0x80) as u8
   }
-  pub fn shr_ror_rcr_word(&mut self, set_val: u16, get_val: u8) -> u16 {
-    if get_val == 0 {
-      return set_val;
-    }
-
-    let prev_sign = set_val & 0b1000_0000_0000_0000;
-    
-    let mut result = set_val >> (get_val - 1);
-    self.carry = result & 1 != 0;
-    result >>= 1;
-    
-    let new_sign = result & 0b1000_0000_0000_0000;
-    self.overflow = prev_sign != new_sign;
-    result
+  pub fn shift_word(&mut self, shift: Shift, value: u16, count: u8) -> u16 {
+    self.shift(shift, value, count, 0x8000)
   }
-  
-  pub fn shl_rol_rcl_byte(&mut self, set_val: u8, get_val: u8) -> u8 {
-    if get_val == 0 {
-      return set_val;
-    }
 
-    let prev_sign = set_val & 0b1000_0000;
-    
-    let mut result = set_val << (get_val - 1);
-    self.carry = result & 0b1000_0000 != 0;
-    result <<= 1;
-    
-    let new_sign = result & 0b1000_0000;
-    self.overflow = prev_sign != new_sign;
-    result
-  }
-  pub fn shl_rol_rcl_word(&mut self, set_val: u16, get_val: u8) -> u16 {
-    if get_val == 0 {
-      return set_val;
+  fn shift(&mut self, shift: Shift, value: u16, count: u8, sign: u16) -> u16 {
+    if count == 0 {
+      return value;
+    }
+    let mask = sign | (sign - 1);
+    let mut result = value;
+    for _ in 0..count {
+      let (high, low) = (result & sign != 0, result & 1 != 0);
+      result = match shift {
+        Shift::Rol => (result << 1) | high as u16,
+        Shift::Ror => (result >> 1) | if low { sign } else { 0 },
+        Shift::Rcl => (result << 1) | self.carry as u16,
+        Shift::Rcr => (result >> 1) | if self.carry { sign } else { 0 },
+        Shift::Shl => result << 1,
+        Shift::Shr => result >> 1,
+        Shift::Sar => (result >> 1) | (result & sign),
+      } & mask;
+      self.carry = match shift {
+        Shift::Rol | Shift::Rcl | Shift::Shl => high,
+        Shift::Ror | Shift::Rcr | Shift::Shr | Shift::Sar => low,
+      };
+    }
+    let (high, next) = (result & sign != 0, result & (sign >> 1) != 0);
+    self.overflow = match shift {
+      Shift::Rol | Shift::Rcl | Shift::Shl => high != self.carry,
+      Shift::Ror | Shift::Rcr | Shift::Shr | Shift::Sar => high != next,
+    };
+    if matches!(shift, Shift::Shl | Shift::Shr | Shift::Sar) {
+      self.parity_byte(result as u8);
+      self.zero = result == 0;
+      self.sign = high;
     }
-    
-    let prev_sign = set_val & 0b1000_0000_0000_0000;
-    
-    let mut result = set_val << (get_val - 1);
-    self.carry = result & 0b1000_0000_0000_0000 != 0;
-    result <<= 1;
-    
-    let new_sign = result & 0b1000_0000_0000_0000;
-    self.overflow = prev_sign != new_sign;
     result
   }
 
   pub fn add_byte(&mut self, set_val: u8, get_val: u8) -> u8 {
-    let (result, carry) = set_val.overflowing_add(get_val);
-    self.carry = carry;
-    self.parity_zero_sign_byte(result);
-    self.adjust = (set_val & 0xF) > (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
-    let (_, overflow) = (set_val as i8).overflowing_add(get_val as i8);
-    self.overflow = overflow;
-    result
+    self.adc_byte_carry(set_val, get_val, false)
   }
   pub fn add_word(&mut self, set_val: u16, get_val: u16) -> u16 {
-    let (result, carry) = set_val.overflowing_add(get_val);
-    self.carry = carry;
-    self.parity_zero_sign_word(result);
-    self.adjust = (set_val & 0xF) > (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
-    let (_, overflow) = (set_val as i16).overflowing_add(get_val as i16);
-    self.overflow = overflow;
-    result
+    self.adc_word_carry(set_val, get_val, false)
   }
 
   pub fn adc_byte(&mut self, set_val: u8, get_val: u8) -> u8 {
-    let (r, overflow1) = (set_val as i8).overflowing_add(get_val as i8);
-    if self.carry {
-      let (_, overflow2) = (r as i8).overflowing_add(1);
-      self.overflow = overflow1 | overflow2;
-    } else {
-      self.overflow = overflow1;
-    }
-    let (mut result, carry1) = set_val.overflowing_add(get_val);
-    if self.carry {
-      let (r, carry2) = result.overflowing_add(1);
-      self.carry = carry1 | carry2;
-      result = r;
-    } else {
-      self.carry = carry1;
-    }
+    self.adc_byte_carry(set_val, get_val, self.carry)
+  }
+  pub fn adc_word(&mut self, set_val: u16, get_val: u16) -> u16 {
+    self.adc_word_carry(set_val, get_val, self.carry)
+  }
+
+  fn adc_byte_carry(&mut self, set_val: u8, get_val: u8, carry: bool) -> u8 {
+    let full = set_val as u16 + get_val as u16 + carry as u16;
+    let result = full as u8;
+    self.carry = full > 0xFF;
     self.parity_zero_sign_byte(result);
-    self.adjust = (set_val & 0xF) > (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
+    self.adjust = (set_val ^ get_val ^ result) & 0x10 != 0;
+    self.overflow = (set_val ^ result) & (get_val ^ result) & 0x80 != 0;
     result
   }
-  pub fn adc_word(&mut self, set_val: u16, get_val: u16) -> u16 {
-    let (r, overflow1) = (set_val as i16).overflowing_add(get_val as i16);
-    if self.carry {
-      let (_, overflow2) = (r as i16).overflowing_add(1);
-      self.overflow = overflow1 | overflow2;
-    } else {
-      self.overflow = overflow1;
-    }
-    let (mut result, carry1) = set_val.overflowing_add(get_val);
-    if self.carry {
-      let (r, carry2) = result.overflowing_add(1);
-      self.carry = carry1 | carry2;
-      result = r;
-    } else {
-      self.carry = carry1;
-    }
+  fn adc_word_carry(&mut self, set_val: u16, get_val: u16, carry: bool) -> u16 {
+    let full = set_val as u32 + get_val as u32 + carry as u32;
+    let result = full as u16;
+    self.carry = full > 0xFFFF;
     self.parity_zero_sign_word(result);
-    self.adjust = (set_val & 0xF) > (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
+    self.adjust = (set_val ^ get_val ^ result) & 0x10 != 0;
+    self.overflow = (set_val ^ result) & (get_val ^ result) & 0x8000 != 0;
     result
   }
   
   pub fn cmp_sub_byte(&mut self, set_val: u8, get_val: u8) -> u8 {
-    let (result, carry) = set_val.overflowing_sub(get_val);
-    self.carry = carry;
-    self.parity_zero_sign_byte(result);
-    self.adjust = (set_val & 0xF) < (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
-    let (_, overflow) = (set_val as i8).overflowing_sub(get_val as i8);
-    self.overflow = overflow;
-    result
+    self.sbb_byte_borrow(set_val, get_val, false)
   }
   pub fn cmp_sub_word(&mut self, set_val: u16, get_val: u16) -> u16 {
-    let (result, carry) = set_val.overflowing_sub(get_val);
-    self.carry = carry;
-    self.parity_zero_sign_word(result);
-    self.adjust = (set_val & 0xF) < (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
-    let (_, overflow) = (set_val as i16).overflowing_sub(get_val as i16);
-    self.overflow = overflow;
-    result
+    self.sbb_word_borrow(set_val, get_val, false)
   }
   
   pub fn sbb_byte(&mut self, set_val: u8, get_val: u8) -> u8 {
-    let (r, overflow1) = (set_val as i8).overflowing_sub(get_val as i8);
-    if self.carry {
-      let (_, overflow2) = (r as i8).overflowing_sub(1);
-      self.overflow = overflow1 | overflow2;
-    } else {
-      self.overflow = overflow1;
-    }
-    let (mut result, carry1) = set_val.overflowing_sub(get_val);
-    if self.carry {
-      let (r, carry2) = result.overflowing_sub(1);
-      self.carry = carry1 | carry2;
-      result = r;
-    } else {
-      self.carry = carry1;
-    }
+    self.sbb_byte_borrow(set_val, get_val, self.carry)
+  }
+  pub fn sbb_word(&mut self, set_val: u16, get_val: u16) -> u16 {
+    self.sbb_word_borrow(set_val, get_val, self.carry)
+  }
+
+  fn sbb_byte_borrow(&mut self, set_val: u8, get_val: u8, borrow: bool) -> u8 {
+    let result = set_val.wrapping_sub(get_val).wrapping_sub(borrow as u8);
+    self.carry = (set_val as u16) < get_val as u16 + borrow as u16;
     self.parity_zero_sign_byte(result);
-    self.adjust = (set_val & 0xF) < (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
+    self.adjust = (set_val ^ get_val ^ result) & 0x10 != 0;
+    self.overflow = (set_val ^ get_val) & (set_val ^ result) & 0x80 != 0;
     result
   }
-  pub fn sbb_word(&mut self, set_val: u16, get_val: u16) -> u16 {
-    let (r, overflow1) = (set_val as i16).overflowing_sub(get_val as i16);
-    if self.carry {
-      let (_, overflow2) = (r as i16).overflowing_sub(1);
-      self.overflow = overflow1 | overflow2;
-    } else {
-      self.overflow = overflow1;
-    }
-    let (mut result, carry1) = set_val.overflowing_sub(get_val);
-    if self.carry {
-      let (r, carry2) = result.overflowing_sub(1);
-      self.carry = carry1 | carry2;
-      result = r;
-    } else {
-      self.carry = carry1;
-    }
+  fn sbb_word_borrow(&mut self, set_val: u16, get_val: u16, borrow: bool) -> u16 {
+    let result = set_val.wrapping_sub(get_val).wrapping_sub(borrow as u16);
+    self.carry = (set_val as u32) < get_val as u32 + borrow as u32;
     self.parity_zero_sign_word(result);
-    self.adjust = (set_val & 0xF) < (result & 0xF); //TODO - Confirm this, especially with negative / wrapping
+    self.adjust = (set_val ^ get_val ^ result) & 0x10 != 0;
+    self.overflow = (set_val ^ get_val) & (set_val ^ result) & 0x8000 != 0;
     result
   }
   
@@ -293,11 +223,7 @@ impl Flags {
     self.parity = (ret & 1) == 0;
   }
   fn parity_word(&mut self, result: u16) { //8086 just looking at first byte. Ignoring remainder.
-    let mut ret = result;
-    ret ^= ret >> 4;
-    ret ^= ret >> 2;
-    ret ^= ret >> 1;
-    self.parity = (ret & 1) == 0;
+    self.parity_byte(result as u8);
   }
 
   fn zero_byte(&mut self, result: u8) {
@@ -316,3 +242,96 @@ impl Flags {
     self.sign = signed < 0;
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn add_sets_overflow_adjust_and_carry() {
+    let mut flags = Flags::default();
+    assert_eq!(flags.add_byte(0x7F, 1), 0x80);
+    assert!(flags.overflow && flags.adjust && flags.sign && !flags.carry && !flags.zero);
+
+    assert_eq!(flags.add_byte(0xFF, 1), 0);
+    assert!(flags.carry && flags.adjust && flags.zero && !flags.overflow && !flags.sign);
+
+    assert_eq!(flags.add_word(0x8000, 0x8000), 0);
+    assert!(flags.carry && flags.overflow && !flags.adjust);
+  }
+
+  #[test]
+  fn adc_adds_the_carry_into_every_flag() {
+    let mut flags = Flags { carry: true, ..Default::default() };
+    assert_eq!(flags.adc_byte(0x7F, 0), 0x80);
+    assert!(flags.overflow && flags.adjust && !flags.carry);
+
+    flags.carry = true;
+    assert_eq!(flags.adc_word(0xFFFF, 0), 0);
+    assert!(flags.carry && flags.zero && !flags.overflow);
+  }
+
+  #[test]
+  fn sub_and_sbb_borrow() {
+    let mut flags = Flags::default();
+    assert_eq!(flags.cmp_sub_byte(0x80, 1), 0x7F);
+    assert!(flags.overflow && flags.adjust && !flags.carry && !flags.sign);
+
+    assert_eq!(flags.cmp_sub_byte(0, 1), 0xFF);
+    assert!(flags.carry && flags.sign && !flags.overflow);
+
+    flags.carry = true;
+    assert_eq!(flags.sbb_word(0, 0), 0xFFFF);
+    assert!(flags.carry && flags.adjust && !flags.zero);
+
+    flags.carry = true;
+    assert_eq!(flags.sbb_byte(1, 0), 0);
+    assert!(!flags.carry && flags.zero);
+  }
+
+  #[test]
+  fn parity_only_looks_at_the_low_byte() {
+    let mut flags = Flags::default();
+    flags.parity_zero_sign_word(0x0103);
+    assert!(flags.parity);
+    flags.parity_zero_sign_word(0x0301);
+    assert!(!flags.parity);
+  }
+
+  #[test]
+  fn rotates_leave_the_other_flags_alone() {
+    let mut flags = Flags { zero: true, ..Default::default() };
+    assert_eq!(flags.shift_byte(Shift::Rol, 0x81, 1), 0x03);
+    assert!(flags.carry && flags.overflow && flags.zero);
+
+    assert_eq!(flags.shift_byte(Shift::Ror, 0x01, 1), 0x80);
+    assert!(flags.carry && flags.overflow);
+
+    flags.carry = true;
+    assert_eq!(flags.shift_word(Shift::Rcr, 0, 1), 0x8000);
+    assert!(!flags.carry);
+
+    assert_eq!(flags.shift_byte(Shift::Rcl, 0x80, 2), 0x01);
+    assert!(!flags.carry);
+  }
+
+  #[test]
+  fn shifts_set_carry_from_the_last_bit_out() {
+    let mut flags = Flags::default();
+    assert_eq!(flags.shift_byte(Shift::Shl, 0x40, 1), 0x80);
+    assert!(!flags.carry && flags.overflow && flags.sign);
+
+    assert_eq!(flags.shift_byte(Shift::Sar, 0x81, 1), 0xC0);
+    assert!(flags.carry && !flags.overflow && flags.sign);
+
+    assert_eq!(flags.shift_word(Shift::Shr, 0x8001, 16), 0);
+    assert!(flags.carry && flags.zero && !flags.sign);
+  }
+
+  #[test]
+  fn shift_by_zero_changes_nothing() {
+    let mut flags = Flags { carry: true, ..Default::default() };
+    assert_eq!(flags.shift_byte(Shift::Shl, 0x80, 0), 0x80);
+    assert!(flags.carry && !flags.zero);
+  }
+}
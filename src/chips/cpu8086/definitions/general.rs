@@ -3,13 +3,11 @@ use super::super::CPU;
 use super::memory;
 
 pub fn push(cpu: &mut CPU, value: u16) {
-  cpu.memory.current_segment = memory::Segment::SS;
-  cpu.regs.sp -= 2;
-  cpu.memory.set_word(cpu.regs.sp, value);
+  cpu.regs.sp = cpu.regs.sp.wrapping_sub(2);
+  cpu.memory.set_word_seg(memory::Segment::SS, cpu.regs.sp, value);
 }
 pub fn pop(cpu: &mut CPU) -> u16 {
-  cpu.memory.current_segment = memory::Segment::SS;
-  let value = cpu.memory.get_word(cpu.regs.sp);
-  cpu.regs.sp += 2;
+  let value = cpu.memory.get_word_seg(memory::Segment::SS, cpu.regs.sp);
+  cpu.regs.sp = cpu.regs.sp.wrapping_add(2);
   value
 }
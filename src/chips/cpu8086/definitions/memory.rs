@@ -8,7 +8,8 @@ pub struct Memory {
   
   pub ip: u16,  //Instruction
   
-  pub current_segment: Segment,
+  pub current_segment: Segment,  //The data segment for this instruction. DS unless there is a prefix, or the address is from BP.
+  pub segment_override: bool,
 
   pub messenger: mpsc::Sender<crate::Msg>,
 
@@ -16,6 +17,7 @@ pub struct Memory {
 
 }
 
+#[derive(Clone, Copy)]
 pub enum Segment {
   ES, CS, SS, DS,
 }
@@ -50,39 +52,56 @@ impl Memory {
   }
   
   pub fn next_byte(&mut self) -> u8 {
-    (*self).ip += 1;
+    self.ip = self.ip.wrapping_add(1);
     let byte = self.current_instruction & 0xFF;
     self.current_instruction >>= 8;
     byte as u8
   }
 
   pub fn next_word(&mut self) -> u16 {
-    (*self).ip += 2;
+    self.ip = self.ip.wrapping_add(2);
     let word = self.current_instruction & 0xFFFF;
     self.current_instruction >>= 2*8;
     word as u16
   }
 
   pub fn set_byte(&mut self, offset: u16, byte: u8) {
-    let addr = calculate_addr(self.get_seg(&self.current_segment), offset);
-    self.set_byte_msg(addr, byte);
+    self.set_byte_seg(self.current_segment, offset, byte);
   }
-  
   pub fn get_byte(&self, offset: u16) -> u8 {
-    let addr = calculate_addr(self.get_seg(&self.current_segment), offset);
-    self.get_byte_msg(addr)
+    self.get_byte_seg(self.current_segment, offset)
   }
-
   pub fn set_word(&mut self, offset: u16, word: u16) {
-    let addr = calculate_addr(self.get_seg(&self.current_segment), offset);
-    self.set_word_msg(addr, word);
+    self.set_word_seg(self.current_segment, offset, word);
   }
-
   pub fn get_word(&self, offset: u16) -> u16 {
-    let addr = calculate_addr(self.get_seg(&self.current_segment), offset);
+    self.get_word_seg(self.current_segment, offset)
+  }
+
+  //For the stack and the string destination, which always use SS and ES.
+  pub fn set_byte_seg(&mut self, segment: Segment, offset: u16, byte: u8) {
+    let addr = calculate_addr(self.get_seg(&segment), offset);
+    self.set_byte_msg(addr, byte);
+  }
+  pub fn get_byte_seg(&self, segment: Segment, offset: u16) -> u8 {
+    let addr = calculate_addr(self.get_seg(&segment), offset);
+    self.get_byte_msg(addr)
+  }
+  pub fn set_word_seg(&mut self, segment: Segment, offset: u16, word: u16) {
+    let addr = calculate_addr(self.get_seg(&segment), offset);
+    self.set_word_msg(addr, word);
+  }
+  pub fn get_word_seg(&self, segment: Segment, offset: u16) -> u16 {
+    let addr = calculate_addr(self.get_seg(&segment), offset);
     self.get_word_msg(addr)
   }
   
+  /// Segment prefixes change the data segment until the end of the instruction.
+  pub fn override_segment(&mut self, segment: Segment) {
+    self.current_segment = segment;
+    self.segment_override = true;
+  }
+
   pub fn get_current_address(&self) -> usize {
     calculate_addr(self.cs, self.ip)
   }
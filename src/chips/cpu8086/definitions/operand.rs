@@ -1,4 +1,4 @@
-use super::memory::Memory;
+use super::memory::{Memory, Segment};
 use super::register::Registers;
 use super::memory;
 use super::register;
@@ -63,66 +63,32 @@ impl Byte {
   
   //Eb
   pub fn extended(memory: &mut Memory, regs: &Registers, op1: u8) -> Byte {
-    let ind1 = op1 & 7;
     match op1 {
       0x00..=0xBF => {
-        let (mut addr, mut label, mut cycles) = match ind1 {
-          0 => (regs.bx + regs.si, "BX+SI".to_string(), 7),
-          1 => (regs.bx + regs.di, "BX+DI".to_string(), 8),
-          2 => (regs.bp + regs.si, "BP+SI".to_string(), 8),
-          3 => (regs.bp + regs.di, "BP+DI".to_string(), 7),
-          4 => (regs.si, "SI".to_string(), 5),
-          5 => (regs.di, "DI".to_string(), 5),
-          6 => (regs.bp, "BP".to_string(), 5),
-          7 => (regs.bx, "BX".to_string(), 5),
-          _ => unreachable!(),
-        };
-        match op1 {
-          0x00..=0x3F => {
-            if ind1 == 0x6 { //Special case replacing bp
-              addr = memory.next_word();
-              label = format!("{:X}", addr);
-            }
-          },
-          0x40..=0x7F => {
-            let offset = memory.next_byte() as u16;
-            addr += offset;
-            label = format!("{}+{:X}", label, offset);
-            cycles += 6;
-          },
-          0x80..=0xBF => {
-            let offset = memory.next_word();
-            addr += offset;
-            label = format!("{}+{:X}", label, offset);
-            cycles += 6;
-          },
-          _ => unreachable!(),
-        };
+        let (addr, label, cycles) = effective_address(memory, regs, op1);
         Byte::Mem{addr, label, cycles}
       },
-      0xC0..=0xFF => {
-        Byte::reg_index(ind1)
-      }
+      0xC0..=0xFF => Byte::reg_index(op1 & 7),
     }
   }
 
   pub fn get_cycles(&self) -> usize {
-    match (self) {
+    match self {
       Byte::Reg(_) => 2,
       Byte::Mem{cycles, ..} => 23+cycles,
       Byte::Imm(_) => unreachable!("This should be impossible. Cycles for an immediate..")
     }
   }
 
-  pub fn get_rotate_cycles(&self, get_op: &Byte) -> usize {
+  pub fn get_rotate_cycles(&self, get_op: &Byte, count: u8) -> usize {
     let mut cycles = self.get_cycles();
     if let Byte::Reg(_) = get_op {
-      cycles += 5 + 4 * get_val;
+      cycles += 6 + 4 * count as usize;
     }
-    cycles  
+    cycles
   }
 
-  pub fn get_cycles_fast_bytes(&self, get_op: &Byte) -> usize {
+  pub fn get_cycles_fast(&self, get_op: &Byte) -> usize {
     let set_op = self;
     match (set_op, get_op) {
       (Byte::Reg(_), Byte::Reg(_)) => 2,
@@ -135,7 +101,7 @@ impl Byte {
     }
   }
   
-  pub fn get_cycles_slow_bytes(&self, get_op: &Byte) -> usize {
+  pub fn get_cycles_slow(&self, get_op: &Byte) -> usize {
     let set_op = self;
     match (set_op, get_op) {
       (Byte::Reg(_), Byte::Reg(_)) => 3,
@@ -195,46 +161,12 @@ impl Word {
   
   //Ew or Ev
   pub fn extended(memory: &mut Memory, regs: &Registers, op1: u8) -> Word {
-    let ind1 = op1 & 7;
     match op1 {
       0x00..=0xBF => {
-        let (mut addr, mut label, mut cycles) = match ind1 {
-          0 => (regs.bx + regs.si, "BX+SI".to_string(), 7),
-          1 => (regs.bx + regs.di, "BX+DI".to_string(), 8),
-          2 => (regs.bp + regs.si, "BP+SI".to_string(), 8),
-          3 => (regs.bp + regs.di, "BP+DI".to_string(), 7),
-          4 => (regs.si, "SI".to_string(), 5),
-          5 => (regs.di, "DI".to_string(), 5),
-          6 => (regs.bp, "BP".to_string(), 5),
-          7 => (regs.bx, "BX".to_string(), 5),
-          _ => unreachable!(),
-        };
-        match op1 {
-          0x00..=0x3F => {
-            if ind1 == 0x6 { //Special case replacing bp
-              addr = memory.next_word();
-              label = format!("{:X}", addr);
-            }
-          },
-          0x40..=0x7F => {
-            let offset = memory.next_byte() as u16;
-            addr += offset;
-            label = format!("{}+{:X}", label, offset);
-            cycles += 6;
-          },
-          0x80..=0xBF => {
-            let offset = memory.next_word();
-            addr += offset;
-            label = format!("{}+{:X}", label, offset);
-            cycles += 6;
-          },
-          _ => unreachable!(),
-        };
+        let (addr, label, cycles) = effective_address(memory, regs, op1);
         Word::Mem{addr, label, cycles}
       },
-      0xC0..=0xFF => {
-        Word::reg_index(ind1)
-      }
+      0xC0..=0xFF => Word::reg_index(op1 & 7),
     }
   }
 
@@ -256,19 +188,19 @@ impl Word {
   }
 
   pub fn get_cycles(&self) -> usize {
-    match (self) {
+    match self {
       Word::Reg(_) | Word::Seg(_) => 3,
       Word::Mem{cycles, ..} => 24+cycles,
       Word::Imm(_) => unreachable!("This should be impossible. Cycles for an immediate..")
     }
   }
 
-  pub fn get_rotate_cycles(&self, get_op: &Byte) -> usize {
+  pub fn get_rotate_cycles(&self, get_op: &Byte, count: u8) -> usize {
     let mut cycles = self.get_cycles();
     if let Byte::Reg(_) = get_op {
-      cycles += 5 + 4 * get_val;
+      cycles += 6 + 4 * count as usize;
     }
-    cycles  
+    cycles
   }
 
   pub fn get_cycles_fast(&self, get_op: &Word) -> usize {
@@ -301,3 +233,46 @@ impl Word {
     }
   }
 }
+
+//The address from a ModRM byte of mod 0, 1 or 2, with its label and cycles.
+//Addresses from BP are in the stack segment, unless there is a segment prefix.
+fn effective_address(memory: &mut Memory, regs: &Registers, op1: u8) -> (u16, String, usize) {
+  let (mut addr, mut label, mut cycles) = match op1 & 7 {
+    0 => (regs.bx.wrapping_add(regs.si), "BX+SI".to_string(), 7),
+    1 => (regs.bx.wrapping_add(regs.di), "BX+DI".to_string(), 8),
+    2 => (regs.bp.wrapping_add(regs.si), "BP+SI".to_string(), 8),
+    3 => (regs.bp.wrapping_add(regs.di), "BP+DI".to_string(), 7),
+    4 => (regs.si, "SI".to_string(), 5),
+    5 => (regs.di, "DI".to_string(), 5),
+    6 => (regs.bp, "BP".to_string(), 5),
+    7 => (regs.bx, "BX".to_string(), 5),
+    _ => unreachable!(),
+  };
+  let from_bp = matches!(op1 & 7, 2 | 3 | 6);
+  match op1 {
+    0x00..=0x3F => {
+      if op1 & 7 == 6 { //Special case replacing bp
+        addr = memory.next_word();
+        label = format!("{:X}", addr);
+        cycles = 6;
+      } else if from_bp && !memory.segment_override {
+        memory.current_segment = Segment::SS;
+      }
+    },
+    0x40..=0xBF => {
+      let offset = if op1 < 0x80 {
+        memory.next_byte() as i8 as u16
+      } else {
+        memory.next_word()
+      };
+      addr = addr.wrapping_add(offset);
+      label = format!("{}+{:X}", label, offset);
+      cycles += 4;
+      if from_bp && !memory.segment_override {
+        memory.current_segment = Segment::SS;
+      }
+    },
+    _ => unreachable!(),
+  }
+  (addr, label, cycles)
+}
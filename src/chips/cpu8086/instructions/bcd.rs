@@ -1,6 +1,8 @@
 use super::super::CPU;
 use super::super::definitions::register;
 
+use super::jump;
+
 use log::Level::Trace;
 use log::{trace, log_enabled};
 
@@ -10,8 +12,8 @@ pub fn aaa(cpu: &mut CPU) -> usize {
   let mut al = cpu.regs.get_byte(&register::Byte::AL);
   let mut ah = cpu.regs.get_byte(&register::Byte::AH);
   if (al & 0xF) > 9 || cpu.flags.adjust {
-    al += 6;
-    ah += 1;
+    al = al.wrapping_add(6);
+    ah = ah.wrapping_add(1);
     cpu.regs.set_byte(&register::Byte::AH, ah);
     cpu.flags.adjust = true;
     cpu.flags.carry = true;
@@ -29,8 +31,8 @@ pub fn aas(cpu: &mut CPU) -> usize {
   let mut al = cpu.regs.get_byte(&register::Byte::AL);
   let mut ah = cpu.regs.get_byte(&register::Byte::AH);
   if (al & 0xF) > 9 || cpu.flags.adjust {
-    al -= 6;
-    ah -= 1;
+    al = al.wrapping_sub(6);
+    ah = ah.wrapping_sub(1);
     cpu.regs.set_byte(&register::Byte::AH, ah);
     cpu.flags.adjust = true;
     cpu.flags.carry = true;
@@ -42,22 +44,25 @@ pub fn aas(cpu: &mut CPU) -> usize {
   8
 }
 
-//ASCII adjust After Multiplication
-pub fn aam(cpu: &mut CPU) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: AAM", cpu.current_address); }
+//ASCII adjust After Multiplication. The base is normally 10, but any byte works.
+pub fn aam(cpu: &mut CPU, base: u8) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: AAM {:X}", cpu.current_address, base); }
+  if base == 0 {
+    return jump::divide_error(cpu) + 83;
+  }
   let al = cpu.regs.get_byte(&register::Byte::AL);
-  cpu.regs.set_byte(&register::Byte::AH, al / 10);
-  cpu.regs.set_byte(&register::Byte::AL, al % 10);
-  cpu.flags.parity_zero_sign_byte(al / 10);  //TODO: Perhaps both AL and AH need to be included here?
+  cpu.regs.set_byte(&register::Byte::AH, al / base);
+  cpu.regs.set_byte(&register::Byte::AL, al % base);
+  cpu.flags.parity_zero_sign_byte(al % base);
   83
 }
 
-//ASCII adjust before? Division
-pub fn aad(cpu: &mut CPU) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: AAD", cpu.current_address); }
+//ASCII adjust before Division
+pub fn aad(cpu: &mut CPU, base: u8) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: AAD {:X}", cpu.current_address, base); }
   let al = cpu.regs.get_byte(&register::Byte::AL);
   let ah = cpu.regs.get_byte(&register::Byte::AH);
-  let result = (ah * 10) + al;
+  let result = ah.wrapping_mul(base).wrapping_add(al);
   cpu.regs.set_byte(&register::Byte::AL, result);
   cpu.regs.set_byte(&register::Byte::AH, 0);
   cpu.flags.parity_zero_sign_byte(result);
@@ -67,39 +72,45 @@ pub fn aad(cpu: &mut CPU) -> usize {
 //Decimal adjust After Addition
 pub fn daa(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: DAA", cpu.current_address); }
-  let mut al = cpu.regs.get_byte(&register::Byte::AL);
+  let (old_al, old_carry) = (cpu.regs.get_byte(&register::Byte::AL), cpu.flags.carry);
+  let mut al = old_al;
   if (al & 0xF) > 9 || cpu.flags.adjust {
-    al += 6;
+    let (result, carry) = al.overflowing_add(6);
+    al = result;
+    cpu.flags.carry = old_carry || carry;
     cpu.flags.adjust = true;
   } else {
     cpu.flags.adjust = false;
   }
-  if al > 0x9F || cpu.flags.carry {
+  if old_al > 0x99 || old_carry {
     al = al.wrapping_add(0x60);
     cpu.flags.carry = true;
   } else {
     cpu.flags.carry = false;
   }
   cpu.regs.set_byte(&register::Byte::AL, al);
+  cpu.flags.parity_zero_sign_byte(al);
   4
 }
 
 //Decimal adjust After Subtraction
-pub fn das(cpu: &mut CPU)-> usize {
+pub fn das(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: DAS", cpu.current_address); }
-  let mut al = cpu.regs.get_byte(&register::Byte::AL);
+  let (old_al, old_carry) = (cpu.regs.get_byte(&register::Byte::AL), cpu.flags.carry);
+  let mut al = old_al;
   if (al & 0xF) > 9 || cpu.flags.adjust {
-    al -= 6;
+    let (result, borrow) = al.overflowing_sub(6);
+    al = result;
+    cpu.flags.carry = old_carry || borrow;
     cpu.flags.adjust = true;
   } else {
     cpu.flags.adjust = false;
   }
-  if al > 0x9F || cpu.flags.carry {
+  if old_al > 0x99 || old_carry {
     al = al.wrapping_sub(0x60);
     cpu.flags.carry = true;
-  } else {
-    cpu.flags.carry = false;
   }
   cpu.regs.set_byte(&register::Byte::AL, al);
+  cpu.flags.parity_zero_sign_byte(al);
   4
 }
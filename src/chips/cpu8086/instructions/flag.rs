@@ -6,67 +6,89 @@ use super::super::definitions::general;
 use log::Level::Trace;
 use log::{trace, log_enabled};
 
-pub fn cmc(cpu: &mut CPU) {
+pub fn cmc(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: CMC", cpu.current_address); }
   cpu.flags.carry = !cpu.flags.carry;
+  2
 }
-pub fn clc(cpu: &mut CPU) {
+pub fn clc(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: CLC", cpu.current_address); }
   cpu.flags.carry = false;
+  2
 }
-pub fn stc(cpu: &mut CPU) {
+pub fn stc(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: STC", cpu.current_address); }
   cpu.flags.carry = true;
+  2
 }
 
-pub fn cli(cpu: &mut CPU) {
+pub fn cli(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: CLI", cpu.current_address); }
   cpu.flags.interrupt = false;
+  2
 }
-pub fn sti(cpu: &mut CPU) {
+pub fn sti(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: STI", cpu.current_address); }
   cpu.flags.interrupt = true;
+  2
 }
 
-pub fn cld(cpu: &mut CPU) {
+pub fn cld(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: CLD", cpu.current_address); }
   cpu.flags.direction = false;
+  2
 }
-pub fn std(cpu: &mut CPU) {
+pub fn std(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: STD", cpu.current_address); }
   cpu.flags.direction = true;
+  2
 }
 
-pub fn push(cpu: &mut CPU, op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: PUSH {}", cpu.current_address, general::label_word(&op)); }
-  let value = cpu.read_word(&op);
+pub fn push(cpu: &mut CPU, op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: PUSH {}", cpu.current_address, op.label()); }
+  let value = match op {
+    operand::Word::Reg(register::Word::SP) => cpu.regs.sp.wrapping_sub(2),  //The 8086 pushes SP after it has gone down.
+    _ => cpu.read_word(&op),
+  };
   general::push(cpu, value);
+  match op {
+    operand::Word::Mem{cycles, ..} => 24 + cycles,
+    _ => 15,
+  }
 }
-pub fn pushf(cpu: &mut CPU) {
+pub fn pushf(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: PUSHF", cpu.current_address); }
   let value = cpu.flags.get_bits_word();
   general::push(cpu, value);
+  14
 }
 
-pub fn pop(cpu: &mut CPU, op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: POP {}", cpu.current_address, general::label_word(&op)); }
+pub fn pop(cpu: &mut CPU, op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: POP {}", cpu.current_address, op.label()); }
   let value = general::pop(cpu);
   cpu.write_word(&op, value);
+  match op {
+    operand::Word::Mem{cycles, ..} => 25 + cycles,
+    _ => 12,
+  }
 }
-pub fn popf(cpu: &mut CPU) {
+pub fn popf(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: POPF", cpu.current_address); }
   let value = general::pop(cpu);
   cpu.flags.set_bits_word(value);
+  12
 }
 
-pub fn lahf(cpu: &mut CPU) {
+pub fn lahf(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: LAHF", cpu.current_address); }
   let value = cpu.flags.get_bits_byte();
   cpu.regs.set_byte(&register::Byte::AH, value);
+  4
 }
 
-pub fn sahf(cpu: &mut CPU) {
+pub fn sahf(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: SAHF", cpu.current_address); }
   let value = cpu.regs.get_byte(&register::Byte::AH);
   cpu.flags.set_bits_byte(value);
+  4
 }
\ No newline at end of file
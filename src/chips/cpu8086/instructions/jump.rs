@@ -8,158 +8,194 @@ use log::Level::{Error, Debug, Trace};
 use log::{error, debug, trace, log_enabled};
 
 
-pub fn jmp_word(cpu: &mut CPU, op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: JMP {}", cpu.current_address, general::label_word(&op)); }
+pub fn jmp_word(cpu: &mut CPU, op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: JMP {}", cpu.current_address, op.label()); }
   let value = cpu.read_word(&op);
   cpu.memory.ip = value;
+  match op {
+    operand::Word::Mem{cycles, ..} => 18 + cycles,
+    _ => 11,
+  }
 }
 
-pub fn jmp_addr(cpu: &mut CPU, segment: operand::Word, offset: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: JMP {}:{}", cpu.current_address, general::label_word(&segment), general::label_word(&offset)); }
+pub fn jmp_addr(cpu: &mut CPU, segment: operand::Word, offset: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: JMP {}:{}", cpu.current_address, segment.label(), offset.label()); }
   let (seg, off) = (cpu.read_word(&segment), cpu.read_word(&offset));
   cpu.memory.cs = seg;
   cpu.memory.ip = off;
+  15
 }
 
-pub fn jmp_relative(cpu: &mut CPU, relative_offset: i8, condition: bool) {
-  let offset = (cpu.memory.ip as i16) + relative_offset as i16;
+pub fn jmp_relative(cpu: &mut CPU, relative_offset: i8, condition: bool) -> usize {
   if condition {
-    cpu.memory.ip = offset as u16;
+    cpu.memory.ip = cpu.memory.ip.wrapping_add(relative_offset as u16);
+    16
+  } else {
+    4
   }
 }
 
-pub fn jmp_relative_word(cpu: &mut CPU, relative_offset: i16) {
+pub fn jmp_relative_word(cpu: &mut CPU, relative_offset: i16) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: JMP +{:X}", cpu.current_address, relative_offset); }
-  let offset = (cpu.memory.ip as i16) + relative_offset;
-  cpu.memory.ip = offset as u16;
+  cpu.memory.ip = cpu.memory.ip.wrapping_add(relative_offset as u16);
+  15
 }
 
-pub fn jmp_far(cpu: &mut CPU, op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: JMP FAR {}", cpu.current_address, general::label_word(&op)); }
+pub fn jmp_far(cpu: &mut CPU, op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: JMP FAR {}", cpu.current_address, op.label()); }
   match op {
-    operand::Word::Mem(addr, _) => {
+    operand::Word::Mem{addr, cycles, ..} => {
       let offset = cpu.memory.get_word(addr);
-      let segment = cpu.memory.get_word(addr + 2);
+      let segment = cpu.memory.get_word(addr.wrapping_add(2));
       cpu.memory.cs = segment;
       cpu.memory.ip = offset;
+      24 + cycles
     },
     _ => {//Tried to jump to a far location without providing a segment. Revert to jumping to just a word.
-      if log_enabled!(Error) { error!("{:05X}: Incorrect Jump Far. Reverting to Jump word {}.", cpu.current_address, general::label_word(&op)); }
+      if log_enabled!(Error) { error!("{:05X}: Incorrect Jump Far. Reverting to Jump word {}.", cpu.current_address, op.label()); }
       let offset = cpu.read_word(&op);
       cpu.memory.ip = offset;
+      11
     },
-  };
+  }
 }
 
-pub fn call_word(cpu: &mut CPU, offset: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: CALL {}", cpu.current_address, general::label_word(&offset)); }
+pub fn call_word(cpu: &mut CPU, offset: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: CALL {}", cpu.current_address, offset.label()); }
   let off_val = cpu.read_word(&offset);
   general::push(cpu, cpu.memory.ip);
   cpu.memory.ip = off_val;
+  match offset {
+    operand::Word::Mem{cycles, ..} => 29 + cycles,
+    _ => 24,
+  }
 }
 
-pub fn call_relative_word(cpu: &mut CPU, offset: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: CALL +{}", cpu.current_address, general::label_word(&offset)); }
-  let relative_offset = cpu.read_word(&offset) as i16;
-  let offset = (cpu.memory.ip as i16) + relative_offset;
+pub fn call_relative_word(cpu: &mut CPU, offset: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: CALL +{}", cpu.current_address, offset.label()); }
+  let relative_offset = cpu.read_word(&offset);
   general::push(cpu, cpu.memory.ip);
-  cpu.memory.ip = offset as u16;
+  cpu.memory.ip = cpu.memory.ip.wrapping_add(relative_offset);
+  23
 }
 
-pub fn call_addr(cpu: &mut CPU, segment: operand::Word, offset: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: CALL {}:{}", cpu.current_address, general::label_word(&segment), general::label_word(&offset)); }
+pub fn call_addr(cpu: &mut CPU, segment: operand::Word, offset: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: CALL {}:{}", cpu.current_address, segment.label(), offset.label()); }
   let (seg, off) = (cpu.read_word(&segment), cpu.read_word(&offset));
   general::push(cpu, cpu.memory.cs);
   general::push(cpu, cpu.memory.ip);
   cpu.memory.cs = seg;
   cpu.memory.ip = off;
+  36
 }
 
-pub fn call_far(cpu: &mut CPU, op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: CALL FAR {}", cpu.current_address, general::label_word(&op)); }
+pub fn call_far(cpu: &mut CPU, op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: CALL FAR {}", cpu.current_address, op.label()); }
   match op {
-    operand::Word::Mem(addr, _) => {
+    operand::Word::Mem{addr, cycles, ..} => {
       let offset = cpu.memory.get_word(addr);
-      let segment = cpu.memory.get_word(addr + 2);
+      let segment = cpu.memory.get_word(addr.wrapping_add(2));
       general::push(cpu, cpu.memory.cs);
       general::push(cpu, cpu.memory.ip);
       cpu.memory.cs = segment;
       cpu.memory.ip = offset;
+      53 + cycles
     },
     _ => {//Tried to jump to a far location without providing a segment. Revert to jumping to just a word.
-      if log_enabled!(Error) { error!("{:05X}: Incorrect Call Far. Reverting to Call word {}.", cpu.current_address, general::label_word(&op)); }
+      if log_enabled!(Error) { error!("{:05X}: Incorrect Call Far. Reverting to Call word {}.", cpu.current_address, op.label()); }
       let offset = cpu.read_word(&op);
       general::push(cpu, cpu.memory.ip);
       cpu.memory.ip = offset;
+      24
     },
-  };
+  }
 }
 
-pub fn ret(cpu: &mut CPU, add_sp: Option<u16>) {
+pub fn ret(cpu: &mut CPU, add_sp: Option<u16>) -> usize {
   cpu.memory.ip = general::pop(cpu);
   if let Some(num) = add_sp {
     if log_enabled!(Trace) { trace!("{:05X}: RET {:X}", cpu.current_address, num); }
-    cpu.regs.sp += num;
+    cpu.regs.sp = cpu.regs.sp.wrapping_add(num);
+    24
   } else {
     if log_enabled!(Trace) { trace!("{:05X}: RET", cpu.current_address); }
+    20
   }
 }
 
-pub fn retf(cpu: &mut CPU, add_sp: Option<u16>) {
+pub fn retf(cpu: &mut CPU, add_sp: Option<u16>) -> usize {
   cpu.memory.ip = general::pop(cpu);
   cpu.memory.cs = general::pop(cpu);
   if let Some(num) = add_sp {
     if log_enabled!(Trace) { trace!("{:05X}: RETF {:X}", cpu.current_address, num); }
-    cpu.regs.sp += num;
+    cpu.regs.sp = cpu.regs.sp.wrapping_add(num);
+    33
   } else {
     if log_enabled!(Trace) { trace!("{:05X}: RETF", cpu.current_address); }
+    34
   }
 }
 
-fn _int(cpu: &mut CPU, index: u8) {
+fn _int(cpu: &mut CPU, index: u8) -> usize {
   general::push(cpu, cpu.flags.get_bits_word());
   cpu.flags.interrupt = false;  //Interrupts are not allowed while inside of an interrupt.
+  cpu.flags.trap = false;
   general::push(cpu, cpu.memory.cs);
   general::push(cpu, cpu.memory.ip);
   if log_enabled!(Debug) { debug!("Interrupt {:X}", index); }
   cpu.print_registers();
   cpu.memory.ip = cpu.memory.get_word_msg(index as usize * 4);
   cpu.memory.cs = cpu.memory.get_word_msg(index as usize * 4 + 2);
+  51
 }
 
-pub fn hardware_int(cpu: &mut CPU, index: u8) {
+pub fn hardware_int(cpu: &mut CPU, index: u8) -> usize {
   if log_enabled!(Trace) { trace!("HARDWARE INT {:X}", index); }
-  _int(cpu, index);
+  _int(cpu, index) + 10
 }
 
-pub fn int(cpu: &mut CPU, index: u8) {
+pub fn int(cpu: &mut CPU, index: u8) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: INT {:X}", cpu.current_address, index); }
-  _int(cpu, index);
+  _int(cpu, index)
 }
 
-pub fn into(cpu: &mut CPU) {
+/// DIV and IDIV interrupt when dividing by zero, or when the quotient doesn't fit.
+pub fn divide_error(cpu: &mut CPU) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: Divide error", cpu.current_address); }
+  _int(cpu, 0)
+}
+
+pub fn into(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: INTO", cpu.current_address); }
   if cpu.flags.overflow {
-    _int(cpu, 3);
+    _int(cpu, 4) + 2
+  } else {
+    4
   }
 }
 
-pub fn iret(cpu: &mut CPU) {
+pub fn iret(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IRET", cpu.current_address); }
   cpu.memory.ip = general::pop(cpu);
   cpu.memory.cs = general::pop(cpu);
   let flag_word = general::pop(cpu);
   cpu.flags.set_bits_word(flag_word);
+  44
 }
 
-pub fn loop_relative(cpu: &mut CPU, relative_offset: i8, condition: bool) {
+pub fn loop_relative(cpu: &mut CPU, relative_offset: i8, condition: bool) -> usize {
   cpu.regs.cx = cpu.regs.cx.wrapping_sub(1);
-  if cpu.regs.cx != 0 {
-    jmp_relative(cpu, relative_offset, condition);
+  if cpu.regs.cx != 0 && condition {
+    jmp_relative(cpu, relative_offset, true) + 1
+  } else {
+    5
   }
 }
 
-pub fn rep(cpu: &mut CPU, zero: bool) {
+//REP repeats the string instruction after it, one step per call, so interrupts can come in between.
+//It goes back to the REP for the next step. Like the 8086, a segment prefix before the REP is lost after an interrupt.
+//REPZ and REPNZ only differ for CMPS and SCAS, which also stop when the zero flag doesn't match.
+pub fn rep(cpu: &mut CPU, zero: bool) -> usize {
   if log_enabled!(Trace) {
     if zero {
       trace!("{:05X}: REPZ", cpu.current_address);
@@ -167,15 +203,20 @@ pub fn rep(cpu: &mut CPU, zero: bool) {
       trace!("{:05X}: REPNZ", cpu.current_address);
     }
   }
-  if cpu.regs.cx != 0 {
-    let prev_ip = cpu.memory.ip;
-    lookup::run_next_instruction(cpu);
-    cpu.regs.cx -= 1;
-    if zero == cpu.flags.zero {
-      cpu.memory.ip = prev_ip - 1;  //Next time we run an instruction should be back at this rep.
-    }
+  let prev_ip = cpu.memory.ip;
+  let op0 = lookup::peek_opcode(cpu);
+  if !matches!(op0, 0xA4..=0xA7 | 0xAA..=0xAF) {
+    return lookup::run_instruction(cpu);
   }
   if cpu.regs.cx == 0 {
-    cpu.flags.zero = true;
+    lookup::skip_instruction(cpu);
+    return 9;
+  }
+  let cycles = lookup::run_instruction(cpu);
+  cpu.regs.cx = cpu.regs.cx.wrapping_sub(1);
+  let compare = matches!(op0, 0xA6 | 0xA7 | 0xAE | 0xAF);
+  if cpu.regs.cx != 0 && (!compare || zero == cpu.flags.zero) {
+    cpu.memory.ip = prev_ip.wrapping_sub(1);  //Next time we run an instruction should be back at this rep.
   }
+  cycles + 9
 }
@@ -62,13 +62,13 @@ pub fn test_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) ->
   set_op.get_cycles_fast(&get_op)
 }
 
-pub fn not_byte(cpu: &mut CPU, op: operand::Byte) {
+pub fn not_byte(cpu: &mut CPU, op: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: NOT {}", cpu.current_address, op.label()); }
   let result = !cpu.read_byte(&op);
   cpu.write_byte(&op, result);
   op.get_cycles()
 }
-pub fn not_word(cpu: &mut CPU, op: operand::Word) {
+pub fn not_word(cpu: &mut CPU, op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: NOT {}", cpu.current_address, op.label()); }
   let result = !cpu.read_word(&op);
   cpu.write_word(&op, result);
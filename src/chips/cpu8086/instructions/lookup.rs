@@ -13,12 +13,16 @@ use log::{trace, log_enabled};
 
 use super::*;
 
+/// Run one instruction with its prefixes, and give how many cycles it took.
 pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
-  
-  let mut next_segment = memory::Segment::DS; //This is for "ES:, CS:, DS:, SS:" commands for future memory lookup. Current memory lookup is in cpu.memory.current_segment
-  let mut cycles = 2;
-  
   cpu.current_address = cpu.memory.get_current_address();
+  cpu.memory.current_segment = memory::Segment::DS;
+  cpu.memory.segment_override = false;
+  run_instruction(cpu)
+}
+
+/// Run the instruction at IP, after the prefixes so far. Prefixes run the rest of the instruction themselves.
+pub fn run_instruction(cpu: &mut super::super::CPU) -> usize {
   let op0 = cpu.memory.next_byte();
 
   match op0 {
@@ -61,8 +65,8 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     },
     0x26 => {
       if log_enabled!(Trace) { trace!("{:05X}: ES:", cpu.current_address); }
-      next_segment = memory::Segment::ES;
-      2
+      cpu.memory.override_segment(memory::Segment::ES);
+      2 + run_instruction(cpu)
     },
     0x27 => bcd::daa(cpu),
     0x28..=0x2D => {
@@ -73,8 +77,8 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     },
     0x2E => {
       if log_enabled!(Trace) { trace!("{:05X}: CS:", cpu.current_address); }
-      next_segment = memory::Segment::CS;
-      2
+      cpu.memory.override_segment(memory::Segment::CS);
+      2 + run_instruction(cpu)
     },
     0x2F => bcd::das(cpu),
     0x30..=0x35 => {
@@ -85,8 +89,8 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     },
     0x36 => {
       if log_enabled!(Trace) { trace!("{:05X}: SS:", cpu.current_address); }
-      next_segment = memory::Segment::SS;
-      2
+      cpu.memory.override_segment(memory::Segment::SS);
+      2 + run_instruction(cpu)
     },
     0x37 => bcd::aaa(cpu),
     0x38..=0x3D => {
@@ -96,9 +100,9 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       }
     }
     0x3E => {
-      if log_enabled!(Trace) { trace!("{:05X}: CS:", cpu.current_address); }
-      next_segment = memory::Segment::CS;
-      2
+      if log_enabled!(Trace) { trace!("{:05X}: DS:", cpu.current_address); }
+      cpu.memory.override_segment(memory::Segment::DS);
+      2 + run_instruction(cpu)
     },
     0x3F => bcd::aas(cpu),
     0x40..=0x47 => math::inc_word(cpu, operand::Word::reg_index(op0 & 7)),
@@ -108,82 +112,82 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     0x70 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JO +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.overflow);
+      jump::jmp_relative(cpu, offset, cpu.flags.overflow)
     },
     0x71 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JNO +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, !cpu.flags.overflow);
+      jump::jmp_relative(cpu, offset, !cpu.flags.overflow)
     },
     0x72 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JB +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.carry);
+      jump::jmp_relative(cpu, offset, cpu.flags.carry)
     },
     0x73 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JNB +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, !cpu.flags.carry);
+      jump::jmp_relative(cpu, offset, !cpu.flags.carry)
     },
     0x74 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JZ +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.zero);
+      jump::jmp_relative(cpu, offset, cpu.flags.zero)
     },
     0x75 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JNZ +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, !cpu.flags.zero);
+      jump::jmp_relative(cpu, offset, !cpu.flags.zero)
     },
     0x76 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JBE +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.carry || cpu.flags.zero);
+      jump::jmp_relative(cpu, offset, cpu.flags.carry || cpu.flags.zero)
     },
     0x77 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JA +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, !cpu.flags.carry && !cpu.flags.zero);
+      jump::jmp_relative(cpu, offset, !cpu.flags.carry && !cpu.flags.zero)
     },
     0x78 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JS +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.sign);
+      jump::jmp_relative(cpu, offset, cpu.flags.sign)
     },
     0x79 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JNS +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, !cpu.flags.sign);
+      jump::jmp_relative(cpu, offset, !cpu.flags.sign)
     },
     0x7A => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JPE +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.parity);
+      jump::jmp_relative(cpu, offset, cpu.flags.parity)
     },
     0x7B => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JPO +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, !cpu.flags.parity);
+      jump::jmp_relative(cpu, offset, !cpu.flags.parity)
     },
     0x7C => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JL +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.sign != cpu.flags.overflow);
+      jump::jmp_relative(cpu, offset, cpu.flags.sign != cpu.flags.overflow)
     },
     0x7D => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JGE +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.sign == cpu.flags.overflow);
+      jump::jmp_relative(cpu, offset, cpu.flags.sign == cpu.flags.overflow)
     },
     0x7E => {
       let offset = cpu.memory.next_byte() as i8;
-      if log_enabled!(Trace) { trace!("{:05X}: JGE +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.sign != cpu.flags.overflow || cpu.flags.zero);
+      if log_enabled!(Trace) { trace!("{:05X}: JLE +{:X}", cpu.current_address, offset); }
+      jump::jmp_relative(cpu, offset, cpu.flags.sign != cpu.flags.overflow || cpu.flags.zero)
     },
     0x7F => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JG +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.flags.sign == cpu.flags.overflow && !cpu.flags.zero);
+      jump::jmp_relative(cpu, offset, cpu.flags.sign == cpu.flags.overflow && !cpu.flags.zero)
     },
     0x80 | 0x82 => {  //Absolutely nothing is different between 0x80 and 0x82. It is a duplicate.
       let op1 = cpu.memory.next_byte();
@@ -228,25 +232,25 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Byte::general(op1);
       let get_op = operand::Byte::extended(&mut cpu.memory, &cpu.regs, op1);
-      logic::test_byte(cpu, set_op, get_op);
+      logic::test_byte(cpu, set_op, get_op)
     },
     0x85 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::general(op1);
       let get_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      logic::test_word(cpu, set_op, get_op);
+      logic::test_word(cpu, set_op, get_op)
     },
     0x86 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Byte::general(op1);
       let get_op = operand::Byte::extended(&mut cpu.memory, &cpu.regs, op1);
-      set::xchg_byte(cpu, set_op, get_op);
+      set::xchg_byte(cpu, set_op, get_op)
     },
     0x87 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::general(op1);
       let get_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      set::xchg_word(cpu, set_op, get_op);
+      set::xchg_word(cpu, set_op, get_op)
     },
     0x88..=0x8B => {
       match lookup::get_standard_ops(cpu, op0 & 0b111) {
@@ -258,26 +262,29 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
       let get_op = operand::Word::segment(op1);
-      set::mov_word(cpu, set_op, get_op);
+      set::mov_word(cpu, set_op, get_op)
     },
     0x8D => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::general(op1);
       let get_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      set::lea_word(cpu, set_op, get_op);
+      set::lea_word(cpu, set_op, get_op)
     }
     0x8E => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::segment(op1);
       let get_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      set::mov_word(cpu, set_op, get_op);
+      set::mov_word(cpu, set_op, get_op)
     },
     0x8F => {
       let op1 = cpu.memory.next_byte();
       let op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      flag::pop(cpu, op);
+      flag::pop(cpu, op)
     }
-    0x90 => if log_enabled!(Trace) { trace!("{:05X}: NOP", cpu.current_address); },
+    0x90 => {
+      if log_enabled!(Trace) { trace!("{:05X}: NOP", cpu.current_address); }
+      3
+    },
     0x91..=0x97 => set::xchg_word(cpu,
                                   operand::Word::reg_index(op0 & 7),
                                   operand::Word::Reg(register::Word::AX)),
@@ -286,12 +293,13 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     0x9A => {
       let offset = operand::Word::Imm(cpu.memory.next_word());
       let segment = operand::Word::Imm(cpu.memory.next_word());
-      jump::call_addr(cpu, segment, offset);
+      jump::call_addr(cpu, segment, offset)
     },
     0x9B => {
-      trace!("{:05X}: WAIT", cpu.current_address);
-      panic!("Stop");
-    }
+      //Waits for the TEST pin, which is low without an 8087.
+      if log_enabled!(Trace) { trace!("{:05X}: WAIT", cpu.current_address); }
+      4
+    },
     0x9C => flag::pushf(cpu),
     0x9D => flag::popf(cpu),
     0x9E => flag::sahf(cpu),
@@ -299,22 +307,22 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     0xA0 => {
       let set_op = operand::Byte::Reg(register::Byte::AL);
       let get_op = operand::Byte::address(&mut cpu.memory);
-      set::mov_byte(cpu, set_op, get_op);
+      set::mov_byte(cpu, set_op, get_op)
     },
     0xA1 => {
       let set_op = operand::Word::Reg(register::Word::AX);
       let get_op = operand::Word::address(&mut cpu.memory);
-      set::mov_word(cpu, set_op, get_op);
+      set::mov_word(cpu, set_op, get_op)
     },
     0xA2 => {
       let set_op = operand::Byte::address(&mut cpu.memory);
       let get_op = operand::Byte::Reg(register::Byte::AL);
-      set::mov_byte(cpu, set_op, get_op);
+      set::mov_byte(cpu, set_op, get_op)
     },
     0xA3 => {
       let set_op = operand::Word::address(&mut cpu.memory);
       let get_op = operand::Word::Reg(register::Word::AX);
-      set::mov_word(cpu, set_op, get_op);
+      set::mov_word(cpu, set_op, get_op)
     },
     0xA4 => string::movsb(cpu),
     0xA5 => string::movsw(cpu),
@@ -323,12 +331,12 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     0xA8 => {
       let set_op = operand::Byte::Reg(register::Byte::AL);
       let get_op = operand::Byte::Imm(cpu.memory.next_byte());
-      logic::test_byte(cpu, set_op, get_op);
+      logic::test_byte(cpu, set_op, get_op)
     },
     0xA9 => {
       let set_op = operand::Word::Reg(register::Word::AX);
       let get_op = operand::Word::Imm(cpu.memory.next_word());
-      logic::test_word(cpu, set_op, get_op);
+      logic::test_word(cpu, set_op, get_op)
     },
     0xAA => string::stosb(cpu),
     0xAB => string::stosw(cpu),
@@ -339,51 +347,51 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     0xB0..=0xB7 => {
       let set_op = operand::Byte::reg_index(op0 & 7); 
       let get_op = operand::Byte::Imm(cpu.memory.next_byte());
-      set::mov_byte(cpu, set_op, get_op);
+      set::mov_byte(cpu, set_op, get_op)
     },
     0xB8..=0xBF => {
       let set_op = operand::Word::reg_index(op0 & 7);
       let get_op = operand::Word::Imm(cpu.memory.next_word());
-      set::mov_word(cpu, set_op, get_op);
+      set::mov_word(cpu, set_op, get_op)
     },
     0xC2 => {
       let word = cpu.memory.next_word();
-      jump::ret(cpu, Some(word));
+      jump::ret(cpu, Some(word))
     },
     0xC3 => jump::ret(cpu, None),
     0xC4 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::general(op1);
       let get_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      set::les_word(cpu, set_op, get_op);
+      set::les_word(cpu, set_op, get_op)
     },
     0xC5 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::general(op1);
       let get_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      set::lds_word(cpu, set_op, get_op);
+      set::lds_word(cpu, set_op, get_op)
     },
     0xC6 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Byte::extended(&mut cpu.memory, &cpu.regs, op1);
       let get_op = operand::Byte::Imm(cpu.memory.next_byte());
-      set::mov_byte(cpu, set_op, get_op);
+      set::mov_byte(cpu, set_op, get_op)
     },
     0xC7 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
       let get_op = operand::Word::Imm(cpu.memory.next_word());
-      set::mov_word(cpu, set_op, get_op);
+      set::mov_word(cpu, set_op, get_op)
     },
     0xCA => {
       let word = cpu.memory.next_word();
-      jump::retf(cpu, Some(word));
+      jump::retf(cpu, Some(word))
     },
     0xCB => jump::retf(cpu, None),
     0xCC => jump::int(cpu, 3),
     0xCD => {
       let index = cpu.memory.next_byte();
-      jump::int(cpu, index);
+      jump::int(cpu, index)
     },
     0xCE => jump::into(cpu),
     0xCF => jump::iret(cpu),
@@ -410,7 +418,7 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
     0xD1 | 0xD3 => {
       let op1 = cpu.memory.next_byte();
       let set_op = operand::Word::extended(&mut cpu.memory, &cpu.regs, op1);
-      let get_op = if op0 == 0xD0 {
+      let get_op = if op0 == 0xD1 {
         operand::Byte::Imm(1)
       } else {
         operand::Byte::Reg(register::Byte::CL)
@@ -428,67 +436,67 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       }
     },
     0xD4 => {
-      cpu.memory.next_byte();  //Bug in 8086.. It takes an extra byte for no good reason. Doesn't use it.
-      bcd::aam(cpu);
+      let base = cpu.memory.next_byte();  //Always 10 from an assembler, but the 8086 uses whatever is there.
+      bcd::aam(cpu, base)
     },
     0xD5 => {
-      cpu.memory.next_byte();  //Bug in 8086.. It takes an extra byte for no good reason. Doesn't use it.
-      bcd::aad(cpu);
+      let base = cpu.memory.next_byte();
+      bcd::aad(cpu, base)
     },
     0xD7 => set::xlat(cpu),
     0xE0 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: LOOPNZ +{:X}", cpu.current_address, offset); }
-      jump::loop_relative(cpu, offset, !cpu.flags.zero);
+      jump::loop_relative(cpu, offset, !cpu.flags.zero)
     },
     0xE1 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: LOOPZ +{:X}", cpu.current_address, offset); }
-      jump::loop_relative(cpu, offset, cpu.flags.zero);
+      jump::loop_relative(cpu, offset, cpu.flags.zero)
     },
     0xE2 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: LOOP +{:X}", cpu.current_address, offset); }
-      jump::loop_relative(cpu, offset, true);
+      jump::loop_relative(cpu, offset, true)
     },
     0xE3 => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JCXZ +{:X}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, cpu.regs.cx == 0);
+      jump::jmp_relative(cpu, offset, cpu.regs.cx == 0)
     },
     0xE4 => {
       let op = operand::Byte::Imm(cpu.memory.next_byte());
-      set::in_al_byte(cpu, op);
+      set::in_al_byte(cpu, op)
     },
     0xE5 => {
       let op = operand::Byte::Imm(cpu.memory.next_byte());
-      set::in_ax_byte(cpu, op);
+      set::in_ax_byte(cpu, op)
     },
     0xE6 => {
       let op = operand::Byte::Imm(cpu.memory.next_byte());
-      set::out_al_byte(cpu, op);
+      set::out_al_byte(cpu, op)
     },
     0xE7 => {
       let op = operand::Byte::Imm(cpu.memory.next_byte());
-      set::out_ax_byte(cpu, op);
+      set::out_ax_byte(cpu, op)
     },
     0xE8 => {
       let offset = operand::Word::Imm(cpu.memory.next_word());
-      jump::call_relative_word(cpu, offset);
+      jump::call_relative_word(cpu, offset)
     },
     0xE9 => {
       let offset = cpu.memory.next_word();
-      jump::jmp_relative_word(cpu, offset as i16);
+      jump::jmp_relative_word(cpu, offset as i16)
     },
     0xEA => {
       let offset = operand::Word::Imm(cpu.memory.next_word());
       let segment = operand::Word::Imm(cpu.memory.next_word());
-      jump::jmp_addr(cpu, segment, offset);
+      jump::jmp_addr(cpu, segment, offset)
     },
     0xEB => {
       let offset = cpu.memory.next_byte() as i8;
       if log_enabled!(Trace) { trace!("{:05X}: JMP +{}", cpu.current_address, offset); }
-      jump::jmp_relative(cpu, offset, true);
+      jump::jmp_relative(cpu, offset, true)
     },
     0xEC => set::in_al_word(cpu),
     0xED => set::in_ax_word(cpu),
@@ -499,12 +507,15 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       //No other chip can read the memory during this time.
       //This is not applicable for us.
       if log_enabled!(Trace) { trace!("{:05X}: LOCK", cpu.current_address); }
+      2 + run_instruction(cpu)
     },
     0xF2 => jump::rep(cpu, false),
     0xF3 => jump::rep(cpu, true),
     0xF4 => {
-      trace!("{:05X}: HLT", cpu.current_address);
-      panic!("Stop");
+      //Stops until an interrupt.
+      if log_enabled!(Trace) { trace!("{:05X}: HLT", cpu.current_address); }
+      cpu.halted = true;
+      2
     },
     0xF5 => flag::cmc(cpu),
     0xF6 => {
@@ -513,7 +524,7 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       match (op1 & 0b111000) >> 3 {
         0 => {
           let get_op = operand::Byte::Imm(cpu.memory.next_byte());
-          logic::test_byte(cpu, set_op, get_op);
+          logic::test_byte(cpu, set_op, get_op)
         },
         1 => panic!("{:05X}: Unknown - {:02X} {:02X}", cpu.current_address, op0, op1),
         2 => logic::not_byte(cpu, set_op),
@@ -531,7 +542,7 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       match (op1 & 0b111000) >> 3 {
         0 => {
           let get_op = operand::Word::Imm(cpu.memory.next_word());
-          logic::test_word(cpu, set_op, get_op);
+          logic::test_word(cpu, set_op, get_op)
         },
         1 => panic!("{:05X}: Unknown - {:02X} {:02X}", cpu.current_address, op0, op1),
         2 => logic::not_word(cpu, set_op),
@@ -575,12 +586,21 @@ pub fn run_next_instruction(cpu: &mut super::super::CPU) -> usize {
       }
     },
     _ => panic!("{:05X}: Unknown - {:02X}", cpu.current_address, op0),
+  }
+}
+
+/// The opcode after any segment prefixes, without taking it.
+pub fn peek_opcode(cpu: &super::super::CPU) -> u8 {
+  let mut bytes = cpu.memory.current_instruction;
+  while matches!(bytes as u8, 0x26 | 0x2E | 0x36 | 0x3E) {
+    bytes >>= 8;
+  }
+  bytes as u8
+}
 
-  };
-  
-  cpu.memory.current_segment = next_segment; //Remember for the next instruction
-  
-  cycles
+/// Take an opcode and its segment prefixes without running it. Only for one byte instructions.
+pub fn skip_instruction(cpu: &mut super::super::CPU) {
+  while matches!(cpu.memory.next_byte(), 0x26 | 0x2E | 0x36 | 0x3E) {}
 }
 
 //Pattern for many operations:
@@ -2,6 +2,8 @@ use super::super::CPU;
 use super::super::definitions::operand;
 use super::super::definitions::register;
 
+use super::jump;
+
 use log::Level::Trace;
 use log::{trace, log_enabled};
 
@@ -111,115 +113,125 @@ pub fn sbb_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) ->
 
 pub fn neg_byte(cpu: &mut CPU, op: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: NEG {}", cpu.current_address, op.label()); }
-  let value = !cpu.read_byte(&op);
-  let result = cpu.flags.inc_byte(value);
+  let value = cpu.read_byte(&op);
+  let result = cpu.flags.cmp_sub_byte(0, value);
   cpu.write_byte(&op, result);
   op.get_cycles()
 }
 pub fn neg_word(cpu: &mut CPU, op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: NEG {}", cpu.current_address, op.label()); }
-  let value = !cpu.read_word(&op);
-  let result = cpu.flags.inc_word(value);
+  let value = cpu.read_word(&op);
+  let result = cpu.flags.cmp_sub_word(0, value);
   cpu.write_word(&op, result);
   op.get_cycles()
 }
 
+//Multiplies and divides take as long from a register as the base, and longer from memory.
+fn long_cycles_byte(op: &operand::Byte, base: usize) -> usize {
+  match op {
+    operand::Byte::Mem{cycles, ..} => base + 6 + cycles,
+    _ => base,
+  }
+}
+fn long_cycles_word(op: &operand::Word, base: usize) -> usize {
+  match op {
+    operand::Word::Mem{cycles, ..} => base + 10 + cycles,
+    _ => base,
+  }
+}
+
 //Unsigned multiply
-pub fn mul_byte(cpu: &mut CPU, op: operand::Byte) {
+pub fn mul_byte(cpu: &mut CPU, op: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: MUL {}", cpu.current_address, op.label()); }
   let al = cpu.regs.get_byte(&register::Byte::AL) as u16;
   let value = cpu.read_byte(&op) as u16;
   let result = al * value;
-  if result & 0b1000_0000 != 0 {
-    cpu.flags.carry = true;
-    cpu.flags.overflow = true;
-  } else {
-    cpu.flags.carry = false;
-    cpu.flags.overflow = false;
-  }
+  cpu.flags.carry = result > 0xFF;
+  cpu.flags.overflow = cpu.flags.carry;
   cpu.regs.set_word(&register::Word::AX, result);
+  long_cycles_byte(&op, 70)
 }
-pub fn mul_word(cpu: &mut CPU, op: operand::Word) {
+pub fn mul_word(cpu: &mut CPU, op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: MUL {}", cpu.current_address, op.label()); }
   let ax = cpu.regs.get_word(&register::Word::AX) as u32;
   let value = cpu.read_word(&op) as u32;
   let result = ax * value;
-  if result & 0b1000_0000_0000_0000 != 0 {
-    cpu.flags.carry = true;
-    cpu.flags.overflow = true;
-  } else {
-    cpu.flags.carry = false;
-    cpu.flags.overflow = false;
-  }
-  let [al, ah, dl, dh] = result.to_le_bytes();
-  let ax = u16::from_le_bytes([al, ah]);
-  let dx = u16::from_le_bytes([dl, dh]);
-  cpu.regs.set_word(&register::Word::AX, ax);
-  cpu.regs.set_word(&register::Word::DX, dx);
+  cpu.flags.carry = result > 0xFFFF;
+  cpu.flags.overflow = cpu.flags.carry;
+  cpu.regs.set_word(&register::Word::AX, result as u16);
+  cpu.regs.set_word(&register::Word::DX, (result >> 16) as u16);
+  long_cycles_word(&op, 118)
 }
 
 //Signed multiply
-pub fn imul_byte(cpu: &mut CPU, op: operand::Byte) {
-  if log_enabled!(Trace) { trace!("{:05X}: MUL {}", cpu.current_address, op.label()); }
+pub fn imul_byte(cpu: &mut CPU, op: operand::Byte) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: IMUL {}", cpu.current_address, op.label()); }
   let al = (cpu.regs.get_byte(&register::Byte::AL) as i8) as i16;
   let value = (cpu.read_byte(&op) as i8) as i16;
-  let result = (al * value) as u16;
-  cpu.flags.carry = false;  //TODO - confirm this?
-  cpu.flags.overflow = false;
-  cpu.regs.set_word(&register::Word::AX, result);
+  let result = al * value;
+  cpu.flags.carry = result != (result as i8) as i16;  //Set when AH is more than the sign of AL.
+  cpu.flags.overflow = cpu.flags.carry;
+  cpu.regs.set_word(&register::Word::AX, result as u16);
+  long_cycles_byte(&op, 80)
 }
-pub fn imul_word(cpu: &mut CPU, op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: MUL {}", cpu.current_address, op.label()); }
+pub fn imul_word(cpu: &mut CPU, op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: IMUL {}", cpu.current_address, op.label()); }
   let ax = (cpu.regs.get_word(&register::Word::AX) as i16) as i32;
   let value = (cpu.read_word(&op) as i16) as i32;
-  let result = (ax * value) as u32;
-  cpu.flags.carry = false;  //TODO - confirm this?
-  cpu.flags.overflow = false;
-  let [al, ah, dl, dh] = result.to_le_bytes();
-  let ax = u16::from_le_bytes([al, ah]);
-  let dx = u16::from_le_bytes([dl, dh]);
-  cpu.regs.set_word(&register::Word::AX, ax);
-  cpu.regs.set_word(&register::Word::DX, dx);
-}
-
-//Unsigned divide
-pub fn div_byte(cpu: &mut CPU, op: operand::Byte) {
+  let result = ax * value;
+  cpu.flags.carry = result != (result as i16) as i32;
+  cpu.flags.overflow = cpu.flags.carry;
+  cpu.regs.set_word(&register::Word::AX, result as u16);
+  cpu.regs.set_word(&register::Word::DX, (result >> 16) as u16);
+  long_cycles_word(&op, 128)
+}
+
+//Unsigned divide. Dividing by zero, or a quotient too big for AL or AX, is a divide error.
+pub fn div_byte(cpu: &mut CPU, op: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: DIV {}", cpu.current_address, op.label()); }
   let ax = cpu.regs.get_word(&register::Word::AX);
   let value = cpu.read_byte(&op) as u16;
+  if value == 0 || ax / value > 0xFF {
+    return jump::divide_error(cpu) + long_cycles_byte(&op, 80);
+  }
   cpu.regs.set_byte(&register::Byte::AL, (ax / value) as u8);
   cpu.regs.set_byte(&register::Byte::AH, (ax % value) as u8);
+  long_cycles_byte(&op, 80)
 }
-pub fn div_word(cpu: &mut CPU, op: operand::Word) {
+pub fn div_word(cpu: &mut CPU, op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: DIV {}", cpu.current_address, op.label()); }
-  let dx = cpu.regs.get_word(&register::Word::DX);
-  let ax = cpu.regs.get_word(&register::Word::AX);
-  let [al, ah] = ax.to_le_bytes();
-  let [dl, dh] = dx.to_le_bytes();
-  let full_number = u32::from_le_bytes([al, ah, dl, dh]);
+  let full_number = ((cpu.regs.get_word(&register::Word::DX) as u32) << 16) | cpu.regs.get_word(&register::Word::AX) as u32;
   let value = cpu.read_word(&op) as u32;
+  if value == 0 || full_number / value > 0xFFFF {
+    return jump::divide_error(cpu) + long_cycles_word(&op, 144);
+  }
   cpu.regs.set_word(&register::Word::AX, (full_number / value) as u16);
   cpu.regs.set_word(&register::Word::DX, (full_number % value) as u16);
+  long_cycles_word(&op, 144)
 }
 
-//Signed divide
-pub fn idiv_byte(cpu: &mut CPU, op: operand::Byte) {
+//Signed divide. The 8086 can't give a quotient of -128 or -32768, so those are divide errors too.
+pub fn idiv_byte(cpu: &mut CPU, op: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IDIV {}", cpu.current_address, op.label()); }
-  let ax = cpu.regs.get_word(&register::Word::AX) as i16;
-  let value = (cpu.read_byte(&op) as i8) as i16;
+  let ax = (cpu.regs.get_word(&register::Word::AX) as i16) as i32;
+  let value = (cpu.read_byte(&op) as i8) as i32;
+  if value == 0 || !(-0x7F..=0x7F).contains(&(ax / value)) {
+    return jump::divide_error(cpu) + long_cycles_byte(&op, 101);
+  }
   cpu.regs.set_byte(&register::Byte::AL, (ax / value) as u8);
   cpu.regs.set_byte(&register::Byte::AH, (ax % value) as u8);
+  long_cycles_byte(&op, 101)
 }
-pub fn idiv_word(cpu: &mut CPU, op: operand::Word) {
+pub fn idiv_word(cpu: &mut CPU, op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IDIV {}", cpu.current_address, op.label()); }
-  let dx = cpu.regs.get_word(&register::Word::DX);
-  let ax = cpu.regs.get_word(&register::Word::AX);
-  let [al, ah] = ax.to_le_bytes();
-  let [dl, dh] = dx.to_le_bytes();
-  let full_number = u32::from_le_bytes([al, ah, dl, dh]) as i32;
-  let value = (cpu.read_word(&op) as i16) as i32;
+  let full_number = (((cpu.regs.get_word(&register::Word::DX) as u32) << 16) | cpu.regs.get_word(&register::Word::AX) as u32) as i32 as i64;
+  let value = (cpu.read_word(&op) as i16) as i64;
+  if value == 0 || !(-0x7FFF..=0x7FFF).contains(&(full_number / value)) {
+    return jump::divide_error(cpu) + long_cycles_word(&op, 165);
+  }
   cpu.regs.set_word(&register::Word::AX, (full_number / value) as u16);
   cpu.regs.set_word(&register::Word::DX, (full_number % value) as u16);
+  long_cycles_word(&op, 165)
 }
 
 //Convert Byte to Word
@@ -237,7 +249,7 @@ pub fn cbw(cpu: &mut CPU) -> usize {
 pub fn cwd(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: CWD", cpu.current_address); }
   if (cpu.regs.get_word(&register::Word::AX) as i16) < 0 {
-    cpu.regs.set_word(&register::Word::DX, 0xFF);
+    cpu.regs.set_word(&register::Word::DX, 0xFFFF);
   } else {
     cpu.regs.set_word(&register::Word::DX, 0x0);
   }
@@ -4,7 +4,6 @@ use super::super::CPU;
 
 use super::super::definitions::operand;
 use super::super::definitions::register;
-use super::super::definitions::memory;
 
 use log::Level::{Trace};
 use log::{trace, log_enabled};
@@ -59,94 +58,108 @@ pub fn xchg_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) ->
   set_op.get_cycles()
 }
 
-pub fn lea_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) {
+pub fn lea_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: LEA {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let value = cpu.read_word(&get_op);
-  cpu.write_word(&set_op, value);
-  if let Mem{cycles, ..} = get_op {
-
-  } else {
-    unreachable!("Tried to ")
+  match get_op {
+    operand::Word::Mem{addr, cycles, ..} => {
+      cpu.write_word(&set_op, addr);
+      2 + cycles
+    },
+    _ => 2, //LEA from a register is undefined. We leave the register as it is.
   }
 }
 
-pub fn les_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) {
+pub fn les_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: LES {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
   let value = cpu.read_word(&get_op);
   cpu.write_word(&set_op, value);
-  if let operand::Word::Mem{addr, ..} = get_op {
-    let val2 = cpu.memory.get_word(addr + 2); //Read next word
+  if let operand::Word::Mem{addr, cycles, ..} = get_op {
+    let val2 = cpu.memory.get_word(addr.wrapping_add(2)); //Read next word
     cpu.memory.es = val2;
-  } //If it is not memory, then this is undefined behaviour. We don't really care. We just won't set ES.
+    24 + cycles
+  } else { //If it is not memory, then this is undefined behaviour. We don't really care. We just won't set ES.
+    2
+  }
 }
-pub fn lds_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) {
-  if log_enabled!(Trace) { trace!("{:05X}: LES {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
+pub fn lds_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Word) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: LDS {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
   let value = cpu.read_word(&get_op);
   cpu.write_word(&set_op, value);
-  if let operand::Word::Mem{addr, ..} = get_op {
-    let val2 = cpu.memory.get_word(addr + 2); //Read next word
+  if let operand::Word::Mem{addr, cycles, ..} = get_op {
+    let val2 = cpu.memory.get_word(addr.wrapping_add(2)); //Read next word
     cpu.memory.ds = val2;
-  } //If it is not memory, then this is undefined behaviour. We don't really care. We just won't set DS.
+    24 + cycles
+  } else { //If it is not memory, then this is undefined behaviour. We don't really care. We just won't set DS.
+    2
+  }
 }
 
 //Translate byte from table.
-pub fn xlat(cpu: &mut CPU) {
+pub fn xlat(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: XLAT", cpu.current_address); }
   //AL = [DS:BX + unsigned AL]
   let offset = cpu.regs.get_byte(&register::Byte::AL) as u16;
-  cpu.memory.current_segment = memory::Segment::DS;
-  let value = cpu.memory.get_byte(cpu.regs.bx + offset);
+  let value = cpu.memory.get_byte(cpu.regs.bx.wrapping_add(offset));
   cpu.regs.set_byte(&register::Byte::AL, value);
+  11
 }
 
-pub fn in_al_byte(cpu: &mut CPU, port: operand::Byte) {
+pub fn in_al_byte(cpu: &mut CPU, port: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IN AL, {}", cpu.current_address, port.label()); }
   let port_val = cpu.read_byte(&port);
   let result = in_byte_msg(cpu, port_val as u16);
   cpu.regs.set_byte(&register::Byte::AL, result);
+  10
 }
-pub fn in_ax_byte(cpu: &mut CPU, port: operand::Byte) {
+pub fn in_ax_byte(cpu: &mut CPU, port: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IN AX, {}", cpu.current_address, port.label()); }
   let port_val = cpu.read_byte(&port);
   let result = in_word_msg(cpu, port_val as u16);
   cpu.regs.set_word(&register::Word::AX, result);
+  14
 }
 
-pub fn in_al_word(cpu: &mut CPU) {
+pub fn in_al_word(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IN AL, DX", cpu.current_address); }
   let port_val = cpu.regs.get_word(&register::Word::DX);
   let result = in_byte_msg(cpu, port_val);
   cpu.regs.set_byte(&register::Byte::AL, result);
+  8
 }
-pub fn in_ax_word(cpu: &mut CPU) {
+pub fn in_ax_word(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: IN AX, DX", cpu.current_address); }
   let port_val = cpu.regs.get_word(&register::Word::DX);
   let result = in_word_msg(cpu, port_val);
   cpu.regs.set_word(&register::Word::AX, result);
+  12
 }
 
-pub fn out_al_byte(cpu: &mut CPU, port: operand::Byte) {
+pub fn out_al_byte(cpu: &mut CPU, port: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: OUT {}, AL", cpu.current_address, port.label()); }
   let port_val = cpu.read_byte(&port);
   let value = cpu.regs.get_byte(&register::Byte::AL);
   out_byte_msg(cpu, port_val as u16, value);
+  10
 }
-pub fn out_ax_byte(cpu: &mut CPU, port: operand::Byte) {
+pub fn out_ax_byte(cpu: &mut CPU, port: operand::Byte) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: OUT {}, AX", cpu.current_address, port.label()); }
   let port_val = cpu.read_byte(&port);
   let value = cpu.regs.get_word(&register::Word::AX);
   out_word_msg(cpu, port_val as u16, value);
+  14
 }
 
-pub fn out_al_word(cpu: &mut CPU) {
+pub fn out_al_word(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: OUT DX, AL", cpu.current_address); }
   let port_val = cpu.regs.get_word(&register::Word::DX);
   let value = cpu.regs.get_byte(&register::Byte::AL);
   out_byte_msg(cpu, port_val, value);
+  8
 }
-pub fn out_ax_word(cpu: &mut CPU) {
+pub fn out_ax_word(cpu: &mut CPU) -> usize {
   if log_enabled!(Trace) { trace!("{:05X}: OUT DX, AX", cpu.current_address); }
   let port_val = cpu.regs.get_word(&register::Word::DX);
   let value = cpu.regs.get_word(&register::Word::AX);
   out_word_msg(cpu, port_val, value);
+  12
 }
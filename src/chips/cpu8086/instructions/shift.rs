@@ -1,147 +1,72 @@
 use super::super::CPU;
 
 use super::super::definitions::operand;
+use super::super::definitions::flag::Shift;
 
 use log::Level::Trace;
 use log::{trace, log_enabled};
 
-pub fn shr_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
+//The count is 1, or CL.
+fn shift_byte(cpu: &mut CPU, name: &str, shift: Shift, set_op: operand::Byte, get_op: operand::Byte) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: {} {}, {}", cpu.current_address, name, set_op.label(), get_op.label()); }
   let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let result = cpu.flags.shr_ror_rcr_byte(set_val, get_val);
+  let result = cpu.flags.shift_byte(shift, set_val, get_val);
   cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  set_op.get_rotate_cycles(&get_op, get_val)
 }
-pub fn shr_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
+fn shift_word(cpu: &mut CPU, name: &str, shift: Shift, set_op: operand::Word, get_op: operand::Byte) -> usize {
+  if log_enabled!(Trace) { trace!("{:05X}: {} {}, {}", cpu.current_address, name, set_op.label(), get_op.label()); }
   let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let result = cpu.flags.shr_ror_rcr_word(set_val, get_val);
+  let result = cpu.flags.shift_word(shift, set_val, get_val);
   cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  set_op.get_rotate_cycles(&get_op, get_val)
 }
 
-pub fn sar_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let mut result = cpu.flags.shr_ror_rcr_byte(set_val, get_val);
-  if (set_val as i8) < 0 {
-    result |= 0b1000_0000;
-  }
-  cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+pub fn rol_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
+  shift_byte(cpu, "ROL", Shift::Rol, set_op, get_op)
 }
-pub fn sar_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let mut result = cpu.flags.shr_ror_rcr_word(set_val, get_val);
-  if (set_val as i8) < 0 {
-    result |= 0b1000_0000;
-  }
-  cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+pub fn rol_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
+  shift_word(cpu, "ROL", Shift::Rol, set_op, get_op)
 }
 
 pub fn ror_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let mut result = cpu.flags.shr_ror_rcr_byte(set_val, get_val);
-  if cpu.flags.carry {
-    result |= 0b1000_0000;
-  }
-  cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  shift_byte(cpu, "ROR", Shift::Ror, set_op, get_op)
 }
 pub fn ror_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let mut result = cpu.flags.shr_ror_rcr_word(set_val, get_val);
-  if cpu.flags.carry {
-    result |= 0b1000_0000;
-  }
-  cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  shift_word(cpu, "ROR", Shift::Ror, set_op, get_op)
+}
+
+pub fn rcl_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
+  shift_byte(cpu, "RCL", Shift::Rcl, set_op, get_op)
+}
+pub fn rcl_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
+  shift_word(cpu, "RCL", Shift::Rcl, set_op, get_op)
 }
 
 pub fn rcr_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let old_carry = cpu.flags.carry;
-  let mut result = cpu.flags.shr_ror_rcr_byte(set_val, get_val);
-  if old_carry {
-    result |= 0b1000_0000;
-  }
-  cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  shift_byte(cpu, "RCR", Shift::Rcr, set_op, get_op)
 }
 pub fn rcr_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHR {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let old_carry = cpu.flags.carry;
-  let mut result = cpu.flags.shr_ror_rcr_word(set_val, get_val);
-  if old_carry {
-    result |= 0b1000_0000;
-  }
-  cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  shift_word(cpu, "RCR", Shift::Rcr, set_op, get_op)
 }
 
-
-
 pub fn shl_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHL {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let result = cpu.flags.shl_rol_rcl_byte(set_val, get_val);
-  cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  shift_byte(cpu, "SHL", Shift::Shl, set_op, get_op)
 }
 pub fn shl_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: SHL {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let result = cpu.flags.shl_rol_rcl_word(set_val, get_val);
-  cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+  shift_word(cpu, "SHL", Shift::Shl, set_op, get_op)
 }
 
-pub fn rol_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: ROL {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let mut result = cpu.flags.shl_rol_rcl_byte(set_val, get_val);
-  if cpu.flags.carry {
-    result |= 1;
-  }
-  cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+pub fn shr_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
+  shift_byte(cpu, "SHR", Shift::Shr, set_op, get_op)
 }
-pub fn rol_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: ROL {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let mut result = cpu.flags.shl_rol_rcl_word(set_val, get_val);
-  if cpu.flags.carry {
-    result |= 1;
-  }
-  cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+pub fn shr_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
+  shift_word(cpu, "SHR", Shift::Shr, set_op, get_op)
 }
 
-pub fn rcl_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: RCL {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_byte(&set_op), cpu.read_byte(&get_op));
-  let old_carry = cpu.flags.carry;
-  let mut result = cpu.flags.shl_rol_rcl_byte(set_val, get_val);
-  if old_carry {
-    result |= 1;
-  }
-  cpu.write_byte(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+pub fn sar_byte(cpu: &mut CPU, set_op: operand::Byte, get_op: operand::Byte) -> usize {
+  shift_byte(cpu, "SAR", Shift::Sar, set_op, get_op)
 }
-pub fn rcl_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
-  if log_enabled!(Trace) { trace!("{:05X}: RCL {}, {}", cpu.current_address, set_op.label(), get_op.label()); }
-  let (set_val, get_val) = (cpu.read_word(&set_op), cpu.read_byte(&get_op));
-  let old_carry = cpu.flags.carry;
-  let mut result = cpu.flags.shl_rol_rcl_word(set_val, get_val);
-  if old_carry {
-    result |= 1;
-  }
-  cpu.write_word(&set_op, result);
-  set_op.get_rotate_cycles(get_op)
+pub fn sar_word(cpu: &mut CPU, set_op: operand::Word, get_op: operand::Byte) -> usize {
+  shift_word(cpu, "SAR", Shift::Sar, set_op, get_op)
 }
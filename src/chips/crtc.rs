@@ -0,0 +1,432 @@
+//6845 - Motorola CRT Controller
+//https://stanislavs.org/helppc/6845.html
+//
+//Both the MDA and the CGA carry one. It counts out characters and scan lines, and generates the video memory addresses.
+//The adapter around it supplies the character clock, and turns the bytes it fetches into dots.
+
+use super::framebuffer::{self, Framebuffer};
+
+use log::{debug, error};
+
+const VERTICAL_SYNC_LINES: usize = 16;  //The 6845 has a fixed vertical sync width.
+pub const CHARACTER_BLINK_FRAMES: u64 = 32;  //Blinking characters are on for 16 frames, then off for 16.
+
+#[derive(Debug, Default)]
+enum Register {
+  #[default]
+  HorizontalTotalCharacter,
+  HorizontalDisplayedCharactersPerLine,
+  HorizontalSyncPosition,
+  HorizontalSyncCharacterWidth,
+  VerticalTotalLines,
+  VerticalTotalAdjust,
+  VerticalDisplayedRows,
+  VerticalSyncCharacterRows,
+  InterlaceMode,
+  MaximumScanLineAddress,
+  CursorStart,
+  CursorEnd,
+  StartAddressMSB,
+  StartAddressLSB,
+  CursorAddressMSB,
+  CursorAddressLSB,
+  LightPenMSB,
+  LightPenLSB,
+}
+
+/// What a front end needs to draw a text mode screen.
+pub struct TextScreen {
+  pub columns: usize,
+  pub rows: usize,
+  pub character_height: u8,
+  pub color: bool,
+  pub blink: bool,          //Attribute bit 7 blinks, instead of brightening the background.
+  pub cursor: Option<Cursor>,
+  pub cells: Vec<(u8, u8)>, //(character, attribute), row by row.
+}
+
+pub struct Cursor {
+  pub column: usize,
+  pub row: usize,
+  pub start: u8,            //First and last scan lines of the cursor block.
+  pub end: u8,
+  pub blink_frames: u64,    //On for half of this many frames, then off for the other half.
+}
+
+#[derive(Debug, Default)]
+pub struct Crtc {
+  current_register: Register,
+  horizontal_total_character: u8,
+  horizontal_displayed_characters_per_line: u8,
+  horizontal_sync_position: u8,
+  horizontal_sync_character_width: u8,
+  vertical_total_lines: u8,
+  vertical_total_adjust: u8,
+  vertical_displayed_rows: u8,
+  vertical_sync_character_rows: u8,
+  interlace_mode: u8,
+  maximum_scan_line_address: u8,
+  cursor_start: u8,
+  cursor_end: u8,
+  start_address: u16,
+  cursor_address: u16,
+  light_pen: u16,
+}
+
+pub fn start() -> Crtc {
+  Default::default()
+}
+
+impl Crtc {
+  pub fn choose_register(&mut self, register: u8) {
+    self.current_register = match register {
+      0x00 => Register::HorizontalTotalCharacter,
+      0x01 => Register::HorizontalDisplayedCharactersPerLine,
+      0x02 => Register::HorizontalSyncPosition,
+      0x03 => Register::HorizontalSyncCharacterWidth,
+      0x04 => Register::VerticalTotalLines,
+      0x05 => Register::VerticalTotalAdjust,
+      0x06 => Register::VerticalDisplayedRows,
+      0x07 => Register::VerticalSyncCharacterRows,
+      0x08 => Register::InterlaceMode,
+      0x09 => Register::MaximumScanLineAddress,
+      0x0A => Register::CursorStart,
+      0x0B => Register::CursorEnd,
+      0x0C => Register::StartAddressMSB,
+      0x0D => Register::StartAddressLSB,
+      0x0E => Register::CursorAddressMSB,
+      0x0F => Register::CursorAddressLSB,
+      0x10 => Register::LightPenMSB,
+      0x11 => Register::LightPenLSB,
+      _ => {error!("Invalid Register index passed to the 6845: {}", register); Register::HorizontalTotalCharacter},
+    };
+    debug!("Referencing {:?}", self.current_register);
+  }
+  
+  pub fn set_register_data(&mut self, register: u8) {
+    match self.current_register {
+      Register::HorizontalTotalCharacter => self.horizontal_total_character = register,
+      Register::HorizontalDisplayedCharactersPerLine => self.horizontal_displayed_characters_per_line = register,
+      Register::HorizontalSyncPosition => self.horizontal_sync_position = register,
+      Register::HorizontalSyncCharacterWidth => self.horizontal_sync_character_width = register,
+      Register::VerticalTotalLines => self.vertical_total_lines = register,
+      Register::VerticalTotalAdjust => self.vertical_total_adjust = register,
+      Register::VerticalDisplayedRows => self.vertical_displayed_rows = register,
+      Register::VerticalSyncCharacterRows => self.vertical_sync_character_rows = register,
+      Register::InterlaceMode => self.interlace_mode = register,
+      Register::MaximumScanLineAddress => self.maximum_scan_line_address = register,
+      Register::CursorStart => self.cursor_start = register,
+      Register::CursorEnd => self.cursor_end = register,
+      Register::StartAddressMSB => self.start_address = (self.start_address & 0xFF) | ((register as u16) << 8),
+      Register::StartAddressLSB => self.start_address = (self.start_address & 0xFF00) | (register as u16),
+      Register::CursorAddressMSB => self.cursor_address = (self.cursor_address & 0xFF) | ((register as u16) << 8),
+      Register::CursorAddressLSB => self.cursor_address = (self.cursor_address & 0xFF00) | (register as u16),
+      Register::LightPenMSB => self.light_pen = (self.light_pen & 0xFF) | ((register as u16) << 8),
+      Register::LightPenLSB => self.light_pen = (self.light_pen & 0xFF00) | (register as u16),
+    }
+    debug!("Set value {:X}", register);
+  }
+
+  /// Only the cursor address and light pen registers can be read back. The rest read as 0.
+  pub fn get_register_data(&self) -> u8 {
+    match self.current_register {
+      Register::CursorAddressMSB => (self.cursor_address >> 8) as u8,
+      Register::CursorAddressLSB => self.cursor_address as u8,
+      Register::LightPenMSB => (self.light_pen >> 8) as u8,
+      Register::LightPenLSB => self.light_pen as u8,
+      _ => 0,
+    }
+  }
+
+  /// Characters per row, or the default before the BIOS has programmed it.
+  pub fn columns(&self, default: usize) -> usize {
+    match self.horizontal_displayed_characters_per_line {
+      0 => default,
+      columns => columns as usize,
+    }
+  }
+
+  /// Character rows per frame, or the default before the BIOS has programmed it.
+  pub fn rows(&self, default: usize) -> usize {
+    match self.vertical_displayed_rows & 0x7F {
+      0 => default,
+      rows => rows as usize,
+    }
+  }
+
+  pub fn lines_per_row(&self) -> usize {
+    (self.maximum_scan_line_address & 0x1F) as usize + 1
+  }
+
+  /// Word address of the top left character.
+  pub fn start_address(&self) -> usize {
+    self.start_address as usize
+  }
+
+  /// (character clocks per scan line, scan lines per frame)
+  fn frame_size(&self) -> (u64, u64) {
+    let horizontal_total = self.horizontal_total_character as u64 + 1;
+    let vertical_total = ((self.vertical_total_lines & 0x7F) as u64 + 1) * self.lines_per_row() as u64 + (self.vertical_total_adjust & 0x1F) as u64;
+    (horizontal_total, vertical_total)
+  }
+
+  /// Where the beam is after this many character clocks, as (character clock within the line, scan line within the frame).
+  fn beam_position(&self, character_clocks: u64) -> (usize, usize) {
+    let (horizontal_total, vertical_total) = self.frame_size();
+    let line = character_clocks / horizontal_total;
+    ((character_clocks % horizontal_total) as usize, (line % vertical_total) as usize)
+  }
+
+  /// Character clocks in one frame, retrace included.
+  pub fn frame_clocks(&self) -> u64 {
+    let (horizontal_total, vertical_total) = self.frame_size();
+    horizontal_total * vertical_total
+  }
+
+  /// Frames since power on. This drives the cursor and character blink.
+  pub fn frame_number(&self, character_clocks: u64) -> u64 {
+    character_clocks / self.frame_clocks()
+  }
+
+  /// (display enable, horizontal sync, vertical sync)
+  pub fn raster_status(&self, character_clocks: u64) -> (bool, bool, bool) {
+    let (column, scan_line) = self.beam_position(character_clocks);
+    let lines_per_row = self.lines_per_row();
+    let display_enable = column < self.horizontal_displayed_characters_per_line as usize
+      && scan_line < (self.vertical_displayed_rows & 0x7F) as usize * lines_per_row;
+    let horizontal_sync_start = self.horizontal_sync_position as usize;
+    let horizontal_sync_width = (self.horizontal_sync_character_width & 0x0F) as usize;
+    let horizontal_sync = (horizontal_sync_start..horizontal_sync_start + horizontal_sync_width).contains(&column);
+    let vertical_sync_start = (self.vertical_sync_character_rows & 0x7F) as usize * lines_per_row;
+    let vertical_sync = (vertical_sync_start..vertical_sync_start + VERTICAL_SYNC_LINES).contains(&scan_line);
+    (display_enable, horizontal_sync, vertical_sync)
+  }
+
+  /// The light pen strobe. Latches the address the beam is at.
+  pub fn strobe_light_pen(&mut self, character_clocks: u64) {
+    let (column, scan_line) = self.beam_position(character_clocks);
+    let row = scan_line / self.lines_per_row();
+    let columns = self.horizontal_displayed_characters_per_line as usize;
+    self.light_pen = (self.start_address as usize + row * columns + column.min(columns)) as u16 & 0x3FFF;
+  }
+
+  /// Addresses wrap around within the video memory given.
+  pub fn text_screen(&self, video_memory: &[u8], default_columns: usize, color: bool, blink: bool) -> TextScreen {
+    let memory_size = video_memory.len();
+    let columns = self.columns(default_columns);
+    let rows = self.rows(25);
+    let character_height = (self.maximum_scan_line_address & 0x1F) + 1;
+    let start = self.cursor_start & 0x1F;
+    let end = self.cursor_end & 0x1F;
+    //Cursor start bits 5-6 = 01 turns the cursor off.
+    let cursor_off = matches!(self.cursor_start & 0b110_0000, 0b010_0000) || start >= character_height;
+    let blink_frames = if matches!(self.cursor_start & 0b110_0000, 0b110_0000) { 32 } else { 16 };
+    let position = (self.cursor_address.wrapping_sub(self.start_address) as usize) & (memory_size / 2 - 1);
+    let cursor = if cursor_off || position >= columns * rows {
+      None
+    } else {
+      Some(Cursor {
+        column: position % columns,
+        row: position / columns,
+        start,
+        end,
+        blink_frames,
+      })
+    };
+    let start_offset = self.start_address as usize * 2;
+    let cells = (0..columns * rows).map(|index| {
+      let offset = (start_offset + index * 2) & (memory_size - 1);
+      (video_memory[offset], video_memory[offset + 1])
+    }).collect();
+    TextScreen {
+      columns,
+      rows,
+      character_height,
+      color,
+      blink,
+      cursor,
+      cells,
+    }
+  }
+}
+
+/// Colors for one character cell: (foreground, background, underlined).
+pub type AttributeColors = fn(attribute: u8, blink: bool) -> ([u8; 3], [u8; 3], bool);
+
+/// Draw a text screen.
+/// With 9 dot wide characters, the 9th column is copied from the 8th for line drawing characters.
+pub fn render_text(screen: &TextScreen, frame: u64, character_width: usize, glyph: fn(u8) -> &'static [u8], colors: AttributeColors) -> Framebuffer {
+  let character_height = screen.character_height as usize;
+  let underline_row = character_height.saturating_sub(2);
+  let mut image = framebuffer::new(screen.columns * character_width, screen.rows * character_height);
+  let blink_on = frame % CHARACTER_BLINK_FRAMES < CHARACTER_BLINK_FRAMES / 2;
+
+  for row in 0..screen.rows {
+    for column in 0..screen.columns {
+      let (character, attribute) = screen.cells[row * screen.columns + column];
+      let (foreground, background, underline) = colors(attribute, screen.blink);
+      //Blinking characters show as their background.
+      let hidden = screen.blink && attribute & 0x80 != 0 && !blink_on;
+      let glyph = glyph(character);
+      let cursor_here = screen.cursor.as_ref().filter(|cursor| {
+        cursor.row == row && cursor.column == column && frame % cursor.blink_frames < cursor.blink_frames / 2
+      });
+
+      for line in 0..character_height {
+        let bits = glyph.get(line).copied().unwrap_or(0);
+        //The cursor covers scan lines start to end. If start is past end, it wraps around.
+        let in_cursor = cursor_here.is_some_and(|cursor| {
+          let (start, end, line) = (cursor.start as usize, cursor.end as usize, line);
+          if start <= end { (start..=end).contains(&line) } else { line >= start || line <= end }
+        });
+        let underlined = underline && line == underline_row;
+        for x in 0..character_width {
+          let pixel = match x {
+            0..=7 => bits & (0x80 >> x) != 0,
+            _ => (0xC0..=0xDF).contains(&character) && bits & 1 != 0,
+          };
+          let lit = in_cursor || ((pixel || underlined) && !hidden);
+          let color = if lit { foreground } else { background };
+          image.set_pixel(column * character_width + x, row * character_height + line, color);
+        }
+      }
+    }
+  }
+  image
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const WHITE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+  const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+
+  //Registers 0 up, in order.
+  fn program(registers: &[u8]) -> Crtc {
+    let mut crtc = start();
+    for (index, &value) in registers.iter().enumerate() {
+      crtc.choose_register(index as u8);
+      crtc.set_register_data(value);
+    }
+    crtc
+  }
+
+  fn solid(_character: u8) -> &'static [u8] {
+    &[0xFF; 8]
+  }
+
+  fn blank(_character: u8) -> &'static [u8] {
+    &[0x00; 8]
+  }
+
+  //Foreground white on black, and underlined if the attribute says 1.
+  fn white_on_black(attribute: u8, _blink: bool) -> ([u8; 3], [u8; 3], bool) {
+    (WHITE, BLACK, attribute == 1)
+  }
+
+  fn screen(cells: Vec<(u8, u8)>, blink: bool, cursor: Option<Cursor>) -> TextScreen {
+    TextScreen {
+      columns: cells.len(),
+      rows: 1,
+      character_height: 8,
+      color: false,
+      blink,
+      cursor,
+      cells,
+    }
+  }
+
+  #[test]
+  fn text_screen_starts_at_the_start_address() {
+    //4x2 characters, starting at word 3.
+    let mut crtc = program(&[0, 4, 0, 0, 0, 0, 2, 0, 0, 7, 0, 0, 0x00, 0x03]);
+    let memory: Vec<u8> = (0..=0xFF).collect();
+    let screen = crtc.text_screen(&memory, 80, false, false);
+    assert_eq!((screen.columns, screen.rows), (4, 2));
+    assert_eq!(screen.cells[0], (6, 7));
+    assert_eq!(screen.cells[4], (14, 15));
+
+    //Addresses wrap around at the end of video memory.
+    crtc.choose_register(0x0D);
+    crtc.set_register_data(0x7F);
+    let screen = crtc.text_screen(&memory, 80, false, false);
+    assert_eq!(screen.cells[0], (0xFE, 0xFF));
+    assert_eq!(screen.cells[1], (0x00, 0x01));
+  }
+
+  #[test]
+  fn cursor_is_placed_from_the_start_address() {
+    let mut crtc = program(&[0, 4, 0, 0, 0, 0, 2, 0, 0, 7, 6, 7, 0x00, 0x03, 0x00, 0x03 + 5]);
+    let memory = vec![0u8; 0x100];
+    let cursor = crtc.text_screen(&memory, 80, false, false).cursor.unwrap();
+    assert_eq!((cursor.column, cursor.row, cursor.start, cursor.end, cursor.blink_frames), (1, 1, 6, 7, 16));
+    assert_eq!(crtc.get_register_data(), 0x08, "The cursor address reads back.");
+
+    crtc.choose_register(0x0A);
+    crtc.set_register_data(0b110_0110);
+    assert_eq!(crtc.text_screen(&memory, 80, false, false).cursor.unwrap().blink_frames, 32, "Slow blink");
+    crtc.set_register_data(0b010_0110);
+    assert!(crtc.text_screen(&memory, 80, false, false).cursor.is_none(), "Cursor off");
+
+    crtc.set_register_data(6);
+    crtc.choose_register(0x0F);
+    crtc.set_register_data(0x03 + 8);
+    assert!(crtc.text_screen(&memory, 80, false, false).cursor.is_none(), "Past the end of the screen");
+  }
+
+  #[test]
+  fn blinking_characters_show_as_background_half_the_time() {
+    let screen = screen(vec![(b'A', 0x80), (b'A', 0x00)], true, None);
+    let on = render_text(&screen, 0, 8, solid, white_on_black);
+    let off = render_text(&screen, CHARACTER_BLINK_FRAMES / 2, 8, solid, white_on_black);
+    assert_eq!(on.pixel(0, 0), WHITE);
+    assert_eq!(off.pixel(0, 0), BLACK);
+    assert_eq!(off.pixel(8, 0), WHITE, "Only bit 7 blinks.");
+
+    let screen = TextScreen{blink: false, ..screen};
+    let off = render_text(&screen, CHARACTER_BLINK_FRAMES / 2, 8, solid, white_on_black);
+    assert_eq!(off.pixel(0, 0), WHITE, "With blink off, bit 7 is left to the colors.");
+  }
+
+  #[test]
+  fn cursor_and_underline_are_drawn_over_the_character() {
+    let cursor = Cursor{column: 0, row: 0, start: 6, end: 7, blink_frames: 16};
+    let screen = screen(vec![(b' ', 0x07), (b' ', 1)], false, Some(cursor));
+    let image = render_text(&screen, 0, 8, blank, white_on_black);
+    assert_eq!(image.pixel(0, 5), BLACK);
+    assert_eq!(image.pixel(0, 6), WHITE);
+    assert_eq!(image.pixel(7, 7), WHITE);
+    assert_eq!(image.pixel(8, 6), WHITE, "Underlined on the second to last line");
+    assert_eq!(image.pixel(8, 7), BLACK);
+
+    let image = render_text(&screen, 8, 8, blank, white_on_black);
+    assert_eq!(image.pixel(0, 6), BLACK, "The cursor blinks.");
+  }
+
+  #[test]
+  fn ninth_column_repeats_line_drawing_characters() {
+    let screen = screen(vec![(0xC4, 0x07), (b'A', 0x07)], false, None);
+    let image = render_text(&screen, 0, 9, solid, white_on_black);
+    assert_eq!(image.pixel(8, 0), WHITE);
+    assert_eq!(image.pixel(17, 0), BLACK);
+  }
+
+  #[test]
+  fn raster_status_follows_the_beam() {
+    //10 clocks per line, 8 displayed, sync at 8-9. 30 lines of 1 scan line, 5 displayed, vertical sync from line 7.
+    let crtc = program(&[9, 8, 8, 2, 29, 0, 5, 7, 0, 0]);
+    assert_eq!(crtc.frame_clocks(), 300);
+    assert_eq!(crtc.raster_status(0), (true, false, false));
+    assert_eq!(crtc.raster_status(7), (true, false, false));
+    assert_eq!(crtc.raster_status(8), (false, true, false));
+    assert_eq!(crtc.raster_status(9), (false, true, false));
+    assert_eq!(crtc.raster_status(50), (false, false, false), "Below the display area");
+    assert_eq!(crtc.raster_status(70), (false, false, true));
+    assert_eq!(crtc.raster_status(220), (false, false, true), "Vertical sync is 16 lines.");
+    assert_eq!(crtc.raster_status(230), (false, false, false));
+    assert_eq!(crtc.raster_status(300), (true, false, false), "The next frame");
+    assert_eq!(crtc.frame_number(299), 0);
+    assert_eq!(crtc.frame_number(300), 1);
+  }
+}
@@ -1,8 +1,21 @@
 //Intel 8237 / AMD 1980 - Direct Memory Access (DMA)
 //https://wiki.osdev.org/ISA_DMA
 
-use log::{debug,error};
+use log::{debug,error,trace};
 use super::shared::FlipFlop;
+use super::memory1mb::Memory;
+
+/// A device on the other end of a DMA channel. Each call is one DACK cycle.
+pub trait DMADevice {
+  /// Write to memory transfer: the device puts a byte on the bus.
+  fn read(&mut self) -> u8;
+  /// Read from memory transfer: the device takes a byte from the bus.
+  fn write(&mut self, value: u8);
+  /// DREQ after the DACK cycle. The device keeps it active while it has more to move.
+  fn dreq(&self) -> bool;
+  /// The count ran out. This is the TC / EOP signal.
+  fn terminal_count(&mut self) {}
+}
 
 #[derive(Default)]
 pub struct DMA {
@@ -16,7 +29,7 @@ pub struct DMA {
 #[derive(Default,Debug)]
 enum TransferType {
   #[default]
-  SelfTest,
+  SelfTest, //Verify. The addresses and count run, but no data moves.
   WriteToMemory,
   ReadFromMemory,
 }
@@ -35,9 +48,18 @@ struct Channel {
   mask: bool,
   address: u16,
   count: u16,
+  base_address: u16,  //Reloaded into address when auto initializing.
+  base_count: u16,
+  page: u8, //Address bits 16-19, from the page register.
   flip_flop: FlipFlop,
   transfer_type: TransferType,
   transfer_mode: TransferMode,
+  auto_init: bool,
+  decrement: bool,
+  request: bool,  //Request register, set by software. Cleared at terminal count.
+  dreq: bool,     //DREQ pin, from the device.
+  terminal_count: bool, //Reached since the status was last read.
+  device: Option<Box<dyn DMADevice>>,
 }
 
 pub fn start() -> DMA {
@@ -46,15 +68,83 @@ pub fn start() -> DMA {
 
 impl DMA {
   
+  //Command Register. Bit 2 is "controller disable".
   pub fn set_status(&mut self, register: u8) {
-    self.enabled = matches!(register & 0b100, 0b100);
+    self.enabled = matches!(register & 0b100, 0);
     debug!("Enabled: {}", self.enabled);
   }
   
+  //Bits 0-3: Channel reached terminal count. Bits 4-7: Channel has a request pending.
+  //Reading clears the terminal count bits.
   pub fn get_status(&mut self) -> u8 {
-    //TODO - implement this
-    debug!("Get Status");
-    0
+    let mut result = 0;
+    for channel_index in 0..4 {
+      let channel = self.get_channel(channel_index);
+      if channel.terminal_count { result |= 1 << channel_index; }
+      if channel.pending() { result |= 0b1_0000 << channel_index; }
+      channel.terminal_count = false;
+    }
+    debug!("Get Status {:08b}", result);
+    result
+  }
+
+  //Request Register. A software DREQ, which lasts until terminal count or until it is cleared.
+  pub fn set_request(&mut self, register: u8) {
+    let channel_index = register & 0b11;
+    let request = matches!(register & 0b100, 0b100);
+    debug!("Channel {} request {}", channel_index, request);
+    self.get_channel(channel_index).request = request;
+  }
+
+  /// DREQ from a device. The request is held until the device drops it.
+  pub fn set_dreq(&mut self, channel_index: u8, request: bool) {
+    trace!("Channel {} DREQ {}", channel_index, request);
+    self.get_channel(channel_index).dreq = request;
+  }
+
+  /// Attach the device whose DACK cycles move the data, and which decides whether DREQ stays active after each one.
+  pub fn connect(&mut self, channel_index: u8, device: Box<dyn DMADevice>) {
+    self.get_channel(channel_index).device = Some(device);
+  }
+
+  //Page registers 87, 83, 81, 82 for channels 0-3.
+  pub fn set_page(&mut self, channel_index: u8, register: u8) {
+    let channel = self.get_channel(channel_index);
+    channel.page = register & 0xF;
+    debug!("Set Channel {} Page: {:X}", channel_index, channel.page);
+  }
+
+  pub fn has_request(&self) -> bool {
+    self.enabled && [&self.channel_0, &self.channel_1, &self.channel_2, &self.channel_3].iter()
+      .any(|channel| channel.pending() && !channel.mask && !matches!(channel.transfer_mode, TransferMode::Cascade))
+  }
+
+  /// Give the bus to every channel with a pending request, in fixed priority order (channel 0 highest).
+  /// Single mode moves one byte per request, block mode runs to terminal count,
+  /// and demand mode runs for as long as DREQ stays active.
+  /// Returns the number of bus cycles taken.
+  pub fn service(&mut self, memory: &mut Memory) -> usize {
+    let mut transfers = 0;
+    if !self.enabled {
+      return transfers;
+    }
+    for channel_index in 0..4 {
+      let channel = self.get_channel(channel_index);
+      if !channel.pending() || channel.mask {
+        continue;
+      }
+      let mut transfer = |channel: &mut Channel| {
+        transfers += 1;
+        channel.transfer(channel_index, memory)
+      };
+      match channel.transfer_mode {
+        TransferMode::SingleDMA => { transfer(channel); },
+        TransferMode::BlockDMA => while !transfer(channel) {},
+        TransferMode::OnDemand => while channel.pending() && !transfer(channel) {},
+        TransferMode::Cascade => {}, //Another DMA controller drives the bus.
+      }
+    }
+    transfers
   }
   
   fn get_channel(&mut self, channel_index: u8) -> &mut Channel {
@@ -111,6 +201,7 @@ impl DMA {
         (channel.address & 0xFF) | ((register as u16) << 8)
       },
     };
+    channel.base_address = channel.address;
     debug!("Set Channel {} Address: {:X}", channel_index, channel.address);
   }
 
@@ -126,19 +217,20 @@ impl DMA {
         (channel.count & 0xFF) | ((register as u16) << 8)
       },
     };
+    channel.base_count = channel.count;
     debug!("Set Channel {} Count: {:X}", channel_index, channel.count);
   }
 
   pub fn reset_master(&mut self) {
-    //TODO - Master Reset sets Flip-Flop low, clears Status, and sets all Mask bits ON.
-    self.channel_0.mask = true;
-    self.channel_0.flip_flop = FlipFlop::Low;
-    self.channel_1.mask = true;
-    self.channel_1.flip_flop = FlipFlop::Low;
-    self.channel_2.mask = true;
-    self.channel_2.flip_flop = FlipFlop::Low;
-    self.channel_3.mask = true;
-    self.channel_3.flip_flop = FlipFlop::Low;
+    //Master Reset clears the Command, Status and Request registers, sets Flip-Flop low, and sets all Mask bits ON.
+    self.enabled = true;
+    for channel_index in 0..4 {
+      let channel = self.get_channel(channel_index);
+      channel.mask = true;
+      channel.flip_flop = FlipFlop::Low;
+      channel.request = false;
+      channel.terminal_count = false;
+    }
     debug!("Master Reset");
   }
   
@@ -183,15 +275,226 @@ impl DMA {
       2 => TransferType::ReadFromMemory,
       _ => {error!("Invalid Transfer type 3 chosen."); TransferType::SelfTest},
     };
-    //TODO 0b1_0000 = AUTO
-    //TODO 0b10_0000 = DOWN
+    channel.auto_init = matches!(register & 0b1_0000, 0b1_0000);
+    channel.decrement = matches!(register & 0b10_0000, 0b10_0000);
     channel.transfer_mode = match (register & 0b1100_0000) >> 6 {
       0 => TransferMode::OnDemand,
       1 => TransferMode::SingleDMA,
       2 => TransferMode::BlockDMA,
       3 | _ => TransferMode::Cascade,
     };
-    debug!("Channel {} {:?} {:?}", channel_index, channel.transfer_type, channel.transfer_mode);
+    debug!("Channel {} {:?} {:?} auto: {} down: {}", channel_index, channel.transfer_type, channel.transfer_mode, channel.auto_init, channel.decrement);
   }
   
-}
\ No newline at end of file
+}
+
+impl Channel {
+  fn pending(&self) -> bool {
+    self.request || self.dreq
+  }
+
+  /// Move one byte. Returns true at terminal count.
+  fn transfer(&mut self, channel_index: u8, memory: &mut Memory) -> bool {
+    let addr = ((self.page as usize) << 16) | self.address as usize;
+    match self.transfer_type {
+      TransferType::SelfTest => {},
+      TransferType::WriteToMemory => {
+        let value = match &mut self.device {
+          Some(device) => device.read(),
+          None => 0xFF, //Nothing is driving the bus.
+        };
+        memory.write_byte(addr, value);
+      },
+      TransferType::ReadFromMemory => {
+        let value = memory.read_byte(addr);
+        if let Some(device) = &mut self.device {
+          device.write(value);
+        }
+      },
+    }
+    if let Some(device) = &self.device {
+      self.dreq = device.dreq();
+    }
+    //The address wraps within the 64KB page. The page register is not incremented.
+    self.address = if self.decrement { self.address.wrapping_sub(1) } else { self.address.wrapping_add(1) };
+    //The transfer ends when the count rolls over from 0 to FFFF.
+    self.count = self.count.wrapping_sub(1);
+    if self.count != 0xFFFF {
+      return false;
+    }
+    trace!("Channel {} terminal count", channel_index);
+    self.terminal_count = true;
+    self.request = false;
+    if let Some(device) = &mut self.device {
+      device.terminal_count();
+    }
+    if self.auto_init {
+      self.address = self.base_address;
+      self.count = self.base_count;
+    } else {
+      self.mask = true;
+    }
+    true
+  }
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::chips::memory1mb;
+  use std::cell::RefCell;
+  use std::collections::VecDeque;
+  use std::rc::Rc;
+
+  //Mode register: bits 6-7 transfer mode, bit 5 decrement, bit 4 auto-initialize, bits 2-3 transfer type, bits 0-1 channel.
+  const DEMAND: u8 = 0b0000_0000;
+  const SINGLE: u8 = 0b0100_0000;
+  const BLOCK: u8 = 0b1000_0000;
+  const DECREMENT: u8 = 0b0010_0000;
+  const AUTO_INIT: u8 = 0b0001_0000;
+  const VERIFY: u8 = 0b0000_0000;
+  const WRITE_TO_MEMORY: u8 = 0b0000_0100;
+  const READ_FROM_MEMORY: u8 = 0b0000_1000;
+  const SET_REQUEST: u8 = 0b0000_0100;
+
+  #[derive(Default)]
+  struct Bus {
+    to_send: VecDeque<u8>,
+    received: Vec<u8>,
+    wanted: usize,
+    terminal_counts: usize,
+  }
+
+  /// Sends bytes until it runs out, or receives until it has as many as it wants.
+  struct TestDevice(Rc<RefCell<Bus>>);
+
+  impl DMADevice for TestDevice {
+    fn read(&mut self) -> u8 {
+      self.0.borrow_mut().to_send.pop_front().expect("DACK with nothing to send")
+    }
+    fn write(&mut self, value: u8) {
+      self.0.borrow_mut().received.push(value);
+    }
+    fn dreq(&self) -> bool {
+      let bus = self.0.borrow();
+      !bus.to_send.is_empty() || bus.received.len() < bus.wanted
+    }
+    fn terminal_count(&mut self) {
+      self.0.borrow_mut().terminal_counts += 1;
+    }
+  }
+
+  fn memory() -> Memory {
+    memory1mb::start(&[0; 0x1_0000], 640)
+  }
+
+  /// Reset, unmask, and program one channel.
+  fn dma(channel_index: u8, mode: u8, page: u8, address: u16, count: u16) -> DMA {
+    let mut dma = start();
+    dma.reset_master();
+    dma.reset_mask();
+    dma.set_mode(mode | channel_index);
+    dma.set_page(channel_index, page);
+    dma.set_address(channel_index, address as u8);
+    dma.set_address(channel_index, (address >> 8) as u8);
+    dma.set_count(channel_index, count as u8);
+    dma.set_count(channel_index, (count >> 8) as u8);
+    dma
+  }
+
+  fn connect(dma: &mut DMA, channel_index: u8, bus: Bus) -> Rc<RefCell<Bus>> {
+    let bus = Rc::new(RefCell::new(bus));
+    dma.connect(channel_index, Box::new(TestDevice(bus.clone())));
+    bus
+  }
+
+  fn read_word(dma: &mut DMA, channel_index: u8) -> (u16, u16) {
+    let address = dma.get_address(channel_index) as u16 | (dma.get_address(channel_index) as u16) << 8;
+    let count = dma.get_count(channel_index) as u16 | (dma.get_count(channel_index) as u16) << 8;
+    (address, count)
+  }
+
+  #[test]
+  fn single_mode_write_to_memory() {
+    let mut memory = memory();
+    let mut dma = dma(1, SINGLE | WRITE_TO_MEMORY, 0x2, 0x1000, 3);
+    let bus = connect(&mut dma, 1, Bus { to_send: VecDeque::from([1, 2, 3, 4]), ..Default::default() });
+    dma.set_dreq(1, true);
+    for _ in 0..4 {
+      assert_eq!(dma.service(&mut memory), 1, "One byte per request.");
+    }
+    assert_eq!((0x2_1000..0x2_1004).map(|addr| memory.read_byte(addr)).collect::<Vec<_>>(), [1, 2, 3, 4], "The page register supplies address bits 16-19.");
+    assert_eq!(bus.borrow().terminal_counts, 1);
+    assert_eq!(dma.get_status(), 0b0010, "Terminal count on channel 1, and DREQ dropped.");
+    assert_eq!(dma.get_status(), 0, "Reading the status clears the terminal count bits.");
+    assert!(!dma.has_request());
+    assert_eq!(read_word(&mut dma, 1), (0x1004, 0xFFFF));
+  }
+
+  #[test]
+  fn block_mode_read_from_memory_decrementing() {
+    let mut memory = memory();
+    for (offset, value) in [10, 20, 30].into_iter().enumerate() {
+      memory.write_byte(0x3_0100 + offset, value);
+    }
+    let mut dma = dma(2, BLOCK | DECREMENT | READ_FROM_MEMORY, 0x3, 0x0102, 2);
+    let bus = connect(&mut dma, 2, Bus { wanted: 3, ..Default::default() });
+    dma.set_dreq(2, true);
+    assert_eq!(dma.service(&mut memory), 3, "Block mode runs to terminal count in one go.");
+    assert_eq!(bus.borrow().received, [30, 20, 10]);
+    assert_eq!(bus.borrow().terminal_counts, 1);
+    assert_eq!(read_word(&mut dma, 2), (0x00FF, 0xFFFF));
+  }
+
+  #[test]
+  fn demand_mode_stops_when_dreq_drops() {
+    let mut memory = memory();
+    let mut dma = dma(3, DEMAND | WRITE_TO_MEMORY, 0x0, 0x0500, 9);
+    let bus = connect(&mut dma, 3, Bus { to_send: VecDeque::from([7, 8, 9]), ..Default::default() });
+    dma.set_dreq(3, true);
+    assert_eq!(dma.service(&mut memory), 3);
+    assert_eq!(bus.borrow().terminal_counts, 0);
+    assert_eq!(dma.get_status(), 0);
+    assert_eq!(read_word(&mut dma, 3), (0x0503, 6));
+  }
+
+  #[test]
+  fn auto_initialize() {
+    let mut memory = memory();
+    let mut dma = dma(1, SINGLE | AUTO_INIT | WRITE_TO_MEMORY, 0x1, 0x0000, 1);
+    let bus = connect(&mut dma, 1, Bus { to_send: VecDeque::from([1, 2, 3, 4, 5, 6]), ..Default::default() });
+    dma.set_dreq(1, true);
+    for _ in 0..6 {
+      dma.service(&mut memory);
+    }
+    assert_eq!(bus.borrow().terminal_counts, 3);
+    assert_eq!([memory.read_byte(0x1_0000), memory.read_byte(0x1_0001), memory.read_byte(0x1_0002)], [5, 6, 0], "The address went back to the start after each terminal count.");
+    assert_eq!(read_word(&mut dma, 1), (0x0000, 1));
+    assert_eq!(dma.get_status(), 0b0010, "Still unmasked, but DREQ dropped.");
+  }
+
+  #[test]
+  fn software_request_cleared_at_terminal_count() {
+    let mut memory = memory();
+    let mut dma = dma(1, BLOCK | AUTO_INIT | VERIFY, 0x0, 0x0000, 4);
+    dma.set_request(SET_REQUEST | 1);
+    assert_eq!(dma.get_status(), 0b10_0000);
+    assert_eq!(dma.service(&mut memory), 5);
+    assert_eq!(dma.get_status(), 0b0010, "The request bit is cleared, even though auto-initialize leaves the channel unmasked.");
+    assert!(!dma.has_request());
+    assert_eq!(dma.service(&mut memory), 0);
+  }
+
+  #[test]
+  fn masked_channel_waits() {
+    let mut memory = memory();
+    let mut dma = dma(1, SINGLE | WRITE_TO_MEMORY, 0x0, 0x0000, 0);
+    let bus = connect(&mut dma, 1, Bus { to_send: VecDeque::from([1]), ..Default::default() });
+    dma.set_mask(0b100 | 1);
+    dma.set_dreq(1, true);
+    assert!(!dma.has_request());
+    assert_eq!(dma.service(&mut memory), 0);
+    dma.set_mask(1);
+    assert_eq!(dma.service(&mut memory), 1);
+    assert_eq!(bus.borrow().terminal_counts, 1, "A count of 0 is one byte.");
+  }
+}
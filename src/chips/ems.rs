@@ -0,0 +1,278 @@
+//LIM EMS 4.0 Expanded Memory Board, in the style of the Intel Above Board.
+//http://www.phatcode.net/res/218/files/limems40.txt
+//
+//Each board holds up to 2MB in 16KB logical pages. Bigger configurations install several boards,
+//each answering on its own I/O base (258, 268, 2A8, 2B8), just like stacking real Above Boards.
+//The four 16KB physical pages of the 64KB page frame are controlled by the page registers at
+//base + 0000, base + 4000, base + 8000 and base + C000.
+//Page register: bit 7 = map enabled, bits 0-6 = logical page on this board.
+//
+//base + 1: Status. Reads how many 16KB logical pages are installed on the board, so a driver can find the board and its size.
+//base + 2: Configuration. Bits 0-1: board number. Bit 2: page frame at E0000 instead of D0000. These are jumpers, so read only.
+//Bit 7: board enabled. When clear, the board's pages are left out of the page frame.
+
+use super::memory1mb::MemoryDevice;
+
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use log::debug;
+
+const PAGE_SIZE: usize = 0x4000;
+const PAGES_PER_BOARD: usize = 128;
+const BOARD_PORTS: [u16; 4] = [0x258, 0x268, 0x2A8, 0x2B8];
+const STATUS: u16 = 1;
+const CONFIGURATION: u16 = 2;
+
+struct Board {
+  storage: Vec<u8>,
+  registers: [[u8; 4]; BOARD_PORTS.len()],  //Per board, per physical page.
+  enabled: [bool; BOARD_PORTS.len()],
+  mapping: [Option<usize>; 4],  //Logical page in storage which each physical page points at.
+}
+
+pub struct Ems {
+  board: Arc<Mutex<Board>>,
+  num_of_boards: usize,
+  frame: usize,
+}
+
+/// The 64KB window at D0000 or E0000 which is installed into the memory map.
+pub struct PageFrame {
+  board: Arc<Mutex<Board>>,
+  frame: usize,
+}
+
+pub fn start(size_kb: usize, frame: usize) -> io::Result<Ems> {
+  let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+  if size_kb == 0 || size_kb > 8192 || !size_kb.is_multiple_of(16) {
+    return invalid(format!("Unsupported EMS size: {}KB. It must be a multiple of 16KB, up to 8MB.", size_kb));
+  }
+  if frame != 0xD_0000 && frame != 0xE_0000 {
+    return invalid(format!("Unsupported EMS page frame: {:05X}. It must be D0000 or E0000.", frame));
+  }
+  let num_of_pages = size_kb * 1024 / PAGE_SIZE;
+  let num_of_boards = num_of_pages.div_ceil(PAGES_PER_BOARD);
+  debug!("EMS {}KB on {} board(s), page frame at {:05X}", size_kb, num_of_boards, frame);
+  Ok(Ems {
+    board: Arc::new(Mutex::new(Board {
+      storage: vec![0u8; num_of_pages * PAGE_SIZE],
+      registers: [[0; 4]; BOARD_PORTS.len()],
+      enabled: [true; BOARD_PORTS.len()],
+      mapping: [None; 4],
+    })),
+    num_of_boards,
+    frame,
+  })
+}
+
+impl Ems {
+  pub fn page_frame(&self) -> (usize, PageFrame) {
+    (self.frame, PageFrame {
+      board: Arc::clone(&self.board),
+      frame: self.frame,
+    })
+  }
+
+  /// Returns (board index, physical page) if the port belongs to one of our page registers.
+  fn decode_port(&self, port: u16) -> Option<(usize, usize)> {
+    let physical_page = (port >> 14) as usize;
+    let board_index = BOARD_PORTS.iter().position(|base| *base == port & 0x3FFF)?;
+    if board_index < self.num_of_boards {
+      Some((board_index, physical_page))
+    } else {
+      None
+    }
+  }
+
+  /// Returns (board index, register) for the status and configuration ports, which have no aliases.
+  fn decode_control_port(&self, port: u16) -> Option<(usize, u16)> {
+    let board_index = BOARD_PORTS.iter().position(|base| (STATUS..=CONFIGURATION).contains(&port.wrapping_sub(*base)))?;
+    if board_index < self.num_of_boards {
+      Some((board_index, port - BOARD_PORTS[board_index]))
+    } else {
+      None
+    }
+  }
+
+  pub fn handles_port(&self, port: u16) -> bool {
+    self.decode_port(port).is_some() || self.decode_control_port(port).is_some()
+  }
+
+  pub fn out_byte(&mut self, port: u16, value: u8) {
+    match self.decode_control_port(port) {
+      Some((board_index, CONFIGURATION)) => {
+        let mut board = self.board.lock().unwrap();
+        board.enabled[board_index] = matches!(value & 0b1000_0000, 0b1000_0000);
+        debug!("EMS board {} enabled: {}", board_index, board.enabled[board_index]);
+      },
+      Some(_) => debug!("EMS status port {:X} got {:X}. It is read only.", port, value),
+      None => self.set_page_register(port, value),
+    }
+  }
+
+  pub fn in_byte(&self, port: u16) -> u8 {
+    match self.decode_control_port(port) {
+      Some((board_index, STATUS)) => self.pages_on_board(board_index) as u8,
+      Some((board_index, _)) => {
+        let mut result = board_index as u8;
+        if self.frame == 0xE_0000 { result |= 0b100 }
+        if self.board.lock().unwrap().enabled[board_index] { result |= 0b1000_0000 }
+        result
+      },
+      None => self.get_page_register(port),
+    }
+  }
+
+  /// 1 to 128. Only the last board can be partly filled.
+  fn pages_on_board(&self, board_index: usize) -> usize {
+    let num_of_pages = self.board.lock().unwrap().storage.len() / PAGE_SIZE;
+    (num_of_pages - board_index * PAGES_PER_BOARD).min(PAGES_PER_BOARD)
+  }
+
+  fn set_page_register(&mut self, port: u16, value: u8) {
+    let (board_index, physical_page) = self.decode_port(port).unwrap();
+    let mut board = self.board.lock().unwrap();
+    board.registers[board_index][physical_page] = value;
+
+    let first_page = board_index * PAGES_PER_BOARD;
+    let logical_page = first_page + (value & 0x7F) as usize;
+    if value & 0b1000_0000 != 0 && logical_page * PAGE_SIZE < board.storage.len() {
+      board.mapping[physical_page] = Some(logical_page);
+    } else if matches!(board.mapping[physical_page], Some(page) if (first_page..first_page + PAGES_PER_BOARD).contains(&page)) {
+      //Only unmap if this board was the one mapped in.
+      board.mapping[physical_page] = None;
+    }
+    debug!("EMS board {} physical page {} -> {:?}", board_index, physical_page, board.mapping[physical_page]);
+  }
+
+  fn get_page_register(&self, port: u16) -> u8 {
+    let (board_index, physical_page) = self.decode_port(port).unwrap();
+    let board = self.board.lock().unwrap();
+    board.registers[board_index][physical_page]
+  }
+}
+
+impl PageFrame {
+  fn storage_index(board: &Board, frame_offset: usize) -> Option<usize> {
+    let logical_page = board.mapping[frame_offset / PAGE_SIZE]?;
+    if !board.enabled[logical_page / PAGES_PER_BOARD] {
+      return None;
+    }
+    Some(logical_page * PAGE_SIZE + frame_offset % PAGE_SIZE)
+  }
+}
+
+impl MemoryDevice for PageFrame {
+  fn read_byte(&mut self, addr: usize) -> u8 {
+    let board = self.board.lock().unwrap();
+    match PageFrame::storage_index(&board, addr - self.frame) {
+      Some(index) => board.storage[index],
+      None => 0xFF,
+    }
+  }
+
+  fn write_byte(&mut self, addr: usize, value: u8) {
+    let mut board = self.board.lock().unwrap();
+    if let Some(index) = PageFrame::storage_index(&board, addr - self.frame) {
+      board.storage[index] = value;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const FRAME: usize = 0xD_0000;
+
+  fn ems_with_frame(size_kb: usize) -> (Ems, PageFrame) {
+    let ems = start(size_kb, FRAME).unwrap();
+    let (_, page_frame) = ems.page_frame();
+    (ems, page_frame)
+  }
+
+  #[test]
+  fn bad_sizes_and_frames_are_errors() {
+    assert!(start(0, FRAME).is_err());
+    assert!(start(24, FRAME).is_err());
+    assert!(start(8192 + 16, FRAME).is_err());
+    assert!(start(64, 0xC_0000).is_err());
+    assert!(start(8192, 0xE_0000).is_ok());
+  }
+
+  #[test]
+  fn page_registers_map_16kb_pages_into_the_frame() {
+    let (mut ems, mut page_frame) = ems_with_frame(256);
+    ems.out_byte(0x258, 0x80 | 3);  //Physical page 0
+    ems.out_byte(0x4258, 0x80 | 5);  //Physical page 1
+    page_frame.write_byte(FRAME + 1, 0xAA);
+    page_frame.write_byte(FRAME + 0x4000, 0xBB);
+    assert_eq!(ems.in_byte(0x4258), 0x85);
+
+    ems.out_byte(0xC258, 0x80 | 3);  //Physical page 3 shows the same logical page as physical page 0.
+    assert_eq!(page_frame.read_byte(FRAME + 0xC001), 0xAA);
+    ems.out_byte(0x258, 0x80 | 5);
+    assert_eq!(page_frame.read_byte(FRAME), 0xBB);
+  }
+
+  #[test]
+  fn unmapped_pages_read_ff_and_ignore_writes() {
+    let (mut ems, mut page_frame) = ems_with_frame(64);
+    assert_eq!(page_frame.read_byte(FRAME), 0xFF);
+
+    ems.out_byte(0x258, 0x80);
+    page_frame.write_byte(FRAME, 0x12);
+    ems.out_byte(0x258, 0);
+    assert_eq!(page_frame.read_byte(FRAME), 0xFF);
+    page_frame.write_byte(FRAME, 0x34);
+
+    ems.out_byte(0x258, 0x80);
+    assert_eq!(page_frame.read_byte(FRAME), 0x12);
+  }
+
+  #[test]
+  fn pages_past_the_installed_memory_stay_unmapped() {
+    let (mut ems, mut page_frame) = ems_with_frame(64);  //4 logical pages
+    ems.out_byte(0x258, 0x80 | 4);
+    assert_eq!(page_frame.read_byte(FRAME), 0xFF);
+  }
+
+  #[test]
+  fn status_and_configuration_ports() {
+    let ems = start(2560, 0xE_0000).unwrap();  //A full board and a quarter full one.
+    assert_eq!(ems.in_byte(0x259), 128);
+    assert_eq!(ems.in_byte(0x269), 32);
+    assert_eq!(ems.in_byte(0x25A), 0b1000_0100);
+    assert_eq!(ems.in_byte(0x26A), 0b1000_0101);
+    assert!(ems.handles_port(0x4268));
+    assert!(!ems.handles_port(0x2A8));
+    assert!(!ems.handles_port(0x2A9));
+  }
+
+  #[test]
+  fn each_board_maps_its_own_pages() {
+    let (mut ems, mut page_frame) = ems_with_frame(4096);
+    ems.out_byte(0x268, 0x80);  //Board 1 page 0, logical page 128.
+    page_frame.write_byte(FRAME, 0x11);
+    ems.out_byte(0x258, 0x80);  //Board 0 page 0 takes over physical page 0.
+    page_frame.write_byte(FRAME, 0x22);
+
+    ems.out_byte(0x268, 0);  //Board 1 unmapping doesn't touch board 0's page.
+    assert_eq!(page_frame.read_byte(FRAME), 0x22);
+    ems.out_byte(0x268, 0x80);
+    assert_eq!(page_frame.read_byte(FRAME), 0x11);
+  }
+
+  #[test]
+  fn disabled_boards_drop_out_of_the_frame() {
+    let (mut ems, mut page_frame) = ems_with_frame(4096);
+    ems.out_byte(0x268, 0x80);
+    page_frame.write_byte(FRAME, 0x11);
+    ems.out_byte(0x26A, 0);
+    assert_eq!(ems.in_byte(0x26A), 0b0000_0001);
+    assert_eq!(page_frame.read_byte(FRAME), 0xFF);
+    ems.out_byte(0x26A, 0x80);
+    assert_eq!(page_frame.read_byte(FRAME), 0x11);
+  }
+}
@@ -4,14 +4,25 @@
 use log::debug;
 
 //Configuration Register
-#[derive(Debug, Default)]
-enum CPUSpeed {
+#[derive(Debug, Default, Clone, Copy)]
+pub enum CPUSpeed {
   #[default]
   MHz477,
   MHz715,
   MHz954,
 }
 
+impl CPUSpeed {
+  /// Length of a CPU clock, in half cycles of the 14.31818 MHz crystal.
+  pub fn half_cycles(&self) -> u64 {
+    match self {
+      CPUSpeed::MHz477 => 6,
+      CPUSpeed::MHz715 => 4,
+      CPUSpeed::MHz954 => 3,
+    }
+  }
+}
+
 //Control Register
 #[derive(Debug, Default)]
 struct Enable {
@@ -20,6 +31,7 @@ struct Enable {
   parity_check: bool,
   io_check: bool,
   keyboard_clock: bool,
+  keyboard_clear: bool,  //Holds the keyboard data register empty.
   nmi: bool,
   nmi_8087: bool,
   lock_register: bool,  //I am saving this, but not locking anything.
@@ -43,10 +55,22 @@ enum MemorySize {
   K640, K512, K256,
 }
 
+/// Switches 5 and 6 tell the BIOS which display to start up on.
+/// With both an MDA and a CGA installed, this picks the primary one.
+#[derive(Debug, Default, Clone, Copy)]
+pub enum InitialVideo {
+  #[default]
+  Ega,  //Or none. The adapter's own ROM sets itself up.
+  Cga40x25,
+  Cga80x25,
+  Mda,
+}
+
 //Switch Register
 #[derive(Debug, Default)]
 struct Switches {
   installed_8087: bool,
+  initial_video: InitialVideo,
   memory_size: MemorySize,
   num_of_floppies: NumOfFloppies,
   switch_select: SwitchSelect,
@@ -66,17 +90,27 @@ pub struct PPI {
   switches: Switches,
   errors: Errors,
   keyboard_character: u8,
+  keyboard_full: bool,  //A scancode is waiting to be read. The keyboard holds on to the next one until it is cleared.
 }
 
-pub fn start() -> PPI {
-  Default::default()
+/// The memory size switches can only express 256K, 512K or 640K, so they are set to the nearest one.
+/// The BIOS counts the memory that is really there. Anything above 640K is upper memory which it doesn't count.
+pub fn start(ram_kb: usize) -> PPI {
+  let mut ppi: PPI = Default::default();
+  ppi.switches.memory_size = match ram_kb {
+    0..=384 => MemorySize::K256,
+    385..=576 => MemorySize::K512,
+    _ => MemorySize::K640,
+  };
+  ppi
 }
 
 impl PPI {
   pub fn set_configuration(&mut self, value: u8) {
     self.enable.parity_check = matches!(value & 0b1, 0);
     self.enable.nmi_8087 = matches!(value & 0b10, 0b10);
-    self.switches.memory_size = match ((value >> 2) & 1, (value >> 4) & 1) {
+    //The switches reflect the RAM actually installed, so they are not overwritten here.
+    let memory_size = match ((value >> 2) & 1, (value >> 4) & 1) {
       (0, 0) => MemorySize::K640,
       (0, 1) => MemorySize::K512,
       (1, 0) => MemorySize::K256,
@@ -87,15 +121,45 @@ impl PPI {
       2 | 3 => CPUSpeed::MHz715,
       _     => CPUSpeed::MHz954,
     };
-    debug!("parity_check enabled: {}, 8087 NMI Enabled: {}, Memory Size: {:?}, CPU Speed: {:?}",
-    self.enable.parity_check, self.enable.nmi_8087, self.switches.memory_size, self.switches.cpu_speed);
+    debug!("parity_check enabled: {}, 8087 NMI Enabled: {}, Memory Size: {:?} (installed {:?}), CPU Speed: {:?}",
+    self.enable.parity_check, self.enable.nmi_8087, memory_size, self.switches.memory_size, self.switches.cpu_speed);
   }
   
+  pub fn get_cpu_speed(&self) -> CPUSpeed {
+    self.switches.cpu_speed
+  }
+
+  pub fn set_initial_video(&mut self, video: InitialVideo) {
+    self.switches.initial_video = video;
+  }
+
   pub fn set_nmi(&mut self, value: u8) {
     self.enable.nmi = matches!(value & 0b1000_0000, 0b1000_0000);
     if self.enable.nmi { debug!("NMI Enabled"); } else { debug!("NMI Disabled"); }
   }
   
+  /// A RAM read failed its parity check. Returns true if this should raise an NMI.
+  /// Port B bit 4 holds the parity latch in reset while it is set, so nothing is latched then.
+  pub fn parity_error(&mut self) -> bool {
+    if !self.enable.parity_check {
+      return false;
+    }
+    self.errors.parity_check = true;
+    debug!("Parity check latched, NMI: {}", self.enable.nmi);
+    self.enable.nmi
+  }
+
+  /// A device asserted I/O CHCK. Returns true if this should raise an NMI.
+  /// Port B bit 5 holds the I/O check latch in reset while it is set, so nothing is latched then.
+  pub fn io_check(&mut self) -> bool {
+    if !self.enable.io_check {
+      return false;
+    }
+    self.errors.io_check = true;
+    debug!("I/O check latched, NMI: {}", self.enable.nmi);
+    self.enable.nmi
+  }
+
   pub fn write_port_a(&self, value: u8) {
     debug!("Port A received {:X}", value);
   }
@@ -104,12 +168,17 @@ impl PPI {
   pub fn write_port_b(&mut self, value: u8) {
     self.enable.timer_2 = matches!(value & 0b1, 0b1);
     self.enable.speaker = matches!(value & 0b10, 0b10);
-    self.switches.switch_select = if matches!(value & 0b100, 0b100) { SwitchSelect::S1 } else { SwitchSelect::S0 }; //NOTE - In XT this is 0b100. In PC this is 0b1000.
+    self.switches.switch_select = if matches!(value & 0b1000, 0b1000) { SwitchSelect::S1 } else { SwitchSelect::S0 }; //NOTE - In XT this is 0b1000. In PC this is 0b100.
     self.enable.parity_check = matches!(value & 0b1_0000, 0); //Note it is reversed here.
     self.enable.io_check = matches!(value & 0b10_0000, 0); //Note it is reversed here.
+    //Disabling a check clears its latch. This is how the NMI handler acknowledges the error.
+    if !self.enable.parity_check { self.errors.parity_check = false; }
+    if !self.enable.io_check { self.errors.io_check = false; }
     self.enable.keyboard_clock = matches!(value & 0b100_0000, 0b100_0000);
-    if matches!(value & 0b1000_0000, 0b1000_0000) { //Clear Keyboard Data Register
+    self.enable.keyboard_clear = matches!(value & 0b1000_0000, 0b1000_0000);
+    if self.enable.keyboard_clear { //Clear Keyboard Data Register
       self.keyboard_character = 0;
+      self.keyboard_full = false;
     }
     debug!("Write {:?}", self.enable);
   }
@@ -120,27 +189,66 @@ impl PPI {
     let mut result = 0;
     if self.enable.timer_2 { result |= 0b1 };
     if self.enable.speaker { result |= 0b10 };
-    if let SwitchSelect::S1 = self.switches.switch_select { //NOTE - In XT this is 0b100. In PC this is 0b1000.
-      result |= 0b100;
+    if let SwitchSelect::S1 = self.switches.switch_select { //NOTE - In XT this is 0b1000. In PC this is 0b100.
+      result |= 0b1000;
     }
     if !self.enable.parity_check { result |= 0b1_0000 }; //Note it is reversed here.
     if !self.enable.io_check { result |= 0b10_0000 }; //Note it is reversed here.
     if self.enable.keyboard_clock { result |= 0b100_0000 };
+    if self.enable.keyboard_clear { result |= 0b1000_0000 };
     debug!("Read {:?}", self.enable);
     result
   }
-  pub fn read_port_c(&self) -> u8 {
+  /// Port B bit 6 drives the keyboard clock line. Holding it low resets the keyboard.
+  pub fn get_keyboard_clock(&self) -> bool {
+    self.enable.keyboard_clock
+  }
+
+  /// Port B bit 7 clears the keyboard data register, which also drops IRQ1.
+  pub fn get_keyboard_clear(&self) -> bool {
+    self.enable.keyboard_clear
+  }
+
+  /// Whether the keyboard may clock in another scancode.
+  pub fn keyboard_ready(&self) -> bool {
+    self.enable.keyboard_clock && !self.enable.keyboard_clear && !self.keyboard_full
+  }
+
+  /// The keyboard finished clocking in a scancode. The motherboard raises IRQ1.
+  pub fn receive_scancode(&mut self, scancode: u8) {
+    self.keyboard_character = scancode;
+    self.keyboard_full = true;
+  }
+
+  /// Port B bit 0 drives PIT counter 2's GATE.
+  pub fn get_timer_2_gate(&self) -> bool {
+    self.enable.timer_2
+  }
+
+  /// Port B bit 1 is ANDed with PIT counter 2's OUT to drive the speaker.
+  pub fn get_speaker_enable(&self) -> bool {
+    self.enable.speaker
+  }
+
+  pub fn read_port_c(&self, timer_2_output: bool) -> u8 {
     let mut result = 0;
     match self.switches.switch_select {
-      SwitchSelect::S0 => {
+      SwitchSelect::S1 => { //Switches 5-8. The BIOS rotates these into the high nibble of the equipment flags.
         result |= (match self.switches.num_of_floppies {
           NumOfFloppies::N0 => 0,
           NumOfFloppies::N1 => 1,
           NumOfFloppies::N2 => 2,
           NumOfFloppies::N3 => 3,
         } << 2);
+        result |= match self.switches.initial_video {
+          InitialVideo::Ega => 0b00,
+          InitialVideo::Cga40x25 => 0b01,
+          InitialVideo::Cga80x25 => 0b10,
+          InitialVideo::Mda => 0b11,
+        };
       },
-      SwitchSelect::S1 => {
+      SwitchSelect::S0 => { //Switches 1-4
+        result |= 0b1;  //Switch 1 off for a normal POST. On, the BIOS loops the POST forever, for burn in.
         if self.switches.installed_8087 { result |= 0b10 }
         result |= (match self.switches.memory_size {
           MemorySize::K640 => 0b00,
@@ -149,11 +257,91 @@ impl PPI {
         } << 2);
       }
     }
-    //TODO - Timer 2 output
+    if timer_2_output { result |= 0b10_0000 }
     if self.errors.io_check { result |= 0b100_0000 }
     if self.errors.parity_check { result |= 0b1000_0000 }
-    debug!("Num of Floppies: {:?}, Memory Size: {:?}, CPU Speed: {:?}, 8087 installed: {}, IO Check: {}, Parity Check: {}",
-    self.switches.num_of_floppies, self.switches.memory_size, self.switches.cpu_speed, self.switches.installed_8087, self.errors.io_check, self.errors.parity_check);
+    debug!("Num of Floppies: {:?}, Initial Video: {:?}, Memory Size: {:?}, CPU Speed: {:?}, 8087 installed: {}, Timer 2 output: {}, IO Check: {}, Parity Check: {}",
+    self.switches.num_of_floppies, self.switches.initial_video, self.switches.memory_size, self.switches.cpu_speed, self.switches.installed_8087, timer_2_output, self.errors.io_check, self.errors.parity_check);
     result
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ENABLE_NMI: u8 = 0x80;
+  const DISABLE_NMI: u8 = 0x00;
+  const CHECKS_ENABLED: u8 = 0b0000_0000;
+  const CHECKS_DISABLED: u8 = 0b0011_0000;  //Port B bits 4 and 5 hold the parity and I/O check latches in reset.
+
+  fn port_c_errors(ppi: &PPI) -> u8 {
+    ppi.read_port_c(false) & 0b1100_0000
+  }
+
+  #[test]
+  fn memory_switches_are_set_to_the_nearest_size() {
+    let memory_switches = |ram_kb| {
+      let mut ppi = start(ram_kb);
+      ppi.write_port_b(0);  //Switches 1-4
+      (ppi.read_port_c(false) >> 2) & 0b11
+    };
+    assert_eq!(memory_switches(64), 0b01);
+    assert_eq!(memory_switches(256), 0b01);
+    assert_eq!(memory_switches(384), 0b01);
+    assert_eq!(memory_switches(448), 0b10);
+    assert_eq!(memory_switches(576), 0b10);
+    assert_eq!(memory_switches(640), 0b00);
+    assert_eq!(memory_switches(736), 0b00);
+  }
+
+  #[test]
+  fn post_runs_once() {
+    let mut ppi = start(640);
+    ppi.write_port_b(0);
+    assert_eq!(ppi.read_port_c(false) & 0b1, 0b1, "Switch 1 on loops the POST.");
+  }
+
+  #[test]
+  fn parity_error_raises_nmi_only_when_unmasked() {
+    let mut ppi = start(640);
+    ppi.write_port_b(CHECKS_ENABLED);
+    ppi.set_nmi(DISABLE_NMI);
+    assert!(!ppi.parity_error());
+    assert_eq!(port_c_errors(&ppi), 0b1000_0000, "The error is latched even with NMI masked.");
+
+    ppi.set_nmi(ENABLE_NMI);
+    assert!(ppi.parity_error());
+  }
+
+  #[test]
+  fn io_check_raises_nmi_only_when_unmasked() {
+    let mut ppi = start(640);
+    ppi.write_port_b(CHECKS_ENABLED);
+    ppi.set_nmi(DISABLE_NMI);
+    assert!(!ppi.io_check());
+    ppi.set_nmi(ENABLE_NMI);
+    assert!(ppi.io_check());
+    assert_eq!(port_c_errors(&ppi), 0b0100_0000);
+  }
+
+  #[test]
+  fn disabled_checks_latch_nothing() {
+    let mut ppi = start(640);
+    ppi.set_nmi(ENABLE_NMI);
+    ppi.write_port_b(CHECKS_DISABLED);
+    assert!(!ppi.parity_error());
+    assert!(!ppi.io_check());
+    assert_eq!(port_c_errors(&ppi), 0);
+  }
+
+  #[test]
+  fn disabling_a_check_clears_its_latch() {
+    let mut ppi = start(640);
+    ppi.write_port_b(CHECKS_ENABLED);
+    ppi.parity_error();
+    ppi.io_check();
+    assert_eq!(port_c_errors(&ppi), 0b1100_0000);
+    ppi.write_port_b(CHECKS_DISABLED);
+    assert_eq!(port_c_errors(&ppi), 0);
+  }
+}
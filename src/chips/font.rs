@@ -0,0 +1,27 @@
+//Character generator ROM
+//The glyphs are read from the IBM VGA BIOS. It carries the CGA's 8x8 font,
+//and the 8x14 font with the alternate glyphs used for 9 pixel wide characters on the MDA.
+
+const VGA_ROM: &[u8] = include_bytes!("../../roms/ibm-vga-1986-10-27.rom");
+const FONT_8X8: usize = 0x378D;
+const FONT_8X14: usize = 0x3F8D;
+const FONT_9X14_ALTERNATES: usize = 0x4D8D;  //Entries of a character code followed by 14 rows. Ends with code 0.
+
+/// 8 rows. Bit 7 is the leftmost pixel.
+pub fn glyph_8x8(character: u8) -> &'static [u8] {
+  let start = FONT_8X8 + character as usize * 8;
+  &VGA_ROM[start..start + 8]
+}
+
+/// 14 rows of the 8 leftmost pixels. The 9th column is up to the adapter.
+pub fn glyph_9x14(character: u8) -> &'static [u8] {
+  let mut entry = FONT_9X14_ALTERNATES;
+  while VGA_ROM[entry] != 0 {
+    if VGA_ROM[entry] == character {
+      return &VGA_ROM[entry + 1..entry + 15];
+    }
+    entry += 15;
+  }
+  let start = FONT_8X14 + character as usize * 14;
+  &VGA_ROM[start..start + 14]
+}
@@ -0,0 +1,191 @@
+//An RGB image of the screen, for front ends and screenshots.
+//
+//Images are saved as binary PPM, or as PNG with uncompressed deflate blocks, so no image library is needed.
+//https://www.w3.org/TR/png/
+
+use std::io::{self, Write};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const STORED_BLOCK_SIZE: usize = 0xFFFF;  //The most a stored deflate block can hold.
+
+pub struct Framebuffer {
+  pub width: usize,
+  pub height: usize,
+  pub pixels: Vec<u8>,  //Red, green, blue. Row by row from the top left.
+}
+
+pub fn new(width: usize, height: usize) -> Framebuffer {
+  Framebuffer {
+    width,
+    height,
+    pixels: vec![0; width * height * 3],
+  }
+}
+
+impl Framebuffer {
+  pub fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 3]) {
+    debug_assert!(x < self.width && y < self.height, "Pixel {},{} is off the screen", x, y);
+    let index = (y * self.width + x) * 3;
+    self.pixels[index..index + 3].copy_from_slice(&color);
+  }
+
+  pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+    let index = (y * self.width + x) * 3;
+    [self.pixels[index], self.pixels[index + 1], self.pixels[index + 2]]
+  }
+
+  /// Binary PPM (P6).
+  pub fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+    out.write_all(&self.pixels)
+  }
+
+  /// 8 bit RGB PNG.
+  pub fn write_png(&self, out: &mut impl Write) -> io::Result<()> {
+    out.write_all(&PNG_SIGNATURE)?;
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(self.width as u32).to_be_bytes());
+    header.extend_from_slice(&(self.height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);  //Bit depth, truecolor, deflate, no filter method, not interlaced.
+    write_chunk(out, b"IHDR", &header)?;
+
+    //Each row starts with its filter type, which is 0 for none.
+    let mut rows = Vec::with_capacity((self.width * 3 + 1) * self.height);
+    for row in self.pixels.chunks(self.width * 3) {
+      rows.push(0);
+      rows.extend_from_slice(row);
+    }
+    write_chunk(out, b"IDAT", &zlib_stored(&rows))?;
+    write_chunk(out, b"IEND", &[])
+  }
+}
+
+/// Length, type, data, then a CRC of the type and data.
+fn write_chunk(out: &mut impl Write, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+  out.write_all(&(data.len() as u32).to_be_bytes())?;
+  out.write_all(chunk_type)?;
+  out.write_all(data)?;
+  let crc = crc32(crc32(0xFFFF_FFFF, chunk_type), data) ^ 0xFFFF_FFFF;
+  out.write_all(&crc.to_be_bytes())
+}
+
+/// A zlib stream of stored (uncompressed) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+  let mut result = vec![0x78, 0x01];  //Deflate with a 32KB window, no dictionary, and a check that makes it a multiple of 31.
+  let mut blocks = data.chunks(STORED_BLOCK_SIZE).peekable();
+  if blocks.peek().is_none() {
+    result.extend_from_slice(&[0b1, 0x00, 0x00, 0xFF, 0xFF]);
+  }
+  while let Some(block) = blocks.next() {
+    result.push(if blocks.peek().is_none() { 0b1 } else { 0b0 });  //Bit 0: Last block. Bits 1-2: 0 for stored.
+    let len = block.len() as u16;
+    result.extend_from_slice(&len.to_le_bytes());
+    result.extend_from_slice(&(!len).to_le_bytes());
+    result.extend_from_slice(block);
+  }
+  result.extend_from_slice(&adler32(data).to_be_bytes());
+  result
+}
+
+fn adler32(data: &[u8]) -> u32 {
+  let (mut a, mut b) = (1u32, 0u32);
+  for &byte in data {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  (b << 16) | a
+}
+
+fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+  for &byte in data {
+    crc ^= byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+    }
+  }
+  crc
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn crc32_known_answers() {
+    let crc = |data: &[u8]| crc32(0xFFFF_FFFF, data) ^ 0xFFFF_FFFF;
+    assert_eq!(crc(b""), 0);
+    assert_eq!(crc(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc(b"IEND"), 0xAE42_6082);
+    assert_eq!(crc32(crc32(0xFFFF_FFFF, b"1234"), b"56789") ^ 0xFFFF_FFFF, 0xCBF4_3926, "A CRC can be carried across calls.");
+  }
+
+  #[test]
+  fn adler32_known_answers() {
+    assert_eq!(adler32(b""), 1);
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    assert_eq!(adler32(&[0xFF; 6000]), 0xA497_59EA, "Sums wrap at 65521.");
+  }
+
+  //Reads back the PNGs write_png makes: checks every CRC, joins the stored deflate blocks, and checks the Adler-32.
+  fn decode_png(png: &[u8]) -> Framebuffer {
+    assert_eq!(png[..8], PNG_SIGNATURE);
+    let u32_at = |offset: usize| u32::from_be_bytes(png[offset..offset + 4].try_into().unwrap());
+    let (mut offset, mut size, mut zlib) = (8, (0, 0), Vec::new());
+    loop {
+      let len = u32_at(offset) as usize;
+      let chunk = &png[offset + 4..offset + 8 + len];
+      assert_eq!(crc32(0xFFFF_FFFF, chunk) ^ 0xFFFF_FFFF, u32_at(offset + 8 + len));
+      let data = &chunk[4..];
+      match &chunk[..4] {
+        b"IHDR" => {
+          size = (u32_at(offset + 8) as usize, u32_at(offset + 12) as usize);
+          assert_eq!(data[8..], [8, 2, 0, 0, 0]);
+        },
+        b"IDAT" => zlib.extend_from_slice(data),
+        b"IEND" => break,
+        other => panic!("Unexpected chunk {:?}", other),
+      }
+      offset += 12 + len;
+    }
+    assert_eq!(offset + 12, png.len());
+
+    assert_eq!((zlib[0] as u16 * 256 + zlib[1] as u16) % 31, 0, "Bad zlib header check");
+    let mut rows = Vec::new();
+    let mut block = 2;
+    loop {
+      let last = zlib[block] & 1 == 1;
+      assert_eq!(zlib[block] & 0b110, 0, "Not a stored block");
+      let len = u16::from_le_bytes([zlib[block + 1], zlib[block + 2]]);
+      assert_eq!(!len, u16::from_le_bytes([zlib[block + 3], zlib[block + 4]]));
+      rows.extend_from_slice(&zlib[block + 5..block + 5 + len as usize]);
+      block += 5 + len as usize;
+      if last { break; }
+    }
+    assert_eq!(zlib[block..], adler32(&rows).to_be_bytes());
+
+    let (width, height) = size;
+    let mut image = new(width, height);
+    for (y, row) in rows.chunks(width * 3 + 1).enumerate() {
+      assert_eq!(row[0], 0, "Filter type");
+      image.pixels[y * width * 3..(y + 1) * width * 3].copy_from_slice(&row[1..]);
+    }
+    image
+  }
+
+  #[test]
+  fn png_round_trip() {
+    //Big enough to need 2 stored blocks.
+    let mut image = new(200, 120);
+    for y in 0..image.height {
+      for x in 0..image.width {
+        image.set_pixel(x, y, [x as u8, y as u8, (x ^ y) as u8]);
+      }
+    }
+    let mut png = Vec::new();
+    image.write_png(&mut png).unwrap();
+    let decoded = decode_png(&png);
+    assert_eq!((decoded.width, decoded.height), (200, 120));
+    assert!(decoded.pixels == image.pixels);
+  }
+}
@@ -0,0 +1,273 @@
+//IBM PC/XT 83 key keyboard
+//https://www.seasip.info/VintagePC/ibm_1501105.html
+//http://www.mcamafia.de/pdf/ibm_hitrc11.pdf (Keyboard section)
+//
+//The keyboard talks scancode set 1: the make code when a key goes down, and make | 0x80 when it comes up.
+//Holding the clock line low resets it. Once the clock is released, it runs its self test and answers 0xAA.
+//It has a 16 code buffer. Codes are only sent when the PPI's shift register is empty.
+
+use std::collections::VecDeque;
+
+use crate::clock::MASTER_HZ;
+
+use log::{debug, trace};
+
+const BUFFER_SIZE: usize = 16;
+const OVERRUN: u8 = 0xFF;           //Replaces the 17th code when the buffer is full.
+const SELF_TEST_PASSED: u8 = 0xAA;
+const BREAK: u8 = 0x80;
+
+const RESET_TIME: u64 = MASTER_HZ / 200;      //The BIOS holds the clock low for 20ms. Accept 5ms, so turbo speeds still reset it.
+const SELF_TEST_TIME: u64 = MASTER_HZ / 1000;
+const BYTE_TIME: u64 = MASTER_HZ / 1000;      //Roughly how long one code takes to clock out.
+const TYPEMATIC_DELAY: u64 = MASTER_HZ / 2;   //0.5 seconds
+const TYPEMATIC_RATE: u64 = MASTER_HZ / 10;   //10 codes per second
+
+pub const LEFT_SHIFT: u8 = 0x2A;
+
+/// Injected codes wait for the BIOS to reset the keyboard and read the self test reply.
+/// Otherwise the reset would throw them away, or they would arrive where the BIOS expects 0xAA.
+#[derive(PartialEq)]
+enum ScriptHold {
+  Reset,      //Waiting for the self test.
+  Reply,      //The self test reply is at the front of the buffer.
+  ReplySent,  //The reply is in the PPI. Once the PPI is ready again, the BIOS has read it.
+  Released,
+}
+
+pub struct Keyboard {
+  buffer: VecDeque<u8>,
+  script: VecDeque<u8>,       //Injected codes, fed into the buffer as it drains.
+  script_hold: ScriptHold,
+  pressed: [bool; 0x80],
+  typematic: Option<(u8, u64)>, //The last key pressed repeats while it is held. (scancode, next repeat)
+  clock: bool,                //Clock line, driven by PPI port B bit 6.
+  clock_low_since: u64,
+  self_test_at: Option<u64>,
+  next_send: u64,
+}
+
+pub fn start() -> Keyboard {
+  Keyboard {
+    buffer: VecDeque::with_capacity(BUFFER_SIZE),
+    script: VecDeque::new(),
+    script_hold: ScriptHold::Reset,
+    pressed: [false; 0x80],
+    typematic: None,
+    clock: false, //The PPI holds the clock low at power on.
+    clock_low_since: 0,
+    self_test_at: None,
+    next_send: 0,
+  }
+}
+
+/// US layout. Returns the make code, and whether shift is needed.
+pub fn scancode_for_char(c: char) -> Option<(u8, bool)> {
+  const UNSHIFTED: &[u8; 0x3A] = b"\0\x1b1234567890-=\x08\tqwertyuiop[]\n\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+  const SHIFTED: &[u8; 0x3A] = b"\0\0!@#$%^&*()_+\0\0QWERTYUIOP{}\0\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0\0\0\0";
+  let c = if c == '\r' { '\n' } else { c };
+  if !c.is_ascii() || c == '\0' {
+    return None;
+  }
+  let byte = c as u8;
+  if let Some(scancode) = UNSHIFTED.iter().position(|&b| b == byte) {
+    return Some((scancode as u8, false));
+  }
+  SHIFTED.iter().position(|&b| b == byte).map(|scancode| (scancode as u8, true))
+}
+
+impl Keyboard {
+  /// PPI port B bit 6.
+  pub fn set_clock(&mut self, now: u64, clock: bool) {
+    if clock == self.clock {
+      return;
+    }
+    self.clock = clock;
+    if !clock {
+      self.clock_low_since = now;
+    } else if now - self.clock_low_since >= RESET_TIME {
+      debug!("Keyboard reset");
+      self.buffer.clear();
+      self.pressed = [false; 0x80];
+      self.typematic = None;
+      self.self_test_at = Some(now + SELF_TEST_TIME);
+      self.script_hold = ScriptHold::Reset;
+    }
+  }
+
+  pub fn key_down(&mut self, now: u64, scancode: u8) {
+    let scancode = scancode & !BREAK;
+    if self.pressed[scancode as usize] {
+      return;
+    }
+    self.pressed[scancode as usize] = true;
+    self.typematic = Some((scancode, now + TYPEMATIC_DELAY));
+    self.push(scancode);
+  }
+
+  pub fn key_up(&mut self, scancode: u8) {
+    let scancode = scancode & !BREAK;
+    if !self.pressed[scancode as usize] {
+      return;
+    }
+    self.pressed[scancode as usize] = false;
+    if matches!(self.typematic, Some((repeating, _)) if repeating == scancode) {
+      self.typematic = None;
+    }
+    self.push(scancode | BREAK);
+  }
+
+  /// Type out a string, pressing shift where needed. Characters with no key are skipped.
+  /// Unlike keys, injected codes never overrun the buffer. They wait until there is room.
+  pub fn type_string(&mut self, text: &str) {
+    for c in text.chars() {
+      let Some((scancode, shift)) = scancode_for_char(c) else {
+        debug!("Keyboard can't type {:?}", c);
+        continue;
+      };
+      if shift { self.script.push_back(LEFT_SHIFT); }
+      self.script.push_back(scancode);
+      self.script.push_back(scancode | BREAK);
+      if shift { self.script.push_back(LEFT_SHIFT | BREAK); }
+    }
+  }
+
+  fn push(&mut self, scancode: u8) {
+    if self.buffer.len() < BUFFER_SIZE {
+      self.buffer.push_back(scancode);
+    } else if self.buffer.len() == BUFFER_SIZE {
+      debug!("Keyboard buffer overrun");
+      self.buffer.push_back(OVERRUN);
+    }
+  }
+
+  /// Run the self test and typematic repeat up to now.
+  pub fn update(&mut self, now: u64) {
+    if self.self_test_at.is_some_and(|at| at <= now) {
+      self.self_test_at = None;
+      self.buffer.push_front(SELF_TEST_PASSED);  //The keyboard doesn't scan keys until the self test is done.
+      self.script_hold = ScriptHold::Reply;
+    }
+    while let Some((scancode, at)) = self.typematic {
+      if at > now { break; }
+      self.push(scancode);
+      self.typematic = Some((scancode, at + TYPEMATIC_RATE));
+    }
+    if self.script_hold == ScriptHold::Released {
+      self.feed_script();
+    }
+  }
+
+  fn feed_script(&mut self) {
+    while self.buffer.len() < BUFFER_SIZE {
+      let Some(scancode) = self.script.pop_front() else { break; };
+      self.buffer.push_back(scancode);
+    }
+  }
+
+  fn can_send(&self) -> bool {
+    let script_due = self.script_hold == ScriptHold::ReplySent && !self.script.is_empty();
+    self.clock && self.self_test_at.is_none() && (!self.buffer.is_empty() || script_due)
+  }
+
+  /// Clock out the next code. Only call this when the PPI is ready to receive it.
+  pub fn take(&mut self, now: u64) -> Option<u8> {
+    if !self.can_send() || now < self.next_send {
+      return None;
+    }
+    self.next_send = now + BYTE_TIME;
+    if self.script_hold == ScriptHold::ReplySent {
+      self.script_hold = ScriptHold::Released;
+      self.feed_script();
+    }
+    let scancode = self.buffer.pop_front();
+    if self.script_hold == ScriptHold::Reply {
+      self.script_hold = ScriptHold::ReplySent;
+    }
+    trace!("Keyboard sent {:02X?}", scancode);
+    scancode
+  }
+
+  /// When the keyboard next has something to do. `ready` is whether the PPI can receive a code.
+  pub fn next_event_time(&self, ready: bool) -> Option<u64> {
+    let send = if ready && self.can_send() { Some(self.next_send) } else { None };
+    [self.self_test_at, self.typematic.map(|(_, at)| at), send].into_iter().flatten().min()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const A: u8 = 0x1E;
+
+  /// The BIOS holds the clock low, then lets it go.
+  fn reset(keyboard: &mut Keyboard, now: u64) -> u64 {
+    keyboard.set_clock(now, false);
+    keyboard.set_clock(now + RESET_TIME, true);
+    now + RESET_TIME + SELF_TEST_TIME
+  }
+
+  /// What the PPI receives, taking each code as soon as the keyboard has it.
+  fn receive(keyboard: &mut Keyboard, now: u64, codes: usize) -> Vec<u8> {
+    (0..codes as u64).map(|index| {
+      let now = now + index * BYTE_TIME;
+      keyboard.update(now);
+      keyboard.take(now).expect("No code to send")
+    }).collect()
+  }
+
+  #[test]
+  fn self_test_reply() {
+    let mut keyboard = start();
+    let now = reset(&mut keyboard, 0);
+    keyboard.update(now - 1);
+    assert_eq!(keyboard.take(now - 1), None, "Still running the self test.");
+    assert_eq!(receive(&mut keyboard, now, 1), [SELF_TEST_PASSED]);
+  }
+
+  #[test]
+  fn script_waits_for_the_self_test_reply() {
+    let mut keyboard = start();
+    keyboard.type_string("a");
+    keyboard.update(0);
+    let now = reset(&mut keyboard, 100);
+    assert_eq!(receive(&mut keyboard, now, 3), [SELF_TEST_PASSED, A, A | BREAK], "The reset didn't throw the script away.");
+  }
+
+  #[test]
+  fn script_survives_a_second_reset() {
+    let mut keyboard = start();
+    keyboard.type_string("a");
+    let now = reset(&mut keyboard, 0);
+    assert_eq!(receive(&mut keyboard, now, 1), [SELF_TEST_PASSED]);
+    let now = reset(&mut keyboard, now + BYTE_TIME);
+    assert_eq!(receive(&mut keyboard, now, 3), [SELF_TEST_PASSED, A, A | BREAK]);
+  }
+
+  #[test]
+  fn keys_come_after_the_self_test_reply() {
+    let mut keyboard = start();
+    keyboard.set_clock(0, true);
+    keyboard.set_clock(0, false);
+    keyboard.set_clock(RESET_TIME, true);
+    keyboard.key_down(RESET_TIME, A);
+    let now = RESET_TIME + SELF_TEST_TIME;
+    assert_eq!(receive(&mut keyboard, now, 2), [SELF_TEST_PASSED, A]);
+  }
+
+  #[test]
+  fn overrun() {
+    let mut keyboard = start();
+    let now = reset(&mut keyboard, 0);
+    assert_eq!(receive(&mut keyboard, now, 1), [SELF_TEST_PASSED]);
+    for scancode in 1..=20 {
+      keyboard.key_down(now, scancode);
+    }
+    let codes = receive(&mut keyboard, now + BYTE_TIME, BUFFER_SIZE + 1);
+    assert_eq!(codes[BUFFER_SIZE - 1], BUFFER_SIZE as u8);
+    assert_eq!(codes[BUFFER_SIZE], OVERRUN);
+    keyboard.update(now + BYTE_TIME * 100);
+    assert_eq!(keyboard.take(now + BYTE_TIME * 100), None, "Codes after the overrun are lost.");
+  }
+}
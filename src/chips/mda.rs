@@ -0,0 +1,389 @@
+//IBM Monochrome Display and Printer Adapter (MDA)
+//http://www.seasip.info/VintagePC/mda.html
+//
+//80x25 text from 4KB of memory at B0000, which repeats through B7FFF.
+//3B0-3B7: 6845 (even ports select the register, odd ports hold its data). 3B8: mode. 3BA: status.
+//The card also carries a parallel printer port at 3BC-3BE.
+//
+//Hercules Graphics Card (HGC)
+//https://www.seasip.info/VintagePC/hercplus.html
+//
+//An MDA with 64KB of memory, and a 720x348 graphics mode with 2 pages of 32KB at B0000 and B8000.
+//3BF is the configuration switch, which has to allow graphics and the second page before the mode register can use them.
+//Graphics memory is split into 4 banks of 8KB, one for each scan line within a character row.
+
+use super::crtc::{self, Crtc, TextScreen};
+use super::framebuffer::{self, Framebuffer};
+use super::video_memory::{self, VideoMemory};
+use super::parallel::{self, ParallelPort};
+use super::memory1mb::MemoryDevice;
+use super::font;
+use crate::clock::MASTER_HZ;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::debug;
+
+pub const MEMORY_START: usize = 0xB_0000;
+const MEMORY_SIZE: usize = 0x1000;
+const HERCULES_MEMORY_SIZE: usize = 0x1_0000;
+const TEXT_MEMORY_SIZE: usize = 0x1000;  //Text pages wrap around within 4KB, even with the Hercules' 64KB.
+const PAGE_1: usize = 0x8000;
+
+const DOT_HZ: u64 = 16_257_000;  //The MDA has its own crystal.
+const CHARACTER_WIDTH: u64 = 9;
+const GRAPHICS_CHARACTER_WIDTH: u64 = 16;  //In Hercules graphics, each character clock is 2 bytes of dots.
+
+const BLACK: [u8; 3] = [0x00, 0x00, 0x00];
+const NORMAL: [u8; 3] = [0xAA, 0xAA, 0xAA];
+const INTENSE: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+#[derive(Debug, Default)]
+struct Mode {
+  high_resolution: bool,  //Must be set, or the card doesn't run.
+  enabled: bool,
+  blink: bool,
+  graphics: bool,         //Hercules only.
+  page_1: bool,           //Hercules only. Show B8000 instead of B0000.
+}
+
+/// Hercules configuration switch at 3BF.
+#[derive(Debug, Default)]
+struct Configuration {
+  allow_graphics: bool,
+  full: Arc<AtomicBool>,  //The second page is mapped at B8000. Shared with the memory map.
+}
+
+/// The Hercules only answers at B8000-BFFFF once the configuration switch allows the second page.
+/// Until then, a CGA can sit there.
+pub struct HerculesMemory {
+  memory: VideoMemory,
+  full: Arc<AtomicBool>,
+}
+
+pub struct Mda {
+  crtc: Crtc,
+  memory: VideoMemory,
+  mode: Mode,
+  printer: ParallelPort,
+  hercules: Option<Configuration>,
+}
+
+pub fn start(hercules: bool) -> Mda {
+  Mda {
+    crtc: crtc::start(),
+    memory: video_memory::new(if hercules { HERCULES_MEMORY_SIZE } else { MEMORY_SIZE }),
+    mode: Default::default(),
+    printer: parallel::start(),
+    hercules: if hercules { Some(Default::default()) } else { None },
+  }
+}
+
+impl Mda {
+  /// To be mapped into the memory map at MEMORY_START, for memory_window() bytes.
+  pub fn video_memory(&self) -> Box<dyn MemoryDevice> {
+    match &self.hercules {
+      Some(configuration) => Box::new(HerculesMemory {
+        memory: self.memory.clone(),
+        full: Arc::clone(&configuration.full),
+      }),
+      None => Box::new(self.memory.clone()),
+    }
+  }
+
+  /// The MDA decodes B0000-B7FFF. The Hercules decodes up to BFFFF, for its second page.
+  pub fn memory_window(&self) -> usize {
+    if self.hercules.is_some() { 0x1_0000 } else { 0x8000 }
+  }
+
+  pub fn out_byte(&mut self, port: u16, value: u8) {
+    match port {
+      0x3B0..=0x3B7 if port & 1 == 0 => self.crtc.choose_register(value),
+      0x3B0..=0x3B7 => self.crtc.set_register_data(value),
+      0x3B8 => self.set_mode(value),
+      0x3BC => self.printer.write_data(value),
+      0x3BE => self.printer.write_control(value),
+      0x3BF if self.hercules.is_some() => self.set_configuration(value),
+      _ => debug!("MDA port {:X} got {:X}. Nothing is there.", port, value),
+    }
+  }
+
+  pub fn in_byte(&mut self, port: u16, now: u64) -> u8 {
+    match port {
+      0x3B0..=0x3B7 if port & 1 == 1 => self.crtc.get_register_data(),
+      0x3B8 => self.get_mode(),
+      0x3BA => self.get_status(now),
+      0x3BC => self.printer.read_data(),
+      0x3BD => self.printer.read_status(),
+      0x3BE => self.printer.read_control(),
+      _ => 0xFF,
+    }
+  }
+
+  /// Bit 0: High resolution. Bit 3: Video enabled. Bit 5: Attribute bit 7 blinks, instead of brightening the background.
+  /// Hercules only, if the configuration switch allows them: Bit 1: Graphics. Bit 7: Show page 1.
+  fn set_mode(&mut self, register: u8) {
+    self.mode.high_resolution = matches!(register & 0b1, 0b1);
+    self.mode.enabled = matches!(register & 0b1000, 0b1000);
+    self.mode.blink = matches!(register & 0b10_0000, 0b10_0000);
+    if let Some(configuration) = &self.hercules {
+      self.mode.graphics = configuration.allow_graphics && matches!(register & 0b10, 0b10);
+      self.mode.page_1 = configuration.full.load(Ordering::Relaxed) && matches!(register & 0b1000_0000, 0b1000_0000);
+    }
+    debug!("{:?}", self.mode);
+  }
+
+  fn get_mode(&self) -> u8 {
+    let mut result = 0;
+    if self.mode.high_resolution { result |= 0b1 }
+    if self.mode.graphics { result |= 0b10 }
+    if self.mode.enabled { result |= 0b1000 }
+    if self.mode.blink { result |= 0b10_0000 }
+    if self.mode.page_1 { result |= 0b1000_0000 }
+    result
+  }
+
+  /// Port 3BF. Bit 0: Allow graphics. Bit 1: Map the second page at B8000.
+  fn set_configuration(&mut self, register: u8) {
+    let Some(configuration) = &mut self.hercules else { return; };
+    configuration.allow_graphics = matches!(register & 0b1, 0b1);
+    configuration.full.store(matches!(register & 0b10, 0b10), Ordering::Relaxed);
+    debug!("Hercules {:?}", configuration);
+  }
+
+  /// Characters clocked out since power on, at the given master clock cycle.
+  fn character_clocks(&self, now: u64) -> u64 {
+    (now as u128 * DOT_HZ as u128 / (MASTER_HZ as u128 * self.character_width() as u128)) as u64
+  }
+
+  fn character_width(&self) -> u64 {
+    if self.mode.graphics { GRAPHICS_CHARACTER_WIDTH } else { CHARACTER_WIDTH }
+  }
+
+  /// Length of a frame in master clock cycles, which is about 50 Hz.
+  pub fn frame_time(&self) -> u64 {
+    (self.crtc.frame_clocks() as u128 * self.character_width() as u128 * MASTER_HZ as u128 / DOT_HZ as u128) as u64
+  }
+
+  /// Port 3BA. Bit 0: Horizontal retrace. Bit 3: Video signal, taken as being on through the display area.
+  /// The MDA leaves bits 4-7 high. The Hercules drives bit 7 low through vertical retrace, and 0 in bits 4-6 identifies the plain HGC.
+  /// Software tells them apart by watching bit 7 change.
+  fn get_status(&self, now: u64) -> u8 {
+    let (display_enable, horizontal_sync, vertical_sync) = self.crtc.raster_status(self.character_clocks(now));
+    let mut result = match (&self.hercules, vertical_sync) {
+      (None, _) => 0xF0,
+      (Some(_), false) => 0x80,
+      (Some(_), true) => 0x00,
+    };
+    if horizontal_sync { result |= 0b1 }
+    if display_enable { result |= 0b1000 }
+    result
+  }
+
+  /// None when the display is off, or in Hercules graphics.
+  pub fn text_screen(&self) -> Option<TextScreen> {
+    if !self.mode.enabled || self.mode.graphics {
+      return None;
+    }
+    let memory = self.memory.lock();
+    let page = self.page();
+    Some(self.crtc.text_screen(&memory[page..page + TEXT_MEMORY_SIZE], 80, false, self.mode.blink))
+  }
+
+  /// Where the shown page starts in the card's memory, in text and graphics alike.
+  fn page(&self) -> usize {
+    if self.mode.page_1 { PAGE_1 } else { 0 }
+  }
+
+  /// Draw the screen as the monitor would show it at the given master clock cycle, which sets the blink phase.
+  pub fn render(&self, time: u64) -> Framebuffer {
+    match self.text_screen() {
+      Some(screen) => {
+        let frame = self.crtc.frame_number(self.character_clocks(time));
+        crtc::render_text(&screen, frame, CHARACTER_WIDTH as usize, font::glyph_9x14, colors)
+      },
+      None if self.mode.enabled => self.render_graphics(),
+      None => framebuffer::new(720, 350),
+    }
+  }
+
+  /// 1 bit per pixel. Each character row is 2 bytes per character clock, and scan line n of the row comes from bank n.
+  fn render_graphics(&self) -> Framebuffer {
+    let columns = self.crtc.columns(45);
+    let rows = self.crtc.rows(87);
+    let lines_per_row = self.crtc.lines_per_row();
+    let page = self.page();
+    let mut image = framebuffer::new(columns * GRAPHICS_CHARACTER_WIDTH as usize, rows * lines_per_row);
+    let memory = self.memory.lock();
+
+    for row in 0..rows {
+      for line in 0..lines_per_row {
+        let y = row * lines_per_row + line;
+        for index in 0..columns * 2 {
+          let offset = ((self.crtc.start_address() + row * columns) * 2 + index) & 0x1FFF;
+          let byte = memory[page | ((line & 0b11) << 13) | offset];
+          for bit in 0..8 {
+            image.set_pixel(index * 8 + bit, y, if byte & (0x80 >> bit) != 0 { NORMAL } else { BLACK });
+          }
+        }
+      }
+    }
+    image
+  }
+}
+
+impl MemoryDevice for HerculesMemory {
+  fn read_byte(&mut self, addr: usize) -> u8 {
+    if addr & PAGE_1 != 0 && !self.full.load(Ordering::Relaxed) {
+      return 0xFF;
+    }
+    self.memory.read_byte(addr)
+  }
+
+  fn write_byte(&mut self, addr: usize, value: u8) {
+    if addr & PAGE_1 != 0 && !self.full.load(Ordering::Relaxed) {
+      return;
+    }
+    self.memory.write_byte(addr, value);
+  }
+}
+
+/// The MDA only knows a few attributes: invisible, reverse video, underline, intensity and blink.
+fn colors(attribute: u8, blink: bool) -> ([u8; 3], [u8; 3], bool) {
+  let intense = attribute & 0x08 != 0;
+  let foreground = if intense { INTENSE } else { NORMAL };
+  match attribute & 0x77 {
+    0x00 => (BLACK, BLACK, false),
+    //Reverse video. With blink off, bit 7 brightens the background instead.
+    0x70 => (BLACK, if !blink && attribute & 0x80 != 0 { INTENSE } else { NORMAL }, false),
+    foreground_bits => (foreground, BLACK, foreground_bits & 0x07 == 0x01),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const HIGH_RESOLUTION: u8 = 0b1;
+  const ENABLED: u8 = 0b1000;
+  const SHOW_PAGE_1: u8 = 0b1000_0000;
+
+  #[test]
+  fn hercules_text_shows_page_1() {
+    let mut hercules = start(true);
+    let mut memory = hercules.video_memory();
+    hercules.out_byte(0x3BF, 0b10);  //Map the second page.
+    memory.write_byte(MEMORY_START, b'0');
+    memory.write_byte(MEMORY_START + PAGE_1, b'1');
+
+    hercules.out_byte(0x3B8, HIGH_RESOLUTION | ENABLED);
+    assert_eq!(hercules.text_screen().unwrap().cells[0].0, b'0');
+    hercules.out_byte(0x3B8, HIGH_RESOLUTION | ENABLED | SHOW_PAGE_1);
+    assert_eq!(hercules.text_screen().unwrap().cells[0].0, b'1');
+  }
+
+  #[test]
+  fn attributes() {
+    assert_eq!(colors(0x07, true), (NORMAL, BLACK, false));
+    assert_eq!(colors(0x0F, true), (INTENSE, BLACK, false));
+    assert_eq!(colors(0x01, true), (NORMAL, BLACK, true));
+    assert_eq!(colors(0x70, true), (BLACK, NORMAL, false));
+    assert_eq!(colors(0x00, true), (BLACK, BLACK, false));
+    assert_eq!(colors(0x88, true), (BLACK, BLACK, false), "Bit 3 doesn't light up invisible characters.");
+    assert_eq!(colors(0xF0, true), (BLACK, NORMAL, false), "Bit 7 blinks.");
+    assert_eq!(colors(0xF0, false), (BLACK, INTENSE, false), "Or brightens the background.");
+  }
+
+  //The first master clock cycle at which this many characters have been clocked out.
+  fn time_of(character_clocks: u64) -> u64 {
+    (character_clocks * CHARACTER_WIDTH * MASTER_HZ).div_ceil(DOT_HZ)
+  }
+
+  fn status_registers(hercules: bool) -> Mda {
+    let mut mda = start(hercules);
+    //10 clocks per line, 8 displayed, horizontal sync at 8-9. 5 lines displayed, vertical sync from line 7.
+    for (register, value) in [9, 8, 8, 2, 29, 0, 5, 7, 0, 0].into_iter().enumerate() {
+      mda.out_byte(0x3B4, register as u8);
+      mda.out_byte(0x3B5, value);
+    }
+    mda
+  }
+
+  #[test]
+  fn mda_status_register() {
+    let mut mda = status_registers(false);
+    assert_eq!(mda.character_clocks(time_of(155)), 155);
+    assert_eq!(mda.in_byte(0x3BA, time_of(0)), 0xF8, "Video on in the display area");
+    assert_eq!(mda.in_byte(0x3BA, time_of(8)), 0xF1, "Horizontal retrace");
+    assert_eq!(mda.in_byte(0x3BA, time_of(155)), 0xF0, "The MDA has no vertical retrace bit.");
+  }
+
+  #[test]
+  fn hercules_status_register_drops_bit_7_in_vertical_retrace() {
+    let mut hercules = status_registers(true);
+    assert_eq!(hercules.in_byte(0x3BA, time_of(0)), 0x88);
+    assert_eq!(hercules.in_byte(0x3BA, time_of(155)), 0x00);
+    assert_eq!(hercules.in_byte(0x3BA, time_of(255)), 0x80);
+  }
+
+  const GRAPHICS: u8 = 0b10;
+
+  //One character clock across (16 dots), one row of 4 scan lines.
+  fn hercules_graphics(configuration: u8, mode: u8) -> (Mda, Box<dyn MemoryDevice>) {
+    let mut hercules = start(true);
+    for (register, value) in [(1, 1), (6, 1), (9, 3)] {
+      hercules.out_byte(0x3B4, register);
+      hercules.out_byte(0x3B5, value);
+    }
+    hercules.out_byte(0x3BF, configuration);
+    hercules.out_byte(0x3B8, HIGH_RESOLUTION | ENABLED | mode);
+    let memory = hercules.video_memory();
+    (hercules, memory)
+  }
+
+  #[test]
+  fn hercules_graphics_scan_lines_come_from_4_banks() {
+    let (hercules, mut memory) = hercules_graphics(0b01, GRAPHICS);
+    for line in 0..4 {
+      memory.write_byte(MEMORY_START + line * 0x2000, 0x80 >> line);
+    }
+    memory.write_byte(MEMORY_START + 1, 0x01);
+    let image = hercules.render(0);
+    assert_eq!((image.width, image.height), (16, 4));
+    for line in 0..4 {
+      let lit: Vec<usize> = (0..16).filter(|&x| image.pixel(x, line) == NORMAL).collect();
+      let expected = if line == 0 { vec![0, 15] } else { vec![line] };
+      assert_eq!(lit, expected, "Scan line {}", line);
+    }
+  }
+
+  #[test]
+  fn hercules_graphics_needs_the_configuration_switch() {
+    let (hercules, _) = hercules_graphics(0b00, GRAPHICS);
+    assert_eq!(hercules.get_mode() & GRAPHICS, 0);
+    assert!(hercules.text_screen().is_some());
+  }
+
+  #[test]
+  fn hercules_graphics_page_1() {
+    let (hercules, mut memory) = hercules_graphics(0b11, GRAPHICS | SHOW_PAGE_1);
+    memory.write_byte(MEMORY_START, 0xFF);
+    memory.write_byte(MEMORY_START + PAGE_1, 0x0F);
+    let image = hercules.render(0);
+    assert_eq!(image.pixel(0, 0), BLACK);
+    assert_eq!(image.pixel(4, 0), NORMAL);
+  }
+
+  #[test]
+  fn hercules_page_1_is_only_mapped_once_allowed() {
+    let (mut hercules, mut memory) = hercules_graphics(0b01, GRAPHICS);
+    memory.write_byte(MEMORY_START + PAGE_1, 0x12);
+    assert_eq!(memory.read_byte(MEMORY_START + PAGE_1), 0xFF, "Left free for a CGA");
+    hercules.out_byte(0x3BF, 0b11);
+    assert_eq!(memory.read_byte(MEMORY_START + PAGE_1), 0x00);
+    memory.write_byte(MEMORY_START + PAGE_1, 0x12);
+    assert_eq!(memory.read_byte(MEMORY_START + PAGE_1), 0x12);
+    assert_eq!(memory.read_byte(MEMORY_START), 0x00);
+  }
+}
@@ -1,51 +1,347 @@
 use crate::MemoryMsg;
 
+use std::io;
+use std::collections::HashSet;
+
+use log::debug;
+
+const ADDRESS_SPACE: usize = 0x10_0000; //20 address lines = 1MB.
+const ADDRESS_MASK: usize = ADDRESS_SPACE - 1;
+const PAGE_SIZE: usize = 0x800; //Regions are mapped at 2KB granularity, the smallest option ROM block size.
+
+/// Something sitting on the bus which answers memory reads and writes by itself,
+/// such as a video adapter or an expanded memory page frame.
+/// The address given is the full 20 bit physical address.
+pub trait MemoryDevice: Send {
+  fn read_byte(&mut self, addr: usize) -> u8;
+  fn write_byte(&mut self, addr: usize, value: u8);
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum Region {
+  #[default]
+  Unmapped, //Nothing is driving the bus, so reads return 0xFF.
+  Ram,
+  Rom,      //Writes are ignored.
+  Device(usize),  //Index into devices.
+}
+
 pub struct Memory {
-  ram: Vec<u8>,
+  data: Vec<u8>,
+  regions: Vec<Region>, //One per page.
+  devices: Vec<Box<dyn MemoryDevice>>,
+  parity_faults: HashSet<usize>,  //RAM bytes whose stored parity bit is wrong.
+  parity_error: bool, //Latched when a faulty byte is read, until the motherboard takes it.
 }
 
-pub fn start(bios_rom: &mut Vec<u8>, video_rom: &mut Vec<u8>) -> Memory {
+/// RAM fills the first `ram_kb`. The motherboard has already checked it is a size a board can hold.
+/// Anything not populated is left unmapped, so the BIOS memory probe stops there.
+pub fn start(bios_rom: &[u8], ram_kb: usize) -> Memory {
   let mut memory = Memory {
-    ram: vec![0u8; 0xC_0000],
+    data: vec![0u8; ADDRESS_SPACE],
+    regions: vec![Region::Unmapped; ADDRESS_SPACE / PAGE_SIZE],
+    devices: Vec::new(),
+    parity_faults: HashSet::new(),
+    parity_error: false,
   };
-  
-  memory.ram.append(video_rom);
-  
-  memory.ram.resize(0xF_0000, 0); //Fill up the remainder of space up to the bios.
-  
+
+  memory.map_ram(0, ram_kb * 1024);
+
   if bios_rom.len() != 0x1_0000 {
     panic!("The ROM size is wrong: {:X}. It must be size 0x10000.", bios_rom.len());
   }
-  memory.ram.append(bios_rom);
-  
+  memory.map_rom(0xF_0000, bios_rom);
+
   memory
 }
 
 impl Memory {
+  fn page_range(start: usize, len: usize) -> std::ops::Range<usize> {
+    if !start.is_multiple_of(PAGE_SIZE) {
+      panic!("Memory region {:05X} is not aligned to {:X}.", start, PAGE_SIZE);
+    }
+    if start + len > ADDRESS_SPACE {
+      panic!("Memory region {:05X} of size {:X} is past 1MB.", start, len);
+    }
+    let first = start / PAGE_SIZE;
+    let last = (start + len).div_ceil(PAGE_SIZE);  //Partial pages are rounded up.
+    first..last
+  }
+
+  fn set_region(&mut self, start: usize, len: usize, region: Region) {
+    for page in Memory::page_range(start, len) {
+      self.regions[page] = region;
+    }
+  }
+
+  pub fn map_ram(&mut self, start: usize, len: usize) {
+    self.set_region(start, len, Region::Ram);
+    debug!("Mapped RAM {:05X}-{:05X}", start, start + len - 1);
+  }
+
+  pub fn map_rom(&mut self, start: usize, rom: &[u8]) {
+    self.set_region(start, rom.len(), Region::Rom);
+    self.data[start..start + rom.len()].copy_from_slice(rom);
+    debug!("Mapped ROM {:05X}-{:05X}", start, start + rom.len() - 1);
+  }
+
+  /// Option ROMs live between C0000 and EFFFF on 2KB boundaries, and are found by the BIOS ROM scan.
+  /// Header: 55 AA, then the size in 512 byte blocks. All bytes of the ROM must add up to 0 (mod 256).
+  pub fn map_option_rom(&mut self, start: usize, rom: &[u8]) -> io::Result<()> {
+    let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    if !(0xC_0000..0xF_0000).contains(&start) || !start.is_multiple_of(PAGE_SIZE) {
+      return invalid(format!("Option ROM address {:05X} must be a 2KB boundary between C0000 and EF800.", start));
+    }
+    if rom.len() < 3 || rom[0] != 0x55 || rom[1] != 0xAA {
+      return invalid(format!("Option ROM at {:05X} is missing the 55AA signature.", start));
+    }
+    let size = rom[2] as usize * 512;
+    if size == 0 || size > rom.len() {
+      return invalid(format!("Option ROM at {:05X} claims size {:X}, but the file is {:X} bytes.", start, size, rom.len()));
+    }
+    if start + size > 0xF_0000 {
+      return invalid(format!("Option ROM at {:05X} of size {:X} overlaps the system BIOS.", start, size));
+    }
+    let checksum = rom[..size].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if checksum != 0 {
+      return invalid(format!("Option ROM at {:05X} has a bad checksum {:02X}.", start, checksum));
+    }
+    self.map_rom(start, &rom[..size]);
+    Ok(())
+  }
+
+  /// True if any of this range holds a ROM, which a device mapped over it would hide.
+  pub fn has_rom(&self, start: usize, len: usize) -> bool {
+    Memory::page_range(start, len).any(|page| matches!(self.regions[page], Region::Rom))
+  }
+
+  pub fn map_device(&mut self, start: usize, len: usize, device: Box<dyn MemoryDevice>) {
+    self.devices.push(device);
+    self.set_region(start, len, Region::Device(self.devices.len() - 1));
+    debug!("Mapped device {} at {:05X}-{:05X}", self.devices.len() - 1, start, start + len - 1);
+  }
+
+  /// Fault injection: the next read of this RAM byte fails its parity check.
+  /// Writing the byte stores fresh parity and clears the fault.
+  pub fn inject_parity_error(&mut self, addr: usize) {
+    debug!("Injected parity error at {:05X}", addr & ADDRESS_MASK);
+    self.parity_faults.insert(addr & ADDRESS_MASK);
+  }
+
+  /// True if a read has hit a parity error since the last call.
+  pub fn take_parity_error(&mut self) -> bool {
+    std::mem::take(&mut self.parity_error)
+  }
+
+  pub fn read_byte(&mut self, addr: usize) -> u8 {
+    let addr = addr & ADDRESS_MASK; //Addresses past FFFFF wrap around to 0, just like on an 8088.
+    match self.regions[addr / PAGE_SIZE] {
+      Region::Unmapped => 0xFF,
+      Region::Ram => {
+        if self.parity_faults.contains(&addr) {
+          debug!("Parity error reading {:05X}", addr);
+          self.parity_error = true;
+        }
+        self.data[addr]
+      },
+      Region::Rom => self.data[addr],
+      Region::Device(index) => self.devices[index].read_byte(addr),
+    }
+  }
+
+  pub fn write_byte(&mut self, addr: usize, value: u8) {
+    let addr = addr & ADDRESS_MASK;
+    match self.regions[addr / PAGE_SIZE] {
+      Region::Unmapped | Region::Rom => {},
+      Region::Ram => {
+        self.parity_faults.remove(&addr);
+        self.data[addr] = value;
+      },
+      Region::Device(index) => self.devices[index].write_byte(addr, value),
+    }
+  }
+
   pub fn process_msg(&mut self, msg: MemoryMsg) {
     match msg {
       MemoryMsg::SetByte{addr, value} => {
-        self.ram[addr] = value;
+        self.write_byte(addr, value);
       },
       MemoryMsg::SetWord{addr, value} => {
         let bytes = value.to_le_bytes();
-        let (_left, right) = self.ram.split_at_mut(addr);
-        let (middle, _) = right.split_at_mut(2);
-        middle.copy_from_slice(&bytes[..]);
+        self.write_byte(addr, bytes[0]);
+        self.write_byte(addr + 1, bytes[1]);
       },
       MemoryMsg::GetByte{addr, socket} => {
-        socket.send(self.ram[addr]).unwrap();
+        socket.send(self.read_byte(addr)).unwrap();
       },
       MemoryMsg::GetWord{addr, socket} => {
-        let slice = &self.ram[addr..addr+2];
-        let word = u16::from_le_bytes(slice.try_into().unwrap());
+        let word = u16::from_le_bytes([self.read_byte(addr), self.read_byte(addr + 1)]);
         socket.send(word).unwrap();
       },
       MemoryMsg::GetBytes8{addr, socket} => {
-        let slice = &self.ram[addr..addr+8];
-        let bytes8 = u64::from_le_bytes(slice.try_into().unwrap());
-        socket.send(bytes8).unwrap();
+        let mut bytes = [0u8; 8];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+          *byte = self.read_byte(addr + i);
+        }
+        socket.send(u64::from_le_bytes(bytes)).unwrap();
+      },
+      MemoryMsg::InjectParityError{addr} => {
+        self.inject_parity_error(addr);
       },
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+  use std::sync::mpsc;
+
+  fn memory(ram_kb: usize) -> Memory {
+    let mut bios_rom = vec![0u8; 0x1_0000];
+    bios_rom[0xFFFF] = 0xEA;
+    start(&bios_rom, ram_kb)
+  }
+
+  //Answers reads with the low byte of the address, and records writes.
+  struct Recorder {
+    writes: Arc<Mutex<Vec<(usize, u8)>>>,
+  }
+
+  impl MemoryDevice for Recorder {
+    fn read_byte(&mut self, addr: usize) -> u8 {
+      addr as u8
+    }
+    fn write_byte(&mut self, addr: usize, value: u8) {
+      self.writes.lock().unwrap().push((addr, value));
+    }
+  }
+
+  #[test]
+  fn addresses_wrap_around_at_1mb() {
+    let mut memory = memory(640);
+    memory.write_byte(0x10_0010, 0x12);
+    assert_eq!(memory.read_byte(0x10), 0x12);
+    assert_eq!(memory.read_byte(0x10_0010), 0x12);
+
+    //A word at FFFFF takes its high byte from 00000.
+    memory.write_byte(0, 0x34);
+    let (socket, reply) = mpsc::channel();
+    memory.process_msg(MemoryMsg::GetWord{addr: 0xF_FFFF, socket});
+    assert_eq!(reply.recv().unwrap(), 0x34EA);
+
+    memory.process_msg(MemoryMsg::SetWord{addr: 0xF_FFFF, value: 0x5678});
+    assert_eq!(memory.read_byte(0xF_FFFF), 0xEA);
+    assert_eq!(memory.read_byte(0), 0x56);
+  }
+
+  #[test]
+  fn rom_writes_are_ignored() {
+    let mut memory = memory(640);
+    memory.write_byte(0xF_FFFF, 0x00);
+    assert_eq!(memory.read_byte(0xF_FFFF), 0xEA);
+    memory.process_msg(MemoryMsg::SetWord{addr: 0xF_0000, value: 0xFFFF});
+    assert_eq!(memory.read_byte(0xF_0000), 0x00);
+  }
+
+  #[test]
+  fn unmapped_memory_reads_ff() {
+    let mut memory = memory(256);
+    assert_eq!(memory.read_byte(256 * 1024 - 1), 0x00);
+    assert_eq!(memory.read_byte(256 * 1024), 0xFF);
+    memory.write_byte(256 * 1024, 0x00);
+    assert_eq!(memory.read_byte(256 * 1024), 0xFF);
+    assert_eq!(memory.read_byte(0xD_0000), 0xFF);
+  }
+
+  #[test]
+  fn device_regions_get_their_own_reads_and_writes() {
+    let mut memory = memory(640);
+    let writes = Arc::new(Mutex::new(Vec::new()));
+    memory.map_device(0xB_8000, 0x4000, Box::new(Recorder{writes: writes.clone()}));
+
+    assert_eq!(memory.read_byte(0xB_8042), 0x42);
+    memory.write_byte(0xB_8001, 0x07);
+    memory.process_msg(MemoryMsg::SetWord{addr: 0xB_BFFF, value: 0x1234});
+    assert_eq!(*writes.lock().unwrap(), vec![(0xB_8001, 0x07), (0xB_BFFF, 0x34)]);
+
+    //The byte after the device is unmapped again.
+    assert_eq!(memory.read_byte(0xB_C000), 0xFF);
+    assert!(!memory.has_rom(0xB_8000, 0x4000));
+    assert!(memory.has_rom(0xF_0000, 0x800));
+  }
+
+  //A ROM of `blocks` 512 byte blocks with a correct header and checksum.
+  fn option_rom(blocks: u8) -> Vec<u8> {
+    let mut rom = vec![0u8; blocks as usize * 512];
+    rom[0] = 0x55;
+    rom[1] = 0xAA;
+    rom[2] = blocks;
+    rom[3] = 0xCB; //RETF
+    let sum = rom.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    rom[5] = sum.wrapping_neg();
+    rom
+  }
+
+  #[test]
+  fn option_rom_is_mapped() {
+    let mut memory = memory(640);
+    let mut rom = option_rom(4);
+    rom.extend_from_slice(&[0x11; 0x800]);  //Padding past the declared size is left off.
+    memory.map_option_rom(0xC_8000, &rom).unwrap();
+
+    assert_eq!(memory.read_byte(0xC_8000), 0x55);
+    assert_eq!(memory.read_byte(0xC_8003), 0xCB);
+    assert!(memory.has_rom(0xC_8000, 0x800));
+    assert_eq!(memory.read_byte(0xC_8800), 0xFF);
+    memory.write_byte(0xC_8003, 0x00);
+    assert_eq!(memory.read_byte(0xC_8003), 0xCB);
+  }
+
+  #[test]
+  fn option_rom_must_be_on_a_2kb_boundary_below_the_bios() {
+    let mut memory = memory(640);
+    assert!(memory.map_option_rom(0xB_8000, &option_rom(4)).is_err());
+    assert!(memory.map_option_rom(0xC_8200, &option_rom(4)).is_err());
+    assert!(memory.map_option_rom(0xF_0000, &option_rom(4)).is_err());
+    assert!(!memory.has_rom(0xB_8000, 0x800));
+  }
+
+  #[test]
+  fn option_rom_needs_the_55aa_signature() {
+    let mut memory = memory(640);
+    let mut rom = option_rom(4);
+    rom[1] = 0x55;
+    assert!(memory.map_option_rom(0xC_8000, &rom).is_err());
+    assert!(memory.map_option_rom(0xC_8000, &[0x55, 0xAA]).is_err());
+    assert!(!memory.has_rom(0xC_8000, 0x800));
+  }
+
+  #[test]
+  fn option_rom_size_byte_must_fit_the_file() {
+    let mut memory = memory(640);
+    let mut rom = option_rom(4);
+    rom[2] = 0;
+    assert!(memory.map_option_rom(0xC_8000, &rom).is_err());
+    rom[2] = 5;
+    assert!(memory.map_option_rom(0xC_8000, &rom).is_err());
+    assert!(!memory.has_rom(0xC_8000, 0x800));
+  }
+
+  #[test]
+  fn option_rom_must_not_overlap_the_bios() {
+    let mut memory = memory(640);
+    assert!(memory.map_option_rom(0xE_F800, &option_rom(8)).is_err());
+    memory.map_option_rom(0xE_F800, &option_rom(4)).unwrap();
+  }
+
+  #[test]
+  fn option_rom_checksum_must_be_zero() {
+    let mut memory = memory(640);
+    let mut rom = option_rom(4);
+    rom[0x100] = 1;
+    assert!(memory.map_option_rom(0xC_8000, &rom).is_err());
+    assert!(!memory.has_rom(0xC_8000, 0x800));
+  }
+}
@@ -1,8 +1,18 @@
 pub mod shared;
 pub mod cpu8086;
 pub mod memory1mb;
-pub mod graphics;
+pub mod crtc;
+pub mod video_memory;
+pub mod mda;
+pub mod cga;
+pub mod parallel;
+pub mod vga;
+pub mod font;
+pub mod framebuffer;
 pub mod pic;
 pub mod pit;
+pub mod speaker;
 pub mod faraday;
 pub mod dma;
+pub mod ems;
+pub mod keyboard;
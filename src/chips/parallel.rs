@@ -0,0 +1,48 @@
+//Parallel printer port, as on the MDA card at 3BC and the printer adapter at 378.
+//https://stanislavs.org/helppc/ports.html
+//
+//base + 0: data latch, which reads back what was written. The BIOS finds the port by writing and reading it.
+//base + 1: status. base + 2: control.
+//No printer is attached, so the status always reads busy and offline.
+
+use log::debug;
+
+//Status register. Busy, acknowledge and error are active low.
+const NO_PRINTER_STATUS: u8 = 0b0100_0111;  //Busy, no acknowledge, not selected, error. Bits 0-2 are unused and read high.
+
+#[derive(Debug, Default)]
+pub struct ParallelPort {
+  data: u8,
+  control: u8,
+}
+
+pub fn start() -> ParallelPort {
+  Default::default()
+}
+
+impl ParallelPort {
+  pub fn write_data(&mut self, value: u8) {
+    self.data = value;
+  }
+
+  pub fn read_data(&self) -> u8 {
+    self.data
+  }
+
+  pub fn read_status(&self) -> u8 {
+    NO_PRINTER_STATUS
+  }
+
+  /// Bit 0: strobe. Bit 1: auto line feed. Bit 2: not initialize. Bit 3: select. Bit 4: IRQ enable.
+  pub fn write_control(&mut self, value: u8) {
+    if matches!(value & 0b1, 0b1) && !matches!(self.control & 0b1, 0b1) {
+      debug!("Printer strobed {:02X} with nothing attached", self.data);
+    }
+    self.control = value & 0x1F;
+  }
+
+  /// Bits 5-7 are unused and read high.
+  pub fn read_control(&self) -> u8 {
+    self.control | 0xE0
+  }
+}
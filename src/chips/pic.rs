@@ -1,32 +1,18 @@
-//Intel 8259 - Programmable Interrupt Controller (PIC)
+//Intel 8259A - Programmable Interrupt Controller (PIC)
 //https://www.stanislavs.org/helppc/8259.html
 //https://wiki.osdev.org/PIC
+//https://pdos.csail.mit.edu/6.828/2005/readings/hardware/8259A.pdf
 
 use crate::PICMsg;
 
-use log::debug;
+use log::{debug, error, trace};
 use std::sync::mpsc;
 
-#[derive(Debug, Default)]
-enum VectorType {
-  #[default]
-  Bytes4,
-  Bytes8,
-}
-
 #[derive(Debug, Default)]
 enum Trigger {
   #[default]
-  Level,
   Edge,
-}
-
-#[derive(Debug, Default)]
-pub struct IRQ {
-  master: bool, //true=master, false=slave
-  enabled: bool,
-  interrupt_requested: bool,
-  interrupted_cpu: bool,
+  Level,
 }
 
 #[derive(Debug, Default)]
@@ -40,49 +26,90 @@ enum RegisterType {
 pub struct PIC {
   icw4_needed: bool,  //Gives additional information about the environment
   single: bool,  //true = single chip. false = cascade.
-  vector_type: VectorType,
   trigger: Trigger,
   next_set_index: u8,
   next_get: RegisterType,
+  poll: bool,         //The next read of port 1 is a poll, instead of IRR/ISR.
   vector_offset: u8,
-  irq0: IRQ,
-  irq1: IRQ,
-  irq2: IRQ,
-  irq3: IRQ,
-  irq4: IRQ,
-  irq5: IRQ,
-  irq6: IRQ,
-  irq7: IRQ,
-  messenger: mpsc::Sender<crate::Msg>,
+  cascade: u8,        //ICW3. On a master, which IRQ lines have a slave connected. On a slave, its ID.
+  slave: bool,        //Wired as a slave. Its INTR goes to the master instead of the CPU.
+  special_fully_nested: bool,
+  auto_eoi: bool,
+  rotate_on_auto_eoi: bool,
+  special_mask: bool,
+  lowest_priority: u8,  //The IRQ with the lowest priority. The one after it has the highest.
+  lines: u8,  //Current level of each IRQ input.
+  irr: u8,    //Interrupt Request Register
+  isr: u8,    //In-Service Register
+  imr: u8,    //Interrupt Mask Register
+  intr: bool, //INTR output to the CPU.
+  messenger: Option<mpsc::Sender<crate::Msg>>,
+}
+
+/// The PICs on a motherboard. AT-class machines have a slave PIC with IRQ8-15 connected to the master's IRQ2.
+/// The XT only has the master.
+pub struct Cascade {
+  master: PIC,
+  slave: Option<PIC>,
+}
+
+const CASCADE_IRQ: u8 = 2;
+
+pub fn start(messenger: mpsc::Sender<crate::Msg>, with_slave: bool) -> Cascade {
+  let mut master = new_pic(Some(messenger), 0x08);  //By default, the PIC uses interrupts 0x08 to 0x0F.
+  if !with_slave {
+    return Cascade {
+      master,
+      slave: None,
+    };
+  }
+  master.single = false;
+  master.cascade = 1 << CASCADE_IRQ;
+  let mut slave = new_pic(None, 0x70);  //The AT BIOS maps IRQ8-15 to interrupts 0x70 to 0x77.
+  slave.single = false;
+  slave.slave = true;
+  slave.cascade = CASCADE_IRQ;
+  Cascade {
+    master,
+    slave: Some(slave),
+  }
 }
 
-pub fn start(messenger: mpsc::Sender<crate::Msg>) -> PIC {
+fn new_pic(messenger: Option<mpsc::Sender<crate::Msg>>, vector_offset: u8) -> PIC {
   PIC {
     next_set_index: 5,  //By default, we are just setting enabled/disabled.
-    vector_offset: 0b1000,  //By default, the PIC uses interrupts 0x08 to 0x0F.
+    vector_offset,
     messenger,
     trigger: Default::default(),
-    single: false,
+    single: true,
     icw4_needed: false,
     next_get: Default::default(),
-    vector_type: Default::default(),
-    irq0: Default::default(),
-    irq1: Default::default(),
-    irq2: Default::default(),
-    irq3: Default::default(),
-    irq4: Default::default(),
-    irq5: Default::default(),
-    irq6: Default::default(),
-    irq7: Default::default(),
+    poll: false,
+    cascade: 0,
+    slave: false,
+    special_fully_nested: false,
+    auto_eoi: false,
+    rotate_on_auto_eoi: false,
+    special_mask: false,
+    lowest_priority: 7,
+    lines: 0,
+    irr: 0,
+    isr: 0,
+    imr: 0,
+    intr: false,
   }
 }
 
 impl PIC {
 
   pub fn in_port_1(&mut self) -> u8 {
+    if self.poll {
+      self.poll = false;
+      return self.poll_command();
+    }
     match self.next_get {
-      RegisterType::IRR => self.get_interrupt_requested(),
-      RegisterType::ISR => self.get_interrupted_cpu(),
+      RegisterType::IRR => {debug!("IRR {:08b}", self.irr); self.irr},
+      RegisterType::ISR => {debug!("ISR {:08b}", self.isr); self.isr},
     }
   }
 
@@ -92,8 +119,9 @@ impl PIC {
       (0, 1) => self.operation_control_3(register),
       _ => self.initialization_1(register),
     }
+    self.update_intr();
   }
-  
+
   pub fn out_port_2(&mut self, register: u8) {
     match self.next_set_index {
       2 => self.initialization_2(register),
@@ -101,172 +129,507 @@ impl PIC {
       4 => self.initialization_4(register),
       _ => self.operation_control_1(register),
     }
+    self.update_intr();
   }
 
+  /// Datasheet: the edge sense circuit is reset, the IMR is cleared, IR7 is assigned the lowest priority,
+  /// special mask mode is cleared and status read is set to IRR. If no ICW4 is needed, all its functions are zero.
   fn initialization_1(&mut self, register: u8) {
     self.icw4_needed = matches!(register & 0b1, 0b1);
     self.single = matches!(register & 0b10, 0b10);
-    self.vector_type = if matches!(register & 0b100, 0b100) { VectorType::Bytes4 } else { VectorType::Bytes8 };
+    //Bit 2 (call address interval) only matters in 8080/8085 mode.
     self.trigger = if matches!(register & 0b1000, 0b1000) { Trigger::Level } else { Trigger::Edge };
     self.next_set_index = 2;
+    self.next_get = RegisterType::IRR;
+    self.poll = false;
+    self.imr = 0;
+    self.isr = 0;
+    self.irr = if let Trigger::Level = self.trigger { self.lines } else { 0 };
+    self.special_mask = false;
+    self.lowest_priority = 7;
+    self.cascade = 0;
+    self.special_fully_nested = false;
+    self.auto_eoi = false;
+    self.rotate_on_auto_eoi = false;
     debug!("PIC Init1 {:?}", self);
   }
-  
+
   fn initialization_2(&mut self, register: u8) {
-    self.vector_offset = register;
+    self.vector_offset = register & 0b1111_1000;  //In 8086 mode, the low 3 bits come from the IRQ number.
     debug!("PIC Init2 offset: {:?}", self.vector_offset);
-    self.next_set_index = 3;
+    self.next_set_index = if self.single {
+      if self.icw4_needed { 4 } else { 5 }  //ICW3 is only sent in cascade mode.
+    } else {
+      3
+    };
   }
 
   fn initialization_3(&mut self, register: u8) {
-    self.irq0.master = matches!(register & 0b1, 0);
-    self.irq1.master = matches!(register & 0b10, 0);
-    self.irq2.master = matches!(register & 0b100, 0);
-    self.irq3.master = matches!(register & 0b1000, 0);
-    self.irq4.master = matches!(register & 0b1_0000, 0);
-    self.irq5.master = matches!(register & 0b10_0000, 0);
-    self.irq6.master = matches!(register & 0b100_0000, 0);
-    self.irq7.master = matches!(register & 0b1000_0000, 0);
-    debug!("PIC Init3");
+    self.cascade = if self.slave { register & 0b111 } else { register };
+    debug!("PIC Init3 cascade: {:08b}", self.cascade);
     if self.icw4_needed {
       self.next_set_index = 4;
     } else {  //Skip over it
       self.next_set_index = 5;
-    }  
+    }
   }
 
-  fn initialization_4(&mut self, _register: u8) {
+  fn initialization_4(&mut self, register: u8) {
     /*
-    I don't really care about this for now..
     #define ICW4_8086	0x01		/* 8086/88 (MCS-80/85) mode */
     #define ICW4_AUTO	0x02		/* Auto (normal) EOI */
     #define ICW4_BUF_SLAVE	0x08		/* Buffered mode/slave */
     #define ICW4_BUF_MASTER	0x0C		/* Buffered mode/master */
     #define ICW4_SFNM	0x10		/* Special fully nested (not) */
     */
-    debug!("PIC Init4");
+    self.auto_eoi = matches!(register & 0b10, 0b10);
+    self.special_fully_nested = matches!(register & 0b1_0000, 0b1_0000);
+    debug!("PIC Init4 auto EOI: {}, special fully nested: {}", self.auto_eoi, self.special_fully_nested);
     self.next_set_index = 5;
   }
-  
+
   //Set IRQs Enabled
   fn operation_control_1(&mut self, register: u8) {
-    self.irq0.enabled = matches!(register & 0b1, 0);
-    self.irq1.enabled = matches!(register & 0b10, 0);
-    self.irq2.enabled = matches!(register & 0b100, 0);
-    self.irq3.enabled = matches!(register & 0b1000, 0);
-    self.irq4.enabled = matches!(register & 0b1_0000, 0);
-    self.irq5.enabled = matches!(register & 0b10_0000, 0);
-    self.irq6.enabled = matches!(register & 0b100_0000, 0);
-    self.irq7.enabled = matches!(register & 0b1000_0000, 0);
-    debug!("IRQ Enabled 0: {}, 1: {}, 2: {}, 3: {}, 4: {}, 5: {}, 6: {}, 7: {}",
-          self.irq0.enabled, self.irq1.enabled, self.irq2.enabled, self.irq3.enabled, self.irq4.enabled, self.irq5.enabled, self.irq6.enabled, self.irq7.enabled);
-    //Check if there are any newly enabled IRQs which were waiting to interrupt, and call them.
-    /*
-    if self.irq0.enabled && self.irq0.interrupt_requested {
-      self.process_msg(PICMsg::PIT{channel_index:0});
-    }
-    //TODO - Fill in the rest of the interrupts here..
-*/
+    self.imr = register;
+    debug!("IMR {:08b}", self.imr);
   }
-  
+
   pub fn get_irqs_enabled(&self) -> u8 {
-    let mut result = 0;
-    if !self.irq0.enabled { result |= 0b1; }
-    if !self.irq1.enabled { result |= 0b10; }
-    if !self.irq2.enabled { result |= 0b100; }
-    if !self.irq3.enabled { result |= 0b1000; }
-    if !self.irq4.enabled { result |= 0b1_0000; }
-    if !self.irq5.enabled { result |= 0b10_0000; }
-    if !self.irq6.enabled { result |= 0b100_0000; }
-    if !self.irq7.enabled { result |= 0b1000_0000; }
-    debug!("IRQ Enabled 0: {}, 1: {}, 2: {}, 3: {}, 4: {}, 5: {}, 6: {}, 7: {}",
-          self.irq0.enabled, self.irq1.enabled, self.irq2.enabled, self.irq3.enabled, self.irq4.enabled, self.irq5.enabled, self.irq6.enabled, self.irq7.enabled);
-    result
+    debug!("IMR {:08b}", self.imr);
+    self.imr
   }
-  
-  fn get_interrupt_requested(&self) -> u8 {
-    let mut result = 0;
-    if self.irq0.interrupt_requested { result |= 0b1; }
-    if self.irq1.interrupt_requested { result |= 0b10; }
-    if self.irq2.interrupt_requested { result |= 0b100; }
-    if self.irq3.interrupt_requested { result |= 0b1000; }
-    if self.irq4.interrupt_requested { result |= 0b1_0000; }
-    if self.irq5.interrupt_requested { result |= 0b10_0000; }
-    if self.irq6.interrupt_requested { result |= 0b100_0000; }
-    if self.irq7.interrupt_requested { result |= 0b1000_0000; }
-    debug!("IRR 0: {}, 1: {}, 2: {}, 3: {}, 4: {}, 5: {}, 6: {}, 7: {}",
-          self.irq0.interrupt_requested, self.irq1.interrupt_requested, self.irq2.interrupt_requested, self.irq3.interrupt_requested,
-          self.irq4.interrupt_requested, self.irq5.interrupt_requested, self.irq6.interrupt_requested, self.irq7.interrupt_requested);
+
+  //End Of Interrupt and priority rotation.
+  //Bits 7-5: Rotate, Specific Level, EOI. Bits 2-0: Level.
+  fn operation_control_2(&mut self, register: u8) {
+    let level = register & 0b111;
+    match register >> 5 {
+      0b001 => { //Non-specific EOI
+        if let Some(irq) = self.highest_in_service() {
+          self.isr &= !(1 << irq);
+          debug!("Non-specific EOI for IRQ{}", irq);
+        }
+      },
+      0b011 => { //Specific EOI
+        self.isr &= !(1 << level);
+        debug!("Specific EOI for IRQ{}", level);
+      },
+      0b101 => { //Rotate on non-specific EOI
+        if let Some(irq) = self.highest_in_service() {
+          self.isr &= !(1 << irq);
+          self.lowest_priority = irq;
+          debug!("Rotate on non-specific EOI for IRQ{}", irq);
+        }
+      },
+      0b111 => { //Rotate on specific EOI
+        self.isr &= !(1 << level);
+        self.lowest_priority = level;
+        debug!("Rotate on specific EOI for IRQ{}", level);
+      },
+      0b110 => { //Set priority
+        self.lowest_priority = level;
+        debug!("Set lowest priority to IRQ{}", level);
+      },
+      0b100 => self.rotate_on_auto_eoi = true,
+      0b000 => self.rotate_on_auto_eoi = false,
+      _ => debug!("Got an NOP Operation Control Word 2 command: {:X}", register),
+    }
+  }
+
+  //Bits 6-5: Special mask mode. Bit 2: Poll. Bits 1-0: Read register.
+  fn operation_control_3(&mut self, register: u8) {
+    match (register >> 5) & 0b11 {
+      0b11 => self.special_mask = true,
+      0b10 => self.special_mask = false,
+      _ => {},
+    }
+    self.poll = matches!(register & 0b100, 0b100);
+    if matches!(register & 0b10, 0b10) {
+      self.next_get = match register & 0b1 {
+        0 => RegisterType::IRR,
+        _ => RegisterType::ISR,
+      };
+    }
+    debug!("Got a Operation Control Word 3. Read: {:?}, Poll: {}, Special mask: {}", self.next_get, self.poll, self.special_mask);
+  }
+
+  /// IRQ numbers from highest to lowest priority.
+  fn priority_order(&self) -> impl Iterator<Item = u8> {
+    let highest = (self.lowest_priority + 1) & 0b111;
+    (0..8).map(move |offset| (highest + offset) & 0b111)
+  }
+
+  fn highest_in_service(&self) -> Option<u8> {
+    self.priority_order().find(|irq| self.isr & (1 << irq) != 0)
+  }
+
+  /// The IRQ which would be given to the CPU on the next acknowledge, if any.
+  /// In fully nested mode, an IRQ only gets through if it has higher priority than everything in service.
+  /// In special mask mode, only the in-service IRQs which are also masked stop lower priorities.
+  /// In special fully nested mode, a slave's line being in service doesn't stop another, higher priority request from that slave.
+  fn pending_irq(&self) -> Option<u8> {
+    let requests = self.irr & !self.imr;
+    for irq in self.priority_order() {
+      let bit = 1 << irq;
+      if self.isr & bit != 0 && !self.special_mask {
+        let nested_slave = self.special_fully_nested && self.cascade & bit != 0 && requests & bit != 0;
+        return if nested_slave { Some(irq) } else { None };
+      }
+      if requests & bit != 0 && self.isr & bit == 0 {
+        return Some(irq);
+      }
+    }
+    None
+  }
+
+  fn update_intr(&mut self) {
+    let intr = self.pending_irq().is_some();
+    if intr != self.intr {
+      self.intr = intr;
+      trace!("PIC INTR {}", intr);
+      if let Some(messenger) = &self.messenger {
+        let msg = crate::Msg::CPU(crate::CPUMsg::INTR(intr));
+        messenger.send(msg).unwrap();
+      }
+    }
+  }
+
+  /// The IRQ wins the priority resolution and moves from IRR to ISR.
+  /// Returns None if the request went away before it was acknowledged.
+  fn acknowledge_irq(&mut self) -> Option<u8> {
+    let irq = self.pending_irq()?;
+    self.irr &= !(1 << irq);
+    if let Trigger::Level = self.trigger {
+      self.irr |= self.lines & (1 << irq); //Still held high.
+    }
+    if self.auto_eoi {
+      if self.rotate_on_auto_eoi {
+        self.lowest_priority = irq;
+      }
+    } else {
+      self.isr |= 1 << irq;
+    }
+    Some(irq)
+  }
+
+  /// INTA from the CPU. A request which disappeared before the acknowledge gives a spurious IRQ7,
+  /// without setting its in-service bit.
+  pub fn acknowledge(&mut self) -> u8 {
+    let vector = match self.acknowledge_irq() {
+      Some(irq) => {
+        debug!("PIC IRQ{} acknowledged! This maps to INT {:X}", irq, self.vector_offset + irq);
+        self.vector_offset + irq
+      },
+      None => {
+        debug!("Spurious IRQ7");
+        self.vector_offset + 7
+      },
+    };
+    self.update_intr();
+    vector
+  }
+
+  /// Poll command: instead of an acknowledge, the CPU reads 1000_0LLL if an IRQ is pending, or 0.
+  fn poll_command(&mut self) -> u8 {
+    let result = match self.acknowledge_irq() {
+      Some(irq) => 0b1000_0000 | irq,
+      None => 0,
+    };
+    debug!("PIC Poll {:08b}", result);
+    self.update_intr();
     result
   }
 
-  fn get_interrupted_cpu(&self) -> u8 {
-    let mut result = 0;
-    if self.irq0.interrupted_cpu { result |= 0b1; }
-    if self.irq1.interrupted_cpu { result |= 0b10; }
-    if self.irq2.interrupted_cpu { result |= 0b100; }
-    if self.irq3.interrupted_cpu { result |= 0b1000; }
-    if self.irq4.interrupted_cpu { result |= 0b1_0000; }
-    if self.irq5.interrupted_cpu { result |= 0b10_0000; }
-    if self.irq6.interrupted_cpu { result |= 0b100_0000; }
-    if self.irq7.interrupted_cpu { result |= 0b1000_0000; }
-    debug!("ISR 0: {}, 1: {}, 2: {}, 3: {}, 4: {}, 5: {}, 6: {}, 7: {}",
-          self.irq0.interrupted_cpu, self.irq1.interrupted_cpu, self.irq2.interrupted_cpu, self.irq3.interrupted_cpu,
-          self.irq4.interrupted_cpu, self.irq5.interrupted_cpu, self.irq6.interrupted_cpu, self.irq7.interrupted_cpu);
+  /// Edge triggered IRQs are requested on a low to high transition.
+  /// Level triggered IRQs are requested for as long as the line is high.
+  pub fn raise_irq(&mut self, irq: u8) {
+    let bit = 1 << irq;
+    if self.lines & bit == 0 || matches!(self.trigger, Trigger::Level) {
+      self.irr |= bit;
+    }
+    self.lines |= bit;
+    self.update_intr();
+  }
+
+  pub fn lower_irq(&mut self, irq: u8) {
+    let bit = 1 << irq;
+    self.lines &= !bit;
+    if let Trigger::Level = self.trigger {
+      self.irr &= !bit;
+    }
+    self.update_intr();
+  }
+}
+
+impl Cascade {
+  /// chip_index 0 = master (ports 20-21), 1 = slave (ports A0-A1).
+  fn get_chip(&mut self, chip_index: u8) -> &mut PIC {
+    match (chip_index, &mut self.slave) {
+      (0, _) => &mut self.master,
+      (1, Some(slave)) => slave,
+      _ => unreachable!(),
+    }
+  }
+
+  pub fn in_port_1(&mut self, chip_index: u8) -> u8 {
+    let result = self.get_chip(chip_index).in_port_1(); //A poll can acknowledge an IRQ.
+    self.sync_slave_intr();
     result
   }
-  
-  //End Of Interrupt
-  fn operation_control_2(&mut self, register: u8) {
-    debug!("Got an End Of Interrupt command: {:X}", register);
-    self.irq0.interrupted_cpu = false;
-    self.irq1.interrupted_cpu = false;
-    self.irq2.interrupted_cpu = false;
-    self.irq3.interrupted_cpu = false;
-    self.irq4.interrupted_cpu = false;
-    self.irq5.interrupted_cpu = false;
-    self.irq6.interrupted_cpu = false;
-    self.irq7.interrupted_cpu = false;
-    self.irq0.interrupt_requested = false;
-    self.irq1.interrupt_requested = false;
-    self.irq2.interrupt_requested = false;
-    self.irq3.interrupt_requested = false;
-    self.irq4.interrupt_requested = false;
-    self.irq5.interrupt_requested = false;
-    self.irq6.interrupt_requested = false;
-    self.irq7.interrupt_requested = false;
-  }
-  
-  fn operation_control_3(&mut self, register: u8) {
-    if matches!(register & 0b10, 0) {
-      debug!("Got an NOP Operation Control Word 3 command: {:X}", register);
-      return;
+
+  pub fn out_port_1(&mut self, chip_index: u8, register: u8) {
+    self.get_chip(chip_index).out_port_1(register);
+    self.sync_slave_intr();
+  }
+
+  pub fn out_port_2(&mut self, chip_index: u8, register: u8) {
+    self.get_chip(chip_index).out_port_2(register);
+    self.sync_slave_intr();
+  }
+
+  pub fn get_irqs_enabled(&mut self, chip_index: u8) -> u8 {
+    self.get_chip(chip_index).get_irqs_enabled()
+  }
+
+  /// The slave's INTR output is the master's IRQ2 input.
+  fn sync_slave_intr(&mut self) {
+    let Some(slave) = &self.slave else { return; };
+    if slave.intr {
+      self.master.raise_irq(CASCADE_IRQ);
+    } else {
+      self.master.lower_irq(CASCADE_IRQ);
+    }
+  }
+
+  /// IRQ 0-7 go to the master, IRQ 8-15 to the slave.
+  pub fn raise_irq(&mut self, irq: u8) {
+    match (irq, &mut self.slave) {
+      (0..=7, _) => self.master.raise_irq(irq),
+      (_, Some(slave)) => {
+        slave.raise_irq(irq - 8);
+        self.sync_slave_intr();
+      },
+      (_, None) => error!("IRQ{} raised with no slave PIC", irq),
+    }
+  }
+
+  pub fn lower_irq(&mut self, irq: u8) {
+    match (irq, &mut self.slave) {
+      (0..=7, _) => self.master.lower_irq(irq),
+      (_, Some(slave)) => {
+        slave.lower_irq(irq - 8);
+        self.sync_slave_intr();
+      },
+      (_, None) => error!("IRQ{} lowered with no slave PIC", irq),
     }
-    self.next_get = match register & 0b1 {
-      0 => RegisterType::IRR,
-      _ => RegisterType::ISR,
+  }
+
+  /// The master resolves priority. If the winner is a cascade line, the master puts its number on CAS0-2,
+  /// and the slave with that ID supplies the vector.
+  pub fn acknowledge(&mut self) -> u8 {
+    let vector = match (self.master.acknowledge_irq(), &mut self.slave) {
+      (Some(irq), Some(slave)) if self.master.cascade & (1 << irq) != 0 => {
+        if slave.cascade != irq {
+          error!("No slave PIC with ID {} answered the acknowledge.", irq);
+        }
+        let vector = slave.acknowledge();
+        //The master only latched the slave's request once. Latch it again if the slave still has more to give.
+        self.master.lower_irq(CASCADE_IRQ);
+        self.sync_slave_intr();
+        vector
+      },
+      (Some(irq), _) => {
+        debug!("PIC IRQ{} acknowledged! This maps to INT {:X}", irq, self.master.vector_offset + irq);
+        self.master.vector_offset + irq
+      },
+      (None, _) => {
+        debug!("Spurious IRQ7");
+        self.master.vector_offset + 7
+      },
     };
-    debug!("Got a Operation Control Word 3 to read: {:?}", self.next_get);
+    self.master.update_intr();
+    vector
   }
-  
+
   pub fn process_msg(&mut self, msg: PICMsg) {
     match msg {
-      //IRQ0
-      PICMsg::PIT{select_counter} => {
-        if select_counter == 0 {
-          if self.irq0.enabled && !self.irq0.interrupted_cpu { //Only PIT channel 0 can interrupt on a x86. Unclear about other machines.
-            let interrupt = self.vector_offset + 0;
-            debug!("PIC IRQ0 triggered! This maps to INT {}", interrupt);
-            //Signal the CPU.
-            let msg = crate::Msg::CPU(crate::CPUMsg::Interrupt(interrupt));
-            self.messenger.send(msg).unwrap();
-            self.irq0.interrupted_cpu = true;
-            self.irq0.interrupt_requested = false;
-          } else {
-            self.irq0.interrupt_requested = true;
-          }
-        }
+      PICMsg::RaiseIRQ(irq) => self.raise_irq(irq),
+      PICMsg::LowerIRQ(irq) => self.lower_irq(irq),
+      PICMsg::Acknowledge{socket} => {
+        let vector = self.acknowledge();
+        socket.send(vector).unwrap();
       },
     }
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const ICW1_ICW4_NEEDED: u8 = 0b0001_0001;
+  const ICW1_SINGLE: u8 = 0b0000_0010;
+  const ICW4_8086: u8 = 0b0000_0001;
+  const ICW4_AUTO_EOI: u8 = 0b0000_0010;
+  const ICW4_SPECIAL_FULLY_NESTED: u8 = 0b0001_0000;
+  const NON_SPECIFIC_EOI: u8 = 0b0010_0000;
+  const SPECIFIC_EOI: u8 = 0b0110_0000;
+  const ROTATE_ON_NON_SPECIFIC_EOI: u8 = 0b1010_0000;
+  const READ_ISR: u8 = 0b0000_1011;
+  const POLL: u8 = 0b0000_1100;
+
+  /// The messenger has to stay open for as long as the PIC can send INTR changes.
+  fn single(icw4: u8) -> (Cascade, mpsc::Receiver<crate::Msg>) {
+    let (sender, receiver) = mpsc::channel();
+    let mut pic = start(sender, false);
+    pic.out_port_1(0, ICW1_ICW4_NEEDED | ICW1_SINGLE);
+    pic.out_port_2(0, 0x08);
+    pic.out_port_2(0, icw4);
+    (pic, receiver)
+  }
+
+  /// Programmed the way the AT BIOS does it.
+  fn cascade(master_icw4: u8) -> (Cascade, mpsc::Receiver<crate::Msg>) {
+    let (sender, receiver) = mpsc::channel();
+    let mut pic = start(sender, true);
+    pic.out_port_1(0, ICW1_ICW4_NEEDED);
+    pic.out_port_2(0, 0x08);
+    pic.out_port_2(0, 1 << CASCADE_IRQ);
+    pic.out_port_2(0, master_icw4);
+    pic.out_port_1(1, ICW1_ICW4_NEEDED);
+    pic.out_port_2(1, 0x70);
+    pic.out_port_2(1, CASCADE_IRQ);
+    pic.out_port_2(1, ICW4_8086);
+    (pic, receiver)
+  }
+
+  fn intr(pic: &Cascade) -> bool {
+    pic.master.intr
+  }
+
+  #[test]
+  fn lower_irq_has_priority() {
+    let (mut pic, _receiver) = single(ICW4_8086);
+    pic.raise_irq(4);
+    pic.raise_irq(1);
+    assert_eq!(pic.acknowledge(), 0x09);
+    assert_eq!(pic.acknowledge(), 0x0F, "IRQ1 is in service, so IRQ4 has to wait and the acknowledge is spurious.");
+    pic.out_port_1(0, NON_SPECIFIC_EOI);
+    assert_eq!(pic.acknowledge(), 0x0C);
+  }
+
+  #[test]
+  fn higher_priority_nests() {
+    let (mut pic, _receiver) = single(ICW4_8086);
+    pic.raise_irq(4);
+    assert_eq!(pic.acknowledge(), 0x0C);
+    pic.raise_irq(1);
+    assert!(intr(&pic), "IRQ1 interrupts the IRQ4 handler.");
+    assert_eq!(pic.acknowledge(), 0x09);
+    pic.out_port_1(0, READ_ISR);
+    assert_eq!(pic.in_port_1(0), 0b1_0010);
+    pic.out_port_1(0, NON_SPECIFIC_EOI);
+    assert_eq!(pic.in_port_1(0), 0b1_0000, "A non-specific EOI ends the highest priority IRQ in service.");
+    pic.out_port_1(0, SPECIFIC_EOI | 4);
+    assert_eq!(pic.in_port_1(0), 0);
+  }
+
+  #[test]
+  fn masked_irq_waits() {
+    let (mut pic, _receiver) = single(ICW4_8086);
+    pic.out_port_2(0, 0b1);
+    pic.raise_irq(0);
+    assert!(!intr(&pic));
+    pic.out_port_2(0, 0);
+    assert!(intr(&pic), "The request is still in the IRR once unmasked.");
+    assert_eq!(pic.acknowledge(), 0x08);
+  }
+
+  #[test]
+  fn edge_triggered_needs_a_new_edge() {
+    let (mut pic, _receiver) = single(ICW4_8086 | ICW4_AUTO_EOI);
+    pic.raise_irq(0);
+    assert_eq!(pic.acknowledge(), 0x08);
+    pic.raise_irq(0);
+    assert!(!intr(&pic), "The line is still high from before.");
+    pic.lower_irq(0);
+    pic.raise_irq(0);
+    assert!(intr(&pic));
+  }
+
+  #[test]
+  fn rotate_on_eoi() {
+    let (mut pic, _receiver) = single(ICW4_8086);
+    pic.raise_irq(0);
+    assert_eq!(pic.acknowledge(), 0x08);
+    pic.out_port_1(0, ROTATE_ON_NON_SPECIFIC_EOI);
+    pic.lower_irq(0);
+    pic.raise_irq(0);
+    pic.raise_irq(3);
+    assert_eq!(pic.acknowledge(), 0x0B, "IRQ0 was rotated to the lowest priority.");
+  }
+
+  #[test]
+  fn poll() {
+    let (mut pic, _receiver) = single(ICW4_8086);
+    pic.raise_irq(5);
+    pic.out_port_1(0, POLL);
+    assert_eq!(pic.in_port_1(0), 0b1000_0101);
+    pic.out_port_1(0, POLL);
+    assert_eq!(pic.in_port_1(0), 0, "IRQ5 is now in service.");
+  }
+
+  #[test]
+  fn slave_vectors() {
+    let (mut pic, _receiver) = cascade(ICW4_8086);
+    pic.raise_irq(8);
+    assert!(intr(&pic));
+    assert_eq!(pic.acknowledge(), 0x70);
+    pic.raise_irq(14);
+    pic.raise_irq(1);
+    assert_eq!(pic.acknowledge(), 0x09, "IRQ1 has a higher priority than everything on the slave.");
+    pic.raise_irq(0);
+    assert_eq!(pic.acknowledge(), 0x08);
+  }
+
+  #[test]
+  fn slave_relatches_after_acknowledge() {
+    let (mut pic, _receiver) = cascade(ICW4_8086 | ICW4_AUTO_EOI);
+    pic.out_port_2(1, 0b0100_0000);  //Mask IRQ14 for now.
+    pic.raise_irq(14);
+    pic.raise_irq(11);
+    assert_eq!(pic.acknowledge(), 0x73);
+    pic.out_port_1(1, NON_SPECIFIC_EOI);
+    pic.out_port_2(1, 0);
+    assert!(intr(&pic), "The slave still has IRQ14, so the master latched IRQ2 again.");
+    assert_eq!(pic.acknowledge(), 0x76);
+    pic.out_port_1(1, NON_SPECIFIC_EOI);
+    assert!(!intr(&pic));
+  }
+
+  #[test]
+  fn fully_nested_blocks_the_slave() {
+    let (mut pic, _receiver) = cascade(ICW4_8086);
+    pic.raise_irq(11);
+    assert_eq!(pic.acknowledge(), 0x73);
+    pic.raise_irq(9);
+    assert!(!intr(&pic), "IRQ2 is in service on the master, so nothing more from the slave gets through.");
+  }
+
+  #[test]
+  fn special_fully_nested() {
+    let (mut pic, _receiver) = cascade(ICW4_8086 | ICW4_SPECIAL_FULLY_NESTED);
+    pic.raise_irq(11);
+    assert_eq!(pic.acknowledge(), 0x73);
+    pic.raise_irq(12);
+    assert!(!intr(&pic), "IRQ12 has a lower priority than IRQ11 on the slave.");
+    pic.raise_irq(9);
+    assert!(intr(&pic), "IRQ9 has a higher priority than IRQ11, even though IRQ2 is in service on the master.");
+    assert_eq!(pic.acknowledge(), 0x71);
+    pic.out_port_1(1, NON_SPECIFIC_EOI);
+    pic.out_port_1(1, READ_ISR);
+    assert_eq!(pic.in_port_1(1), 0b1000, "IRQ11 is still in service on the slave.");
+    pic.out_port_1(0, READ_ISR);
+    assert_eq!(pic.in_port_1(0), 0b100, "So the master keeps IRQ2 in service.");
+  }
+}
@@ -2,11 +2,6 @@
 //https://stanislavs.org/helppc/8253.html
 //https://wiki.osdev.org/Pit
 
-use std::sync::{mpsc, Arc};
-use std::sync::atomic::{AtomicU16,Ordering};
-use std::thread;
-use std::time;
-
 use log::{debug, trace};
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -29,94 +24,330 @@ enum Access {
 }
 
 pub struct PIT (pub Controller, pub Controller, pub Controller);
-pub struct Threads (pub thread::JoinHandle<()>, pub thread::JoinHandle<()>, pub thread::JoinHandle<()>);
+
+/// An OUT pin changed level on the given PIT clock.
+pub struct OutputChange {
+  pub select_counter: u8,
+  pub tick: u64,
+  pub output: bool,
+}
 
 pub struct Controller {
   access: Access,
-  to_processor: mpsc::Sender<ProcessorMsg>,
-  output_latch: Arc<AtomicU16>,
+  processor: Processor,
+  output_latch: Option<u16>,    //Set by the Counter Latch command. Frozen until read.
+  status_latch: Option<u8>,     //Set by the Read-back command. Read before the count.
   low_count: Option<u16>,       //This should only be set with set_count(..) flip_flop Low.
   select_counter: u8,
 }
 
-#[derive(Default)]
+//Status byte, as returned by the Read-back command.
+const STATUS_OUTPUT: u8 = 0b1000_0000;
+const STATUS_NULL_COUNT: u8 = 0b0100_0000;  //A count was written, but not yet loaded into the counting element.
+const STATUS_CONTROL_WORD: u8 = 0b0011_1111; //RW, Mode and BCD bits, exactly as written in the control word.
+
 struct Processor {
-  latched: bool,
   mode: Mode,
+  bcd: bool,
   counting_element: u16,
   initial_count_register: u16,
-  output_latch: Arc<AtomicU16>,
-  enabled: bool,
-}
-
-enum ProcessorMsg {
-  NewCount(u16),
-  ControlWord{mode: Mode},
-  SetLatch(bool),
+  status: u8,
+  output: bool,           //OUT pin
+  gate: bool,             //GATE pin
+  gate_triggered: bool,   //GATE had a rising edge since the last clock.
+  load_pending: bool,     //The count register holds a count which is not yet in the counting element.
+  has_count: bool,        //A count was written since the last control word.
+  counting: bool,
+  armed: bool,            //Modes 4 and 5 only strobe OUT once per load.
+  odd_extra_clock: bool,  //Mode 3 with an odd count stays high for one extra clock.
+  ticks: u64,             //CLK pulses so far.
+  output_changes: Vec<(u64, bool)>,  //Not yet collected by advance(..)
+  select_counter: u8,
 }
 
-pub fn start(to_bus: mpsc::Sender<crate::Msg>,
-             from_clock: (mpsc::Receiver<()>, mpsc::Receiver<()>, mpsc::Receiver<()>)) -> PIT {
+/// The PIT is clocked at 1.19 MHz, so it is not run on every tick.
+/// The motherboard calls advance(..) to catch up, and asks ticks_to_next_event() when OUT may next change.
+pub fn start() -> PIT {
   PIT (
-    new_controller(to_bus.clone(), 0, from_clock.0),
-    new_controller(to_bus.clone(), 1, from_clock.1),
-    new_controller(to_bus.clone(), 2, from_clock.2)
+    new_controller(0),
+    new_controller(1),
+    new_controller(2)
   )
 }
 
 /// Datasheet:
 /// Note that the CE (counting_element) cannot be written into; whenever a
 /// count is written, it is written into the CR (count_register).
-fn new_controller(to_bus: mpsc::Sender<crate::Msg>, select_counter: u8, from_clock: mpsc::Receiver<()>) -> Controller {
-  let output_latch_arc = Arc::new(AtomicU16::new(0));
-  let (to_processor, from_controller) = mpsc::channel();
-
-  let mut processor = Processor {
-    output_latch: Arc::clone(&output_latch_arc),
-    ..Default::default()
+fn new_controller(select_counter: u8) -> Controller {
+  let processor = Processor {
+    mode: Default::default(),
+    bcd: false,
+    counting_element: 0,
+    initial_count_register: 0,
+    status: 0,
+    output: false,
+    gate: true,
+    gate_triggered: false,
+    load_pending: false,
+    has_count: false,
+    counting: false,
+    armed: false,
+    odd_extra_clock: false,
+    ticks: 0,
+    output_changes: Vec::new(),
+    select_counter,
   };
-  thread::spawn(move || {
-    while from_clock.recv().is_ok() {  //Wait for next ticks
-      for msg in from_controller.try_iter() { //Don't block, because we check on every tick.
-        match msg {
-          ProcessorMsg::NewCount(count_register) => {
-            if !processor.enabled {
-              debug!("PIT Enabled");
-              processor.enabled = true;
-            }
-            processor.initial_count_register = count_register;
-            processor.counting_element = count_register;
+
+  Controller {
+    access: Default::default(),
+    processor,
+    output_latch: None,
+    status_latch: None,
+    low_count: None,
+    select_counter,
+  }
+}
+
+impl Processor {
+  /// Writing a control word stops the counter until a new count is written.
+  fn control_word(&mut self, mode: Mode, bcd: bool) {
+    self.mode = mode;
+    self.bcd = bcd;
+    self.counting = false;
+    self.load_pending = false;
+    self.has_count = false;
+    self.armed = false;
+    self.odd_extra_clock = false;
+    self.set_output(!matches!(mode, Mode::Interrupt)); //Mode 0 starts low, every other mode starts high.
+  }
+
+  /// Datasheet: the count is loaded into the CE on the next clock pulse (modes 0, 2, 3, 4),
+  /// or on the next clock pulse after a GATE trigger (modes 1 and 5).
+  /// In modes 2 and 3, a new count written while counting only takes effect at the end of the current period.
+  fn new_count(&mut self, count_register: u16) {
+    self.initial_count_register = count_register;
+    self.load_pending = true;
+    self.has_count = true;
+  }
+
+  /// Datasheet: in mode 0, writing the first byte of a 2 byte count stops counting and drives OUT low straight away.
+  /// The count is loaded once the second byte is written.
+  fn first_count_byte(&mut self) {
+    if matches!(self.mode, Mode::Interrupt) {
+      self.counting = false;
+      self.set_output(false);
+    }
+  }
+
+  /// GATE low suspends counting in modes 0, 2, 3 and 4. A rising edge triggers modes 1, 2, 3 and 5.
+  /// In modes 2 and 3, GATE low also forces OUT high immediately.
+  fn set_gate(&mut self, gate: bool) {
+    if gate && !self.gate {
+      self.gate_triggered = true;
+    }
+    self.gate = gate;
+    if !gate && matches!(self.mode, Mode::RateGenerator | Mode::SquareWave) {
+      self.odd_extra_clock = false;
+      self.set_output(true);
+    }
+  }
+
+  /// Copy the CR into the CE.
+  fn load(&mut self) {
+    self.counting_element = self.initial_count_register;
+    self.load_pending = false;
+    self.counting = true;
+    self.status &= !STATUS_NULL_COUNT;
+  }
+
+  /// One CLK pulse.
+  fn tick(&mut self) {
+    let triggered = std::mem::take(&mut self.gate_triggered);
+    match self.mode {
+      //Mode 0: OUT goes low when the count is loaded, and goes high at terminal count until a new count or control word.
+      Mode::Interrupt => {
+        if self.load_pending {
+          self.load();
+          self.set_output(false);
+          return;
+        }
+        if !self.counting || !self.gate { return; }
+        self.counting_element = self.decrement(self.counting_element);
+        if self.counting_element == 0 {
+          self.set_output(true);
+        }
+      },
+      //Mode 1: A GATE trigger loads the count and drives OUT low until terminal count. Retriggerable.
+      Mode::OneShot => {
+        if triggered && self.has_count {
+          self.load();
+          self.set_output(false);
+          return;
+        }
+        if !self.counting { return; }
+        self.counting_element = self.decrement(self.counting_element);
+        if self.counting_element == 0 {
+          self.set_output(true);
+        }
+      },
+      //Mode 2: OUT goes low for one clock when the count reaches 1, then the count reloads.
+      Mode::RateGenerator => {
+        if (self.load_pending && !self.counting) || (triggered && self.has_count) {
+          self.load();
+          self.set_output(true);
+          return;
+        }
+        if !self.counting || !self.gate { return; }
+        self.counting_element = self.decrement(self.counting_element);
+        match self.counting_element {
+          1 => self.set_output(false),
+          0 => {
+            self.load();
+            self.set_output(true);
           },
-          ProcessorMsg::ControlWord{mode} => processor.mode = mode,
-          ProcessorMsg::SetLatch(latch) => if processor.enabled { processor.latched = latch },
+          _ => {},
         }
-      }
-      if processor.enabled {
-        processor.counting_element = processor.counting_element.wrapping_sub(1);
-        if processor.counting_element == 0 {
-          if let Mode::Interrupt = processor.mode {
-            processor.enabled = false;
-            debug!("Interrupting PIC");
-            let msg = crate::Msg::PIC(crate::PICMsg::PIT{select_counter: select_counter});
-            to_bus.send(msg).unwrap();
+      },
+      //Mode 3: Like mode 2, but counting down by two and toggling OUT each time the count expires.
+      //Odd counts are high for (N+1)/2 clocks and low for (N-1)/2 clocks.
+      Mode::SquareWave => {
+        if (self.load_pending && !self.counting) || (triggered && self.has_count) {
+          self.load_square_wave();
+          self.set_output(true);
+          return;
+        }
+        if !self.counting || !self.gate { return; }
+        if self.odd_extra_clock {
+          self.odd_extra_clock = false;
+          self.load_square_wave();
+          self.set_output(false);
+          return;
+        }
+        self.counting_element = self.decrement(self.decrement(self.counting_element));
+        if self.counting_element == 0 {
+          if self.output && self.initial_count_register & 1 == 1 {
+            self.odd_extra_clock = true;
           } else {
-            //Starting over again
-            processor.counting_element = processor.initial_count_register;
+            self.load_square_wave();
+            self.set_output(!self.output);
           }
         }
-        if !processor.latched {
-          processor.output_latch.store(processor.counting_element, Ordering::Relaxed);
+      },
+      //Mode 4: OUT goes low for one clock at terminal count.
+      Mode::SoftwareStrobe => {
+        if !self.output { self.set_output(true); }
+        if self.load_pending {
+          self.load();
+          self.armed = true;
+          return;
+        }
+        if !self.counting || !self.gate { return; }
+        self.counting_element = self.decrement(self.counting_element);
+        if self.counting_element == 0 && self.armed {
+          self.armed = false;
+          self.set_output(false);
+        }
+      },
+      //Mode 5: Like mode 4, but the count is loaded by a GATE trigger. Retriggerable.
+      Mode::HardwareStrobe => {
+        if !self.output { self.set_output(true); }
+        if triggered && self.has_count {
+          self.load();
+          self.armed = true;
+          return;
+        }
+        if !self.counting { return; }
+        self.counting_element = self.decrement(self.counting_element);
+        if self.counting_element == 0 && self.armed {
+          self.armed = false;
+          self.set_output(false);
         }
+      },
+    }
+  }
+
+  /// Mode 3 counts down by two, so odd counts are loaded as count - 1.
+  fn load_square_wave(&mut self) {
+    self.load();
+    self.counting_element &= !1;
+  }
+
+  /// The counting element counts down in binary or in 4 decade BCD.
+  /// A count of 0 is the maximum: 65536 in binary, 10000 in BCD.
+  fn decrement(&self, value: u16) -> u16 {
+    if !self.bcd {
+      return value.wrapping_sub(1);
+    }
+    let mut result = value;
+    for digit in 0..4 {
+      let shift = digit * 4;
+      let nibble = (result >> shift) & 0xF;
+      result &= !(0xF << shift);
+      if nibble == 0 {
+        result |= 9 << shift; //Borrow from the next digit.
+      } else {
+        result |= (nibble - 1) << shift;
+        break;
       }
     }
-  });
+    result
+  }
 
-  Controller {
-    to_processor,
-    access: Default::default(),
-    output_latch: Arc::clone(&output_latch_arc),
-    low_count: None,
-    select_counter,
+  /// Changes are queued with the current tick, for the motherboard to route to the PIC, DMA and speaker.
+  fn set_output(&mut self, output: bool) {
+    if output == self.output {
+      return;
+    }
+    self.output = output;
+    if output {
+      self.status |= STATUS_OUTPUT;
+      trace!("Counter {} OUT rising edge", self.select_counter);
+    } else {
+      self.status &= !STATUS_OUTPUT;
+    }
+    self.output_changes.push((self.ticks, output));
+  }
+
+  /// Clocks needed for the counting element to count down to 0.
+  fn remaining(&self) -> u64 {
+    let value = if self.bcd {
+      (0..4).rev().fold(0, |value, digit| value * 10 + ((self.counting_element >> (digit * 4)) & 0xF) as u64)
+    } else {
+      self.counting_element as u64
+    };
+    match (value, self.bcd) {
+      (0, false) => 0x1_0000,
+      (0, true) => 10_000,
+      (value, _) => value,
+    }
+  }
+
+  /// The earliest number of clocks after which OUT could change. None if it won't change without a port write.
+  /// It may be too early, but never too late.
+  fn ticks_to_next_event(&self) -> Option<u64> {
+    if self.load_pending || self.gate_triggered {
+      return Some(1);
+    }
+    match self.mode {
+      Mode::Interrupt | Mode::OneShot => {
+        if !self.counting || self.output || (matches!(self.mode, Mode::Interrupt) && !self.gate) { return None; }
+        Some(self.remaining())
+      },
+      Mode::RateGenerator => {
+        if !self.counting || !self.gate { return None; }
+        Some(self.remaining().saturating_sub(1).max(1))
+      },
+      Mode::SquareWave => {
+        if !self.counting || !self.gate { return None; }
+        if self.odd_extra_clock { return Some(1); }
+        Some((self.remaining() / 2).max(1))
+      },
+      Mode::SoftwareStrobe | Mode::HardwareStrobe => {
+        if !self.output { return Some(1); }
+        if !self.counting || !self.armed || (matches!(self.mode, Mode::SoftwareStrobe) && !self.gate) { return None; }
+        Some(self.remaining())
+      },
+    }
   }
 }
 
@@ -127,25 +358,67 @@ impl PIT {
       0 => &mut self.0,
       1 => &mut self.1,
       2 => &mut self.2,
-      3 => unimplemented!(),  //TODO 8254 function "Read-back command"
+      3 => return self.read_back(value),
       _ => unreachable!(),
     };
     controller.set_control_word(select_counter, value);
   }
+
+  /// Run every counter up to the given PIT clock, and collect the OUT changes since the last call, oldest first.
+  pub fn advance(&mut self, to_tick: u64) -> Vec<OutputChange> {
+    let mut changes = Vec::new();
+    for controller in [&mut self.0, &mut self.1, &mut self.2] {
+      let processor = &mut controller.processor;
+      while processor.ticks < to_tick {
+        processor.ticks += 1;
+        processor.tick();
+      }
+      changes.extend(processor.output_changes.drain(..).map(|(tick, output)| OutputChange {
+        select_counter: processor.select_counter,
+        tick,
+        output,
+      }));
+    }
+    changes.sort_by_key(|change| change.tick);
+    changes
+  }
+
+  /// How many PIT clocks the motherboard can let pass before calling advance(..) again.
+  pub fn ticks_to_next_event(&self) -> Option<u64> {
+    [&self.0, &self.1, &self.2].into_iter()
+      .filter_map(|controller| controller.processor.ticks_to_next_event())
+      .min()
+  }
+
+  /// 8254 only. Latches the count and/or status of several counters at once.
+  /// Bit 5 = 0: latch count. Bit 4 = 0: latch status. Bits 1-3 select counters 0-2.
+  fn read_back(&mut self, value: u8) {
+    let latch_count = matches!(value & 0b10_0000, 0);
+    let latch_status = matches!(value & 0b1_0000, 0);
+    debug!("Read-back command: count: {}, status: {}", latch_count, latch_status);
+    for (select_counter, controller) in [&mut self.0, &mut self.1, &mut self.2].into_iter().enumerate() {
+      if value & (0b10 << select_counter) == 0 {
+        continue;
+      }
+      if latch_count {
+        controller.latch();
+      }
+      //Only the first status latch counts until it has been read.
+      if latch_status && controller.status_latch.is_none() {
+        controller.status_latch = Some(controller.processor.status);
+      }
+    }
+  }
 }
 
 impl Controller {
   fn set_control_word(&mut self, select_counter: u8, value: u8) {
     if (value & 0b11_0000) >> 4 == 0 {  //Latch mode
-      self.to_processor.send(ProcessorMsg::SetLatch(true)).unwrap();
+      self.latch();
       debug!("Counter {}: Latched!", select_counter);
     }
     else {  //Initialization mode
-      //Hopefully I won't have to build this..
-      if matches!(value & 0b1, 0b1) {
-        unimplemented!("BCD requested for PIT..");
-      }
-      
+      let bcd = matches!(value & 0b1, 0b1);
       let mode = match (value & 0b1110) >> 1 {
         0 => Mode::Interrupt,
         1 => Mode::OneShot,
@@ -154,16 +427,20 @@ impl Controller {
         4 => Mode::SoftwareStrobe,
         5 | _ => Mode::HardwareStrobe,
       };
-      self.to_processor.send(ProcessorMsg::ControlWord{mode}).unwrap();
+      self.processor.control_word(mode, bcd);
+      self.output_latch = None;
+      //Writing a control word leaves the counter with a null count until a new count is loaded.
+      let status = &mut self.processor.status;
+      *status = (*status & STATUS_OUTPUT) | STATUS_NULL_COUNT | (value & STATUS_CONTROL_WORD);
       
       self.access = match (value & 0b11_0000) >> 4 {
-        1 => Access::MSB,
-        2 => Access::LSB,
+        1 => Access::LSB,
+        2 => Access::MSB,
         3 => {self.low_count = None; Access::LSBThenMSB},
         _ => unreachable!(),
       };
 
-      debug!("Counter {}: mode: {:?}, access: {:?}", select_counter, mode, self.access);
+      debug!("Counter {}: mode: {:?}, access: {:?}, bcd: {}", select_counter, mode, self.access, bcd);
     }
   }
 
@@ -178,6 +455,7 @@ impl Controller {
       Access::LSBThenMSB => match self.low_count {
         None => {
           self.low_count = Some(new_count);
+          self.processor.first_count_byte();
           None  //Don't trigger yet. Wait for the next value to be given first.
         },
         Some(low) => {
@@ -188,15 +466,37 @@ impl Controller {
     };
     if let Some(count) = count_register {
       debug!("Counter {}'s count_register was set to {:X}", self.select_counter, count);
-      self.to_processor.send(ProcessorMsg::NewCount(count)).unwrap();
+      self.processor.status |= STATUS_NULL_COUNT;
+      self.processor.new_count(count);
     } else if let Some(count) = self.low_count {
       debug!("Counter {}'s was given a low count {:X}", self.select_counter, count);
     }
   }
 
+  /// Counter 0 and 1 have GATE tied high. Counter 2's GATE is driven by the PPI.
+  pub fn set_gate(&mut self, gate: bool) {
+    self.processor.set_gate(gate);
+  }
+
+  /// The OUT pin, as seen by other devices.
+  pub fn get_output(&self) -> bool {
+    self.processor.output
+  }
+
+  /// Only the first latch command counts, until the latched count has been read.
+  fn latch(&mut self) {
+    if self.output_latch.is_none() {
+      self.output_latch = Some(self.processor.counting_element);
+    }
+  }
+
   pub fn get_count(&mut self) -> u8 {
+    if let Some(status) = self.status_latch.take() {
+      debug!("Read Counter {}'s status {:08b}", self.select_counter, status);
+      return status;
+    }
     let mut release_latch = true;
-    let output_latch = self.output_latch.load(Ordering::Relaxed);
+    let output_latch = self.output_latch.unwrap_or(self.processor.counting_element);
     let count_u8 = {
       match self.access {
         Access::LSB => output_latch & 0xFF,
@@ -217,10 +517,330 @@ impl Controller {
       }
     } as u8;
     if release_latch {
-      self.to_processor.send(ProcessorMsg::SetLatch(false)).unwrap();
+      self.output_latch = None;
     }
     
     debug!("Read Counter {}'s count {:X}", self.select_counter, count_u8);
     count_u8
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  //Control words: counter in bits 6-7, access in bits 4-5, mode in bits 1-3.
+  const COUNTER_0: u8 = 0b0000_0000;
+  const LATCH: u8 = 0b0000_0000;
+  const LSB: u8 = 0b0001_0000;
+  const MSB: u8 = 0b0010_0000;
+  const COUNTER_2: u8 = 0b1000_0000;
+  const LSB_THEN_MSB: u8 = 0b0011_0000;
+  const BCD: u8 = 0b0000_0001;
+  //Read-back commands: bit 5 clear latches the count, bit 4 clear latches the status, bits 1-3 select counters 0-2.
+  const READ_BACK: u8 = 0b1100_0000;
+  const READ_BACK_STATUS_ONLY: u8 = 0b1110_0000;
+  const READ_BACK_COUNTER_0: u8 = 0b0000_0010;
+  const READ_BACK_COUNTER_2: u8 = 0b0000_1000;
+
+  fn mode(mode: u8) -> u8 {
+    mode << 1
+  }
+
+  /// The OUT changes of one counter, as (tick, level), up to the given tick.
+  fn output_changes(pit: &mut PIT, select_counter: u8, to_tick: u64) -> Vec<(u64, bool)> {
+    pit.advance(to_tick).into_iter()
+      .filter(|change| change.select_counter == select_counter)
+      .map(|change| (change.tick, change.output))
+      .collect()
+  }
+
+  fn read_latched_count(pit: &mut PIT) -> u16 {
+    pit.set_control_word(COUNTER_0 | LATCH);
+    let low = pit.0.get_count() as u16;
+    let high = pit.0.get_count() as u16;
+    low | (high << 8)
+  }
+
+  #[test]
+  fn lsb_only_access() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(0));
+    pit.0.set_count(0x34);
+    pit.advance(1);
+    assert_eq!(pit.0.processor.counting_element, 0x0034, "The high byte is zero.");
+    pit.advance(5);
+    pit.set_control_word(COUNTER_0 | LATCH);
+    assert_eq!(pit.0.get_count(), 0x30);
+  }
+
+  #[test]
+  fn msb_only_access() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | MSB | mode(0));
+    pit.0.set_count(0x12);
+    pit.advance(1);
+    assert_eq!(pit.0.processor.counting_element, 0x1200, "The low byte is zero.");
+    pit.advance(2);
+    pit.set_control_word(COUNTER_0 | LATCH);
+    assert_eq!(pit.0.get_count(), 0x11);
+  }
+
+  #[test]
+  fn lsb_then_msb_access() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(0));
+    pit.0.set_count(0x34);
+    pit.0.set_count(0x12);
+    pit.advance(1);
+    assert_eq!(read_latched_count(&mut pit), 0x1234);
+  }
+
+  #[test]
+  fn bcd_counts_down_in_decades() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(0) | BCD);
+    pit.0.set_count(0x00);
+    pit.0.set_count(0x01);
+    pit.advance(1);
+    assert_eq!(read_latched_count(&mut pit), 0x0100);
+    pit.advance(2);
+    assert_eq!(read_latched_count(&mut pit), 0x0099);
+    pit.advance(13);
+    assert_eq!(read_latched_count(&mut pit), 0x0088);
+  }
+
+  #[test]
+  fn bcd_terminal_count() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(0) | BCD);
+    pit.0.set_count(0x10);
+    assert_eq!(output_changes(&mut pit, 0, 20), [(11, true)], "0x10 is ten clocks in BCD, not sixteen.");
+  }
+
+  #[test]
+  fn bcd_zero_is_ten_thousand() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(0) | BCD);
+    pit.0.set_count(0x00);
+    pit.0.set_count(0x00);
+    pit.advance(2);
+    assert_eq!(read_latched_count(&mut pit), 0x9999);
+    assert_eq!(output_changes(&mut pit, 0, 20_000), [(10_001, true)]);
+  }
+
+  #[test]
+  fn read_back_status() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(3));
+    pit.set_control_word(READ_BACK_STATUS_ONLY | READ_BACK_COUNTER_0);
+    assert_eq!(pit.0.get_count(), STATUS_OUTPUT | STATUS_NULL_COUNT | LSB_THEN_MSB | mode(3), "No count has been loaded yet.");
+
+    pit.0.set_count(0x00);
+    pit.0.set_count(0x10);
+    pit.advance(1);
+    pit.set_control_word(READ_BACK_STATUS_ONLY | READ_BACK_COUNTER_0);
+    assert_eq!(pit.0.get_count(), STATUS_OUTPUT | LSB_THEN_MSB | mode(3), "The count has been loaded.");
+  }
+
+  #[test]
+  fn read_back_status_then_count() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(0));
+    pit.0.set_count(0x34);
+    pit.0.set_count(0x12);
+    pit.advance(1);
+    pit.set_control_word(READ_BACK | READ_BACK_COUNTER_0);
+    pit.advance(11);
+    assert_eq!(pit.0.get_count(), LSB_THEN_MSB | mode(0), "The status comes first, with OUT low.");
+    assert_eq!(pit.0.get_count(), 0x34, "The count was latched before counting on.");
+    assert_eq!(pit.0.get_count(), 0x12);
+    assert_eq!(read_latched_count(&mut pit), 0x1234 - 10);
+  }
+
+  #[test]
+  fn read_back_latches_several_counters() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(2));
+    pit.set_control_word(COUNTER_2 | MSB | mode(4) | BCD);
+    pit.set_control_word(READ_BACK_STATUS_ONLY | READ_BACK_COUNTER_0 | READ_BACK_COUNTER_2);
+    pit.set_control_word(COUNTER_0 | LSB | mode(3));
+    assert_eq!(pit.0.get_count(), STATUS_OUTPUT | STATUS_NULL_COUNT | LSB | mode(2), "The first status latched is kept until read.");
+    assert_eq!(pit.1.status_latch, None);
+    assert_eq!(pit.2.get_count(), STATUS_OUTPUT | STATUS_NULL_COUNT | MSB | mode(4) | BCD);
+  }
+
+  #[test]
+  fn first_latch_holds_until_read() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(0));
+    pit.0.set_count(0x00);
+    pit.0.set_count(0x01);
+    pit.advance(1);
+    pit.set_control_word(COUNTER_0 | LATCH);
+    pit.advance(6);
+    assert_eq!(read_latched_count(&mut pit), 0x0100, "The second latch command is ignored.");
+    assert_eq!(read_latched_count(&mut pit), 0x0100 - 5);
+  }
+
+  #[test]
+  fn mode_0_interrupt_on_terminal_count() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(0));
+    pit.0.set_count(5);
+    assert_eq!(output_changes(&mut pit, 0, 100), [(6, true)], "Loaded on clock 1, then five clocks to terminal count.");
+    pit.0.set_count(2);
+    assert_eq!(output_changes(&mut pit, 0, 200), [(101, false), (103, true)], "A new count drives OUT low again on the next clock.");
+  }
+
+  #[test]
+  fn mode_0_first_byte_stops_counting() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB_THEN_MSB | mode(0));
+    pit.0.set_count(5);
+    pit.0.set_count(0);
+    assert_eq!(output_changes(&mut pit, 0, 100), [(6, true)]);
+    pit.0.set_count(3);
+    assert_eq!(output_changes(&mut pit, 0, 200), [(100, false)], "OUT goes low without waiting for a clock, and stays low.");
+    assert_eq!(pit.ticks_to_next_event(), None, "Nothing counts until the second byte.");
+    pit.0.set_count(0);
+    assert_eq!(output_changes(&mut pit, 0, 300), [(204, true)]);
+  }
+
+  #[test]
+  fn mode_1_one_shot() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(1));
+    pit.0.set_count(3);
+    assert_eq!(output_changes(&mut pit, 0, 10), [(0, true)], "Nothing happens without a GATE trigger.");
+    pit.0.set_gate(false);
+    pit.0.set_gate(true);
+    assert_eq!(output_changes(&mut pit, 0, 100), [(11, false), (14, true)]);
+  }
+
+  #[test]
+  fn mode_2_rate_generator() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(2));
+    pit.0.set_count(3);
+    assert_eq!(output_changes(&mut pit, 0, 7), [(0, true), (3, false), (4, true), (6, false), (7, true)], "Low for one clock out of every three.");
+  }
+
+  #[test]
+  fn mode_2_new_count_waits_for_the_period() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(2));
+    pit.0.set_count(4);
+    pit.advance(2);
+    pit.0.set_count(2);
+    assert_eq!(output_changes(&mut pit, 0, 7), [(4, false), (5, true), (6, false), (7, true)]);
+  }
+
+  #[test]
+  fn mode_3_even_square_wave() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(3));
+    pit.0.set_count(4);
+    assert_eq!(output_changes(&mut pit, 0, 7), [(0, true), (3, false), (5, true), (7, false)]);
+  }
+
+  #[test]
+  fn mode_3_odd_square_wave() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(3));
+    pit.0.set_count(5);
+    assert_eq!(output_changes(&mut pit, 0, 9), [(0, true), (4, false), (6, true), (9, false)], "High for three clocks, low for two.");
+  }
+
+  #[test]
+  fn mode_4_software_strobe() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(4));
+    pit.0.set_count(3);
+    assert_eq!(output_changes(&mut pit, 0, 1000), [(0, true), (4, false), (5, true)], "Strobes once, then keeps counting without strobing.");
+  }
+
+  #[test]
+  fn mode_5_hardware_strobe() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(5));
+    pit.0.set_count(3);
+    assert_eq!(output_changes(&mut pit, 0, 10), [(0, true)], "Nothing happens without a GATE trigger.");
+    pit.0.set_gate(false);
+    pit.0.set_gate(true);
+    assert_eq!(output_changes(&mut pit, 0, 1000), [(14, false), (15, true)]);
+  }
+
+  #[test]
+  fn next_event_is_never_late() {
+    for control_word in [mode(0), mode(2), mode(3), mode(4)] {
+      let mut every_tick = start();
+      let mut by_event = start();
+      for pit in [&mut every_tick, &mut by_event] {
+        pit.set_control_word(COUNTER_0 | LSB | control_word);
+        pit.0.set_count(7);
+      }
+      let expected = output_changes(&mut every_tick, 0, 50);
+      let mut changes = Vec::new();
+      let mut tick = 0;
+      while let Some(ticks) = by_event.ticks_to_next_event() {
+        tick += ticks;
+        if tick > 50 { break; }
+        changes.extend(output_changes(&mut by_event, 0, tick));
+      }
+      changes.extend(output_changes(&mut by_event, 0, 50));
+      assert_eq!(changes, expected, "Mode {}", control_word >> 1);
+    }
+  }
+
+  #[test]
+  fn gate_low_suspends_mode_0() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(0));
+    pit.0.set_count(5);
+    pit.advance(3);
+    pit.0.set_gate(false);
+    assert_eq!(output_changes(&mut pit, 0, 10), []);
+    pit.0.set_gate(true);
+    assert_eq!(output_changes(&mut pit, 0, 100), [(13, true)], "Two clocks counted before GATE went low, three after.");
+  }
+
+  #[test]
+  fn gate_low_forces_mode_3_high() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(3));
+    pit.0.set_count(4);
+    assert_eq!(output_changes(&mut pit, 0, 4), [(0, true), (3, false)]);
+    pit.0.set_gate(false);
+    assert!(pit.0.get_output());
+    assert_eq!(output_changes(&mut pit, 0, 10), [(4, true)]);
+    pit.0.set_gate(true);
+    assert_eq!(output_changes(&mut pit, 0, 15), [(13, false), (15, true)], "The rising edge reloads the count.");
+  }
+
+  #[test]
+  fn gate_retriggers_mode_1() {
+    let mut pit = start();
+    pit.set_control_word(COUNTER_0 | LSB | mode(1));
+    pit.0.set_count(4);
+    pit.0.set_gate(false);
+    pit.0.set_gate(true);
+    assert_eq!(output_changes(&mut pit, 0, 3), [(0, true), (1, false)]);
+    pit.0.set_gate(false);
+    pit.0.set_gate(true);
+    assert_eq!(output_changes(&mut pit, 0, 100), [(8, true)], "OUT stays low for four clocks after the second trigger.");
+  }
+
+  #[test]
+  fn gate_only_affects_its_own_counter() {
+    let mut pit = start();
+    for counter in [COUNTER_0, COUNTER_2] {
+      pit.set_control_word(counter | LSB | mode(0));
+    }
+    pit.0.set_count(5);
+    pit.2.set_count(5);
+    pit.2.set_gate(false);
+    let changes: Vec<_> = pit.advance(100).into_iter().map(|change| (change.select_counter, change.tick)).collect();
+    assert_eq!(changes, [(0, 6)]);
+  }
+}
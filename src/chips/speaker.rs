@@ -0,0 +1,211 @@
+//PC Speaker
+//https://wiki.osdev.org/PC_Speaker
+//
+//The speaker is driven by PIT counter 2's OUT, ANDed with port 61h bit 1.
+//When counter 2's GATE is low, OUT2 sits high, so toggling bit 1 directly drives the speaker cone.
+//The motherboard reports every change of the signal. The time it spends high is averaged over each sample.
+
+use crate::clock::MASTER_HZ;
+
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::fs::File;
+
+use log::{debug, error};
+
+const AMPLITUDE: f32 = 8000.0;  //Leave plenty of headroom. A square wave at full scale is painfully loud.
+const BUFFER_SAMPLES: usize = 1024;
+
+/// Receives mono 16 bit samples at the configured sample rate.
+pub trait AudioSink: Send {
+  fn write_samples(&mut self, samples: &[i16]);
+}
+
+/// Writes a mono 16 bit PCM WAV file.
+/// The header is rewritten after every block, so the file is valid even if the emulator is killed.
+/// If a write fails, the error is logged and recording stops, leaving the file valid up to the last good block.
+pub struct WavSink {
+  file: File,
+  data_len: u32,
+  stopped: bool,
+}
+
+pub struct Speaker {
+  sink: Box<dyn AudioSink>,
+  sample_rate: u64,
+  input: bool,        //OUT2 AND port 61h bit 1
+  last_change: u64,   //Master clock cycle of the last input change.
+  high_cycles: u64,   //Master clock cycles the input was high during the current sample.
+  sample_start: u64,
+  samples: u64,       //Samples taken so far.
+  buffer: Vec<i16>,
+}
+
+pub fn wav(path: &str, sample_rate: u32) -> io::Result<WavSink> {
+  let mut sink = WavSink {
+    file: File::create(path)?,
+    data_len: 0,
+    stopped: false,
+  };
+  let mut header = Vec::with_capacity(44);
+  header.extend_from_slice(b"RIFF");
+  header.extend_from_slice(&36u32.to_le_bytes());  //Patched as data comes in.
+  header.extend_from_slice(b"WAVEfmt ");
+  header.extend_from_slice(&16u32.to_le_bytes());
+  header.extend_from_slice(&1u16.to_le_bytes());  //PCM
+  header.extend_from_slice(&1u16.to_le_bytes());  //Mono
+  header.extend_from_slice(&sample_rate.to_le_bytes());
+  header.extend_from_slice(&(sample_rate * 2).to_le_bytes());  //Bytes per second
+  header.extend_from_slice(&2u16.to_le_bytes());  //Block align
+  header.extend_from_slice(&16u16.to_le_bytes()); //Bits per sample
+  header.extend_from_slice(b"data");
+  header.extend_from_slice(&0u32.to_le_bytes());  //Patched as data comes in.
+  sink.file.write_all(&header)?;
+  Ok(sink)
+}
+
+impl WavSink {
+  fn append(&mut self, samples: &[i16]) -> io::Result<()> {
+    let bytes: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    self.file.seek(SeekFrom::End(0))?;
+    self.file.write_all(&bytes)?;
+    self.data_len += bytes.len() as u32;
+    self.file.seek(SeekFrom::Start(4))?;
+    self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+    self.file.seek(SeekFrom::Start(40))?;
+    self.file.write_all(&self.data_len.to_le_bytes())?;
+    Ok(())
+  }
+}
+
+impl AudioSink for WavSink {
+  fn write_samples(&mut self, samples: &[i16]) {
+    if self.stopped {
+      return;
+    }
+    if let Err(err) = self.append(samples) {
+      error!("Stopped recording the speaker: {}", err);
+      self.stopped = true;
+    }
+  }
+}
+
+pub fn start(sink: Box<dyn AudioSink>, sample_rate: u32) -> Speaker {
+  debug!("Speaker sampling at {} Hz", sample_rate);
+  Speaker {
+    sink,
+    sample_rate: sample_rate as u64,
+    input: false,
+    last_change: 0,
+    high_cycles: 0,
+    sample_start: 0,
+    samples: 0,
+    buffer: Vec::with_capacity(BUFFER_SAMPLES),
+  }
+}
+
+impl Speaker {
+  /// The speaker input changed level at the given master clock cycle.
+  pub fn set_input(&mut self, time: u64, input: bool) {
+    let time = time.max(self.last_change);
+    if self.input {
+      self.high_cycles += time - self.last_change;
+    }
+    self.last_change = time;
+    self.input = input;
+  }
+
+  /// Master clock cycle at which the next sample is due.
+  pub fn next_sample_time(&self) -> u64 {
+    (self.samples + 1) * MASTER_HZ / self.sample_rate
+  }
+
+  /// Average over the time since the last sample, so tones above the sample rate don't alias too badly.
+  pub fn sample(&mut self, now: u64) {
+    self.set_input(now, self.input);
+    let cycles = (now - self.sample_start).max(1);
+    self.buffer.push((self.high_cycles as f32 / cycles as f32 * AMPLITUDE) as i16);
+    self.high_cycles = 0;
+    self.sample_start = now;
+    self.samples += 1;
+    if self.buffer.len() == BUFFER_SAMPLES {
+      self.flush();
+    }
+  }
+
+  fn flush(&mut self) {
+    if !self.buffer.is_empty() {
+      self.sink.write_samples(&self.buffer);
+      self.buffer.clear();
+    }
+  }
+}
+
+/// The last partial block would otherwise be lost when the emulator stops.
+impl Drop for Speaker {
+  fn drop(&mut self) {
+    self.flush();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  const SAMPLE_RATE: u32 = 44_100;
+
+  struct Capture {
+    samples: Arc<Mutex<Vec<i16>>>,
+  }
+
+  impl AudioSink for Capture {
+    fn write_samples(&mut self, samples: &[i16]) {
+      self.samples.lock().unwrap().extend_from_slice(samples);
+    }
+  }
+
+  #[test]
+  fn square_wave_duty_cycle_sets_the_level() {
+    let samples = Arc::new(Mutex::new(Vec::new()));
+    let mut speaker = start(Box::new(Capture{samples: samples.clone()}), SAMPLE_RATE);
+    //High for the first quarter of every sample.
+    for _ in 0..BUFFER_SAMPLES + 10 {
+      let start = speaker.sample_start;
+      let end = speaker.next_sample_time();
+      speaker.set_input(start, true);
+      speaker.set_input(start + (end - start) / 4, false);
+      speaker.sample(end);
+    }
+    assert_eq!(samples.lock().unwrap().len(), BUFFER_SAMPLES, "Samples are written a block at a time.");
+    drop(speaker);
+
+    let samples = samples.lock().unwrap();
+    assert_eq!(samples.len(), BUFFER_SAMPLES + 10, "The rest are written when the speaker is dropped.");
+    for &sample in samples.iter() {
+      assert!((1990..=2000).contains(&sample), "{} is not a quarter of full scale", sample);
+    }
+  }
+
+  #[test]
+  fn wav_header_counts_the_data() {
+    let path = std::env::temp_dir().join(format!("remu-speaker-{}.wav", std::process::id()));
+    let mut sink = wav(path.to_str().unwrap(), SAMPLE_RATE).unwrap();
+    sink.write_samples(&[0, 1, -1]);
+    sink.write_samples(&[0x1234]);
+    drop(sink);
+
+    let file = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let u32_at = |offset: usize| u32::from_le_bytes(file[offset..offset + 4].try_into().unwrap());
+    assert_eq!(file.len(), 44 + 8);
+    assert_eq!(&file[0..4], b"RIFF");
+    assert_eq!(u32_at(4), 36 + 8);
+    assert_eq!(&file[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(24), SAMPLE_RATE);
+    assert_eq!(&file[36..40], b"data");
+    assert_eq!(u32_at(40), 8);
+    assert_eq!(&file[44..], &[0x00, 0x00, 0x01, 0x00, 0xFF, 0xFF, 0x34, 0x12]);
+  }
+}
@@ -0,0 +1,986 @@
+//IBM EGA and VGA. The VGA is built on the EGA, so one card covers both.
+//http://www.osdever.net/FreeVGA/vga/vga.htm
+//
+//The card has its own BIOS at C0000 (roms/ibm-vga-1986-10-27.rom, or roms/ibm-ega-1984-09-13.rom), found by the system BIOS ROM scan,
+//which sets up every mode itself.
+//Memory is 4 planes of 64KB, or 16KB to 64KB on the EGA. The CPU sees them through a window at A0000, B0000 or B8000,
+//either chained (odd/even for text, 4 way for 256 colors) or all at once, using the latches and write modes 0-3.
+//
+//The EGA has no DAC: the attribute controller's 6 bit palette goes straight to the monitor. It has no chain 4 or 256 color mode,
+//its registers are write only, and 3C2 bit 4 reads back its configuration switches instead of the monitor sense.
+//
+//3C0/3C1: attribute controller. 3C2: miscellaneous output (write), input status 0 (read). 3C4/3C5: sequencer.
+//3C6-3C9: DAC. 3CA/3CC: feature control and miscellaneous output read back. 3CE/3CF: graphics controller.
+//3B4/3B5 or 3D4/3D5: CRTC, and 3BA or 3DA: input status 1, depending on miscellaneous output bit 0.
+
+use super::cga;
+use super::crtc::{Cursor, TextScreen, CHARACTER_BLINK_FRAMES};
+use super::framebuffer::{self, Framebuffer};
+use super::memory1mb::MemoryDevice;
+use crate::clock::MASTER_HZ;
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use log::debug;
+
+pub const ROM_PATH: &str = "roms/ibm-vga-1986-10-27.rom";
+pub const EGA_ROM_PATH: &str = "roms/ibm-ega-1984-09-13.rom";
+pub const ROM_ADDRESS: usize = 0xC_0000;
+pub const MEMORY_START: usize = 0xA_0000;
+pub const MEMORY_WINDOW: usize = 0x2_0000;
+
+const DOT_HZ: [u64; 2] = [25_175_000, 28_322_000];
+const EGA_DOT_HZ: [u64; 2] = [MASTER_HZ, 16_257_000];
+//Switches 1-4, as read back through input status 0. 1001 is an Enhanced Color Display in its 350 line mode, as the primary display.
+const EGA_SWITCHES: u8 = 0b1001;
+const CURSOR_BLINK_FRAMES: u64 = 16;
+const SENSE_LEVEL: u16 = 0x4E;  //The color monitor comparator trips when the DAC output adds up to this.
+
+//Sequencer
+const CLOCKING_MODE: usize = 0x01;
+const MAP_MASK: usize = 0x02;
+const CHARACTER_MAP_SELECT: usize = 0x03;
+const MEMORY_MODE: usize = 0x04;
+
+//Graphics controller
+const SET_RESET: usize = 0x00;
+const ENABLE_SET_RESET: usize = 0x01;
+const COLOR_COMPARE: usize = 0x02;
+const DATA_ROTATE: usize = 0x03;
+const READ_MAP_SELECT: usize = 0x04;
+const GRAPHICS_MODE: usize = 0x05;
+const MISCELLANEOUS: usize = 0x06;
+const COLOR_DONT_CARE: usize = 0x07;
+const BIT_MASK: usize = 0x08;
+
+//Attribute controller. 0x00-0x0F are the palette.
+const MODE_CONTROL: usize = 0x10;
+const COLOR_PLANE_ENABLE: usize = 0x12;
+const COLOR_SELECT: usize = 0x14;
+const PALETTE_ADDRESS_SOURCE: u8 = 0b10_0000;  //In the index. Clear while the palette is being loaded, which blanks the screen.
+
+//CRTC
+const HORIZONTAL_TOTAL: usize = 0x00;
+const END_HORIZONTAL_DISPLAY: usize = 0x01;
+const VERTICAL_TOTAL: usize = 0x06;
+const OVERFLOW: usize = 0x07;
+const MAXIMUM_SCAN_LINE: usize = 0x09;
+const CURSOR_START: usize = 0x0A;
+const CURSOR_END: usize = 0x0B;
+const START_ADDRESS_HIGH: usize = 0x0C;
+const START_ADDRESS_LOW: usize = 0x0D;
+const CURSOR_LOCATION_HIGH: usize = 0x0E;
+const CURSOR_LOCATION_LOW: usize = 0x0F;
+const VERTICAL_RETRACE_START: usize = 0x10;
+const VERTICAL_RETRACE_END: usize = 0x11;
+const VERTICAL_DISPLAY_END: usize = 0x12;
+const OFFSET: usize = 0x13;
+const UNDERLINE_LOCATION: usize = 0x14;
+const CRTC_MODE_CONTROL: usize = 0x17;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Model {
+  Ega,
+  Vga,
+}
+
+struct Card {
+  model: Model,
+  planes: Vec<[u8; 4]>,
+  latches: [u8; 4],
+  miscellaneous_output: u8,
+  feature_control: u8,
+  sequencer_index: usize,
+  sequencer: [u8; 5],
+  graphics_index: usize,
+  graphics: [u8; 9],
+  attribute_index: u8,
+  attribute: [u8; 0x15],
+  attribute_data_next: bool,  //3C0 alternates between index and data. Reading input status 1 resets it to index.
+  crtc_index: usize,
+  crtc: [u8; 0x19],
+  dac: [[u8; 3]; 256],
+  dac_mask: u8,
+  dac_read_index: u8,
+  dac_write_index: u8,
+  dac_reading: bool,
+  dac_component: usize,   //Each entry is written or read as red, green, blue.
+  dac_latch: [u8; 3],
+}
+
+pub struct Vga {
+  card: Arc<Mutex<Card>>,
+}
+
+/// The A0000-BFFFF window which is installed into the memory map.
+/// The graphics controller decides which part of it the card actually answers in.
+pub struct VGAMemory {
+  card: Arc<Mutex<Card>>,
+}
+
+/// memory_kb is 64, 128 or 256 on the EGA. The VGA always has 256.
+pub fn start(model: Model, memory_kb: usize) -> Vga {
+  Vga {
+    card: Arc::new(Mutex::new(Card {
+      model,
+      planes: vec![[0; 4]; memory_kb * 1024 / 4],
+      latches: [0; 4],
+      miscellaneous_output: 0,
+      feature_control: 0,
+      sequencer_index: 0,
+      sequencer: [0; 5],
+      graphics_index: 0,
+      graphics: [0; 9],
+      attribute_index: 0,
+      attribute: [0; 0x15],
+      attribute_data_next: false,
+      crtc_index: 0,
+      crtc: [0; 0x19],
+      dac: [[0; 3]; 256],
+      dac_mask: 0xFF,
+      dac_read_index: 0,
+      dac_write_index: 0,
+      dac_reading: false,
+      dac_component: 0,
+      dac_latch: [0; 3],
+    })),
+  }
+}
+
+impl Vga {
+  fn card(&self) -> MutexGuard<'_, Card> {
+    self.card.lock().unwrap()
+  }
+
+  /// To be mapped into the memory map at MEMORY_START, for MEMORY_WINDOW bytes.
+  pub fn video_memory(&self) -> VGAMemory {
+    VGAMemory {
+      card: Arc::clone(&self.card),
+    }
+  }
+
+  /// The CRTC and input status 1 move between 3Bx and 3Dx, so the card can sit alongside an MDA or a CGA.
+  pub fn handles_port(&self, port: u16) -> bool {
+    let card = self.card();
+    let color = matches!(card.miscellaneous_output & 0b1, 0b1);
+    if card.model == Model::Ega && matches!(port, 0x3C6..=0x3C9) {
+      return false;  //No DAC.
+    }
+    match port {
+      0x3C0..=0x3CF => true,
+      0x3B4 | 0x3B5 | 0x3BA => !color,
+      0x3D4 | 0x3D5 | 0x3DA => color,
+      _ => false,
+    }
+  }
+
+  pub fn out_byte(&mut self, port: u16, value: u8) {
+    let mut card = self.card();
+    match port {
+      0x3C0 => card.write_attribute(value),
+      0x3C2 => {
+        card.miscellaneous_output = value;
+        debug!("VGA miscellaneous output {:08b}", value);
+      },
+      0x3C4 => card.sequencer_index = value as usize,
+      0x3C5 => card.write_sequencer(value),
+      0x3C6 => card.dac_mask = value,
+      0x3C7 => {
+        card.dac_read_index = value;
+        card.dac_reading = true;
+        card.dac_component = 0;
+      },
+      0x3C8 => {
+        card.dac_write_index = value;
+        card.dac_reading = false;
+        card.dac_component = 0;
+      },
+      0x3C9 => card.write_dac(value),
+      0x3CE => card.graphics_index = value as usize,
+      0x3CF => card.write_graphics(value),
+      0x3B4 | 0x3D4 => card.crtc_index = value as usize,
+      0x3B5 | 0x3D5 => card.write_crtc(value),
+      0x3BA | 0x3DA => card.feature_control = value,
+      _ => debug!("VGA port {:X} got {:X}. Nothing is there.", port, value),
+    }
+  }
+
+  pub fn in_byte(&mut self, port: u16, now: u64) -> u8 {
+    let mut card = self.card();
+    match port {
+      0x3C2 | 0x3BA | 0x3DA => {},
+      _ if card.model == Model::Ega => return 0xFF,  //Only the status registers can be read.
+      _ => {},
+    }
+    match port {
+      0x3C0 => card.attribute_index,
+      0x3C1 => card.attribute.get((card.attribute_index & 0x1F) as usize).copied().unwrap_or(0),
+      0x3C2 => card.get_input_status_0(),
+      0x3C4 => card.sequencer_index as u8,
+      0x3C5 => card.sequencer.get(card.sequencer_index).copied().unwrap_or(0xFF),
+      0x3C6 => card.dac_mask,
+      0x3C7 => if card.dac_reading { 0b11 } else { 0b00 },
+      0x3C8 => card.dac_write_index,
+      0x3C9 => card.read_dac(),
+      0x3CA => card.feature_control,
+      0x3CC => card.miscellaneous_output,
+      0x3CE => card.graphics_index as u8,
+      0x3CF => card.graphics.get(card.graphics_index).copied().unwrap_or(0xFF),
+      0x3B4 | 0x3D4 => card.crtc_index as u8,
+      0x3B5 | 0x3D5 => card.crtc.get(card.crtc_index).copied().unwrap_or(0xFF),
+      0x3BA | 0x3DA => card.get_input_status_1(now),
+      _ => 0xFF,
+    }
+  }
+
+  /// None when the screen is off, or in a graphics mode.
+  pub fn text_screen(&self) -> Option<TextScreen> {
+    let card = self.card();
+    if !card.display_on() || card.graphics_mode() {
+      return None;
+    }
+    Some(card.text_screen())
+  }
+
+  /// Length of a frame in master clock cycles, which is 60 or 70 Hz in the BIOS modes.
+  pub fn frame_time(&self) -> u64 {
+    self.card().frame_time()
+  }
+
+  /// Draw the screen as the monitor would show it at the given master clock cycle, which sets the blink phase.
+  pub fn render(&self, time: u64) -> Framebuffer {
+    let card = self.card();
+    let frame = card.frame_number(time);
+    if !card.display_on() {
+      let (width, height) = card.screen_size();
+      return framebuffer::new(width, height);
+    }
+    if card.graphics_mode() {
+      card.render_graphics()
+    } else {
+      card.render_text(frame)
+    }
+  }
+}
+
+impl Card {
+  fn write_attribute(&mut self, value: u8) {
+    if self.attribute_data_next {
+      let index = (self.attribute_index & 0x1F) as usize;
+      if let Some(register) = self.attribute.get_mut(index) {
+        *register = value;
+      }
+    } else {
+      self.attribute_index = value & 0x3F;
+    }
+    self.attribute_data_next = !self.attribute_data_next;
+  }
+
+  fn write_sequencer(&mut self, value: u8) {
+    let index = self.sequencer_index;
+    match self.sequencer.get_mut(index) {
+      Some(register) => *register = value,
+      None => debug!("VGA sequencer register {:X} doesn't exist", index),
+    }
+  }
+
+  fn write_graphics(&mut self, value: u8) {
+    let index = self.graphics_index;
+    match self.graphics.get_mut(index) {
+      Some(register) => *register = value,
+      None => debug!("VGA graphics register {:X} doesn't exist", index),
+    }
+  }
+
+  /// Vertical retrace end bit 7 write protects registers 0-7, apart from the line compare bit in the overflow register.
+  fn write_crtc(&mut self, value: u8) {
+    let index = self.crtc_index;
+    let protected = self.model == Model::Vga && matches!(self.crtc[VERTICAL_RETRACE_END] & 0x80, 0x80);
+    match index {
+      OVERFLOW if protected => self.crtc[OVERFLOW] = (self.crtc[OVERFLOW] & !0b1_0000) | (value & 0b1_0000),
+      0..=7 if protected => {},
+      _ => match self.crtc.get_mut(index) {
+        Some(register) => *register = value,
+        None => debug!("VGA CRTC register {:X} doesn't exist", index),
+      },
+    }
+  }
+
+  fn write_dac(&mut self, value: u8) {
+    self.dac_latch[self.dac_component] = value & 0x3F;
+    self.dac_component += 1;
+    if self.dac_component == 3 {
+      self.dac[self.dac_write_index as usize] = self.dac_latch;
+      self.dac_write_index = self.dac_write_index.wrapping_add(1);
+      self.dac_component = 0;
+    }
+  }
+
+  fn read_dac(&mut self) -> u8 {
+    let value = self.dac[self.dac_read_index as usize][self.dac_component];
+    self.dac_component += 1;
+    if self.dac_component == 3 {
+      self.dac_read_index = self.dac_read_index.wrapping_add(1);
+      self.dac_component = 0;
+    }
+    value
+  }
+
+  /// Bit 4: Switch sense. The VGA BIOS loads the DAC with test levels to find out what kind of monitor is attached.
+  /// This answers as a color monitor would. On the EGA, the clock select bits pick which configuration switch is read.
+  fn get_input_status_0(&self) -> u8 {
+    if self.model == Model::Ega {
+      let switch = (self.miscellaneous_output >> 2) & 0b11;
+      return if EGA_SWITCHES & (0b1000 >> switch) != 0 { 0b1_0000 } else { 0 };
+    }
+    let level: u16 = self.dac[0].iter().map(|&value| value as u16).sum();
+    if level >= SENSE_LEVEL { 0 } else { 0b1_0000 }
+  }
+
+  /// Bit 0: Not in the display area. Bit 3: Vertical retrace.
+  fn get_input_status_1(&mut self, now: u64) -> u8 {
+    self.attribute_data_next = false;
+    let (display_enable, vertical_retrace) = self.raster_status(now);
+    let mut result = 0;
+    if !display_enable { result |= 0b1 }
+    if vertical_retrace { result |= 0b1000 }
+    result
+  }
+
+  /// Where a CPU address falls in the window the graphics controller has selected.
+  fn window_offset(&self, addr: usize) -> Option<usize> {
+    if !matches!(self.miscellaneous_output & 0b10, 0b10) {
+      return None;  //CPU access to video memory is disabled.
+    }
+    let (start, size) = match (self.graphics[MISCELLANEOUS] >> 2) & 0b11 {
+      0b00 => (0xA_0000, 0x2_0000),
+      0b01 => (0xA_0000, 0x1_0000),
+      0b10 => (0xB_0000, 0x8000),
+      _ => (0xB_8000, 0x8000),
+    };
+    (start..start + size).contains(&addr).then(|| addr - start)
+  }
+
+  fn chain_4(&self) -> bool {
+    self.model == Model::Vga && matches!(self.sequencer[MEMORY_MODE] & 0b1000, 0b1000)
+  }
+
+  fn read_byte(&mut self, addr: usize) -> u8 {
+    let Some(offset) = self.window_offset(addr) else {
+      return 0xFF;
+    };
+    //Chain 4: the low 2 address bits pick the plane.
+    if self.chain_4() {
+      self.latches = self.planes[(offset & !0b11) % self.planes.len()];
+      return self.latches[offset & 0b11];
+    }
+    //Odd/even: even addresses read plane 0 (or 2), odd addresses plane 1 (or 3).
+    let odd_even = matches!(self.graphics[GRAPHICS_MODE] & 0b1_0000, 0b1_0000);
+    let address = (if odd_even { offset & !1 } else { offset }) % self.planes.len();
+    self.latches = self.planes[address];
+    //Read mode 1: which bits match the color compare register, in the planes not marked don't care.
+    if matches!(self.graphics[GRAPHICS_MODE] & 0b1000, 0b1000) {
+      let mut result = 0xFF;
+      for plane in 0..4 {
+        if self.graphics[COLOR_DONT_CARE] & (1 << plane) != 0 {
+          let compare = if self.graphics[COLOR_COMPARE] & (1 << plane) != 0 { 0xFF } else { 0x00 };
+          result &= !(self.latches[plane] ^ compare);
+        }
+      }
+      return result;
+    }
+    let read_map = (self.graphics[READ_MAP_SELECT] & 0b11) as usize;
+    let plane = if odd_even { (read_map & 0b10) | (offset & 1) } else { read_map };
+    self.latches[plane]
+  }
+
+  fn write_byte(&mut self, addr: usize, value: u8) {
+    let Some(offset) = self.window_offset(addr) else {
+      return;
+    };
+    let mut map_mask = self.sequencer[MAP_MASK] & 0x0F;
+    let offset = if self.chain_4() {
+      map_mask &= 1 << (offset & 0b11);
+      offset & !0b11
+    } else if !matches!(self.sequencer[MEMORY_MODE] & 0b100, 0b100) {
+      map_mask &= if offset & 1 == 1 { 0b1010 } else { 0b0101 };
+      offset & !1
+    } else {
+      offset
+    };
+    let address = offset % self.planes.len();
+    let data = self.write_data(value);
+    for (plane, byte) in data.iter().enumerate() {
+      if map_mask & (1 << plane) != 0 {
+        self.planes[address][plane] = *byte;
+      }
+    }
+  }
+
+  /// What each plane gets written with, after the write mode, logical function and bit mask.
+  fn write_data(&self, value: u8) -> [u8; 4] {
+    let rotate = self.graphics[DATA_ROTATE];
+    let rotated = value.rotate_right((rotate & 0b111) as u32);
+    let set_reset = self.graphics[SET_RESET];
+    let expand = |bit: u8| if bit != 0 { 0xFF } else { 0x00 };
+    let mut bit_mask = self.graphics[BIT_MASK];
+    let mut data = [0u8; 4];
+    match self.graphics[GRAPHICS_MODE] & 0b11 {
+      //Write mode 0: the rotated byte, or set/reset for the planes it is enabled on.
+      0 => for (plane, byte) in data.iter_mut().enumerate() {
+        let enabled = self.graphics[ENABLE_SET_RESET] & (1 << plane) != 0;
+        *byte = if enabled { expand(set_reset & (1 << plane)) } else { rotated };
+      },
+      //Write mode 1: the latches, as read by the last read. Used to copy video memory 4 planes at a time.
+      1 => return self.latches,
+      //Write mode 2: bits 0-3 are a color, spread across the planes.
+      2 => for (plane, byte) in data.iter_mut().enumerate() {
+        *byte = expand(value & (1 << plane));
+      },
+      //Write mode 3: set/reset is the color, and the rotated byte is ANDed into the bit mask.
+      _ => {
+        bit_mask &= rotated;
+        for (plane, byte) in data.iter_mut().enumerate() {
+          *byte = expand(set_reset & (1 << plane));
+        }
+      },
+    }
+    for (byte, latch) in data.iter_mut().zip(self.latches) {
+      let combined = match (rotate >> 3) & 0b11 {
+        0b00 => *byte,
+        0b01 => *byte & latch,
+        0b10 => *byte | latch,
+        _ => *byte ^ latch,
+      };
+      *byte = (combined & bit_mask) | (latch & !bit_mask);
+    }
+    data
+  }
+
+  fn graphics_mode(&self) -> bool {
+    matches!(self.attribute[MODE_CONTROL] & 0b1, 0b1)
+  }
+
+  fn color_256(&self) -> bool {
+    self.model == Model::Vga && matches!(self.graphics[GRAPHICS_MODE] & 0b100_0000, 0b100_0000)
+  }
+
+  /// The screen is blanked while the sequencer has it turned off, or while the palette is being loaded.
+  fn display_on(&self) -> bool {
+    !matches!(self.sequencer[CLOCKING_MODE] & 0b10_0000, 0b10_0000) && self.attribute_index & PALETTE_ADDRESS_SOURCE != 0
+  }
+
+  fn character_width(&self) -> usize {
+    if matches!(self.sequencer[CLOCKING_MODE] & 0b1, 0b1) { 8 } else { 9 }
+  }
+
+  fn double_scan(&self) -> bool {
+    self.model == Model::Vga && matches!(self.crtc[MAXIMUM_SCAN_LINE] & 0x80, 0x80)
+  }
+
+  fn lines_per_row(&self) -> usize {
+    (self.crtc[MAXIMUM_SCAN_LINE] & 0x1F) as usize + 1
+  }
+
+  fn horizontal_total(&self) -> u64 {
+    self.crtc[HORIZONTAL_TOTAL] as u64 + if self.model == Model::Vga { 5 } else { 2 }
+  }
+
+  fn horizontal_display_end(&self) -> usize {
+    self.crtc[END_HORIZONTAL_DISPLAY] as usize + 1
+  }
+
+  /// Registers 6, 10 and 12 are 10 bits. The overflow register holds bits 8 and 9. The EGA only has bit 8.
+  fn ten_bits(&self, index: usize, bit_8: u8, bit_9: u8) -> usize {
+    let overflow = self.crtc[OVERFLOW];
+    let mut value = self.crtc[index] as usize;
+    if overflow & (1 << bit_8) != 0 { value |= 0x100 }
+    if overflow & (1 << bit_9) != 0 && self.model == Model::Vga { value |= 0x200 }
+    value
+  }
+
+  fn vertical_total(&self) -> u64 {
+    self.ten_bits(VERTICAL_TOTAL, 0, 5) as u64 + 2
+  }
+
+  fn vertical_display_end(&self) -> usize {
+    self.ten_bits(VERTICAL_DISPLAY_END, 1, 6) + 1
+  }
+
+  /// Characters clocked out since power on, at the given master clock cycle.
+  fn character_clocks(&self, now: u64) -> u64 {
+    (now as u128 * self.dot_hz() as u128 / (MASTER_HZ as u128 * self.character_width() as u128)) as u64
+  }
+
+  fn dot_hz(&self) -> u64 {
+    let dot_hz = match self.model {
+      Model::Ega => EGA_DOT_HZ[((self.miscellaneous_output >> 2) & 1) as usize],
+      Model::Vga => DOT_HZ[((self.miscellaneous_output >> 2) & 1) as usize],
+    };
+    if matches!(self.sequencer[CLOCKING_MODE] & 0b1000, 0b1000) {
+      dot_hz / 2
+    } else {
+      dot_hz
+    }
+  }
+
+  /// Length of a frame in master clock cycles.
+  fn frame_time(&self) -> u64 {
+    let frame_clocks = self.horizontal_total() * self.vertical_total();
+    (frame_clocks as u128 * self.character_width() as u128 * MASTER_HZ as u128 / self.dot_hz() as u128) as u64
+  }
+
+  /// Frames since power on. This drives the cursor and character blink.
+  fn frame_number(&self, now: u64) -> u64 {
+    self.character_clocks(now) / (self.horizontal_total() * self.vertical_total())
+  }
+
+  /// (display enable, vertical retrace)
+  fn raster_status(&self, now: u64) -> (bool, bool) {
+    let character_clocks = self.character_clocks(now);
+    let horizontal_total = self.horizontal_total();
+    let column = (character_clocks % horizontal_total) as usize;
+    let line = ((character_clocks / horizontal_total) % self.vertical_total()) as usize;
+    let display_enable = column < self.horizontal_display_end() && line < self.vertical_display_end();
+    //The retrace end register only holds the low 4 bits of the line it ends on.
+    let retrace_start = self.ten_bits(VERTICAL_RETRACE_START, 2, 7);
+    let retrace_width = match (self.crtc[VERTICAL_RETRACE_END] as usize).wrapping_sub(retrace_start) & 0x0F {
+      0 => 16,
+      width => width,
+    };
+    (display_enable, (retrace_start..retrace_start + retrace_width).contains(&line))
+  }
+
+  /// Scan lines shown, after double scanning.
+  fn scan_lines(&self) -> usize {
+    self.vertical_display_end() / if self.double_scan() { 2 } else { 1 }
+  }
+
+  /// In pixels. Graphics modes which repeat each row of pixels over several scan lines are only drawn once per row.
+  fn screen_size(&self) -> (usize, usize) {
+    let columns = self.horizontal_display_end();
+    if !self.graphics_mode() {
+      return (columns * self.character_width(), self.scan_lines());
+    }
+    let width = columns * if self.color_256() { 4 } else { 8 };
+    let height = if self.row_scan_addressing() { self.scan_lines() } else { self.scan_lines() / self.lines_per_row() };
+    (width, height)
+  }
+
+  /// CGA compatible modes replace address bits 13 and 14 with the row scan counter, to interleave scan lines.
+  fn row_scan_addressing(&self) -> bool {
+    !matches!(self.crtc[CRTC_MODE_CONTROL] & 0b11, 0b11)
+  }
+
+  fn start_address(&self) -> usize {
+    u16::from_be_bytes([self.crtc[START_ADDRESS_HIGH], self.crtc[START_ADDRESS_LOW]]) as usize
+  }
+
+  /// Row to row distance in CRTC addresses.
+  fn row_offset(&self) -> usize {
+    self.crtc[OFFSET] as usize * 2
+  }
+
+  /// Turn a CRTC address into a plane address. Each address fetches 1 byte from every plane in byte mode,
+  /// 2 bytes (the even and odd plane pairs) in word mode, and 4 bytes (the chained planes) in double word mode.
+  fn plane_address(&self, address: usize, row_scan: usize) -> usize {
+    let mode = self.crtc[CRTC_MODE_CONTROL];
+    let mut address = if self.crtc[UNDERLINE_LOCATION] & 0b100_0000 != 0 {
+      address << 2
+    } else if mode & 0b100_0000 == 0 {
+      address << 1
+    } else {
+      address
+    };
+    if mode & 0b1 == 0 { address = (address & !(1 << 13)) | ((row_scan & 1) << 13) }
+    if mode & 0b10 == 0 { address = (address & !(1 << 14)) | (((row_scan >> 1) & 1) << 14) }
+    address % self.planes.len()
+  }
+
+  /// An attribute controller input (4 bits) to an RGB color, through the palette and the DAC.
+  fn color(&self, input: u8) -> [u8; 3] {
+    let palette = self.attribute[(input & self.attribute[COLOR_PLANE_ENABLE] & 0x0F) as usize];
+    if self.model == Model::Ega {
+      return self.ega_color(palette);
+    }
+    let select = self.attribute[COLOR_SELECT];
+    let mut index = if matches!(self.attribute[MODE_CONTROL] & 0x80, 0x80) {
+      (palette & 0x0F) | ((select & 0b11) << 4)
+    } else {
+      palette & 0x3F
+    };
+    index |= (select & 0b1100) << 4;
+    self.dac_color(index)
+  }
+
+  /// The EGA palette drives the monitor directly. The Enhanced Color Display takes negative vertical sync as
+  /// the 350 line mode, with palette bits 0-5 as blue, green, red, then their low intensity halves.
+  /// In 200 line modes it acts as a Color Display, with bit 4 as intensity.
+  fn ega_color(&self, palette: u8) -> [u8; 3] {
+    if !matches!(self.miscellaneous_output & 0x80, 0x80) {
+      return cga::PALETTE[((palette & 0b111) | ((palette >> 1) & 0b1000)) as usize];
+    }
+    let level = |primary: u8, secondary: u8| ((palette >> primary) & 1) * 0xAA + ((palette >> secondary) & 1) * 0x55;
+    [level(2, 5), level(1, 4), level(0, 3)]
+  }
+
+  /// The DAC is 6 bits per component.
+  fn dac_color(&self, index: u8) -> [u8; 3] {
+    self.dac[(index & self.dac_mask) as usize].map(|value| (value as u16 * 255 / 63) as u8)
+  }
+
+  fn blink(&self) -> bool {
+    matches!(self.attribute[MODE_CONTROL] & 0b1000, 0b1000)
+  }
+
+  fn text_screen(&self) -> TextScreen {
+    let columns = self.horizontal_display_end();
+    let character_height = self.lines_per_row();
+    let rows = self.scan_lines() / character_height;
+    let start = self.start_address();
+    let cells = (0..rows).flat_map(|row| (0..columns).map(move |column| (row, column))).map(|(row, column)| {
+      let address = self.plane_address(start + row * self.row_offset() + column, 0);
+      let [character, attribute, _, _] = self.planes[address];
+      (character, attribute)
+    }).collect();
+    //Cursor start bit 5 turns the cursor off. Unlike the 6845, there is no cursor if it starts after it ends.
+    let cursor_start = self.crtc[CURSOR_START];
+    let (start_line, end_line) = (cursor_start & 0x1F, self.crtc[CURSOR_END] & 0x1F);
+    let location = u16::from_be_bytes([self.crtc[CURSOR_LOCATION_HIGH], self.crtc[CURSOR_LOCATION_LOW]]) as usize;
+    let position = location.wrapping_sub(start);
+    let cursor_off = matches!(cursor_start & 0b10_0000, 0b10_0000) || start_line > end_line;
+    let cursor = if cursor_off || columns == 0 || position >= columns * rows {
+      None
+    } else {
+      Some(Cursor {
+        column: position % columns,
+        row: position / columns,
+        start: start_line,
+        end: end_line,
+        blink_frames: CURSOR_BLINK_FRAMES,
+      })
+    };
+    TextScreen {
+      columns,
+      rows,
+      character_height: character_height as u8,
+      color: !matches!(self.attribute[MODE_CONTROL] & 0b10, 0b10),
+      blink: self.blink(),
+      cursor,
+      cells,
+    }
+  }
+
+  /// Fonts are loaded into plane 2, 32 bytes per character. Character map select picks one of 8 fonts
+  /// for characters with attribute bit 3 set (map A), and one for those without (map B).
+  fn font_offset(&self, attribute: u8) -> usize {
+    let select = self.sequencer[CHARACTER_MAP_SELECT];
+    let map = if attribute & 0b1000 != 0 {
+      ((select >> 2) & 0b11) | ((select >> 3) & 0b100)
+    } else {
+      (select & 0b11) | ((select >> 2) & 0b100)
+    } as usize;
+    (map & 0b11) * 0x4000 + (map >> 2) * 0x2000
+  }
+
+  fn render_text(&self, frame: u64) -> Framebuffer {
+    let screen = self.text_screen();
+    let character_width = self.character_width();
+    let character_height = screen.character_height as usize;
+    let (width, height) = self.screen_size();
+    let mut image = framebuffer::new(width, height);
+    let blink_on = frame % CHARACTER_BLINK_FRAMES < CHARACTER_BLINK_FRAMES / 2;
+    let line_graphics = matches!(self.attribute[MODE_CONTROL] & 0b100, 0b100);
+    let underline_row = (self.crtc[UNDERLINE_LOCATION] & 0x1F) as usize;
+
+    for row in 0..screen.rows {
+      for column in 0..screen.columns {
+        let (character, attribute) = screen.cells[row * screen.columns + column];
+        let blinking = screen.blink && attribute & 0x80 != 0;
+        let foreground = self.color(attribute & 0x0F);
+        let background = self.color(if screen.blink { (attribute >> 4) & 0x07 } else { attribute >> 4 });
+        let hidden = blinking && !blink_on;
+        let underline = attribute & 0x77 == 0x01;
+        let font = self.font_offset(attribute) + character as usize * 32;
+        let cursor_here = screen.cursor.as_ref().filter(|cursor| {
+          cursor.row == row && cursor.column == column && frame % cursor.blink_frames < cursor.blink_frames / 2
+        });
+
+        for line in 0..character_height {
+          let bits = self.planes[(font + line) % self.planes.len()][2];
+          let in_cursor = cursor_here.is_some_and(|cursor| (cursor.start as usize..=cursor.end as usize).contains(&line));
+          let underlined = underline && line == underline_row;
+          for x in 0..character_width {
+            let pixel = match x {
+              0..=7 => bits & (0x80 >> x) != 0,
+              _ => line_graphics && (0xC0..=0xDF).contains(&character) && bits & 1 != 0,
+            };
+            let lit = in_cursor || ((pixel || underlined) && !hidden);
+            let (x, y) = (column * character_width + x, row * character_height + line);
+            if x < width && y < height {
+              image.set_pixel(x, y, if lit { foreground } else { background });
+            }
+          }
+        }
+      }
+    }
+    image
+  }
+
+  /// 16 color planar modes take a bit from each plane per pixel. CGA compatible modes take 2 bits at a time from the even
+  /// then odd planes. 256 color modes take a byte from each of the 4 chained planes per pixel.
+  fn render_graphics(&self) -> Framebuffer {
+    let (width, height) = self.screen_size();
+    let mut image = framebuffer::new(width, height);
+    let columns = self.horizontal_display_end();
+    let interleaved = matches!(self.graphics[GRAPHICS_MODE] & 0b10_0000, 0b10_0000);
+    let lines_per_row = if self.row_scan_addressing() { self.lines_per_row() } else { 1 };
+
+    for y in 0..height {
+      let (row, row_scan) = (y / lines_per_row, y % lines_per_row);
+      let row_start = self.start_address() + row * self.row_offset();
+      for column in 0..columns {
+        let planes = self.planes[self.plane_address(row_start + column, row_scan)];
+        if self.color_256() {
+          for (pixel, byte) in planes.iter().enumerate() {
+            image.set_pixel(column * 4 + pixel, y, self.dac_color(*byte));
+          }
+          continue;
+        }
+        for pixel in 0..8 {
+          let color = if interleaved {
+            let (low, high) = if pixel < 4 { (planes[0], planes[2]) } else { (planes[1], planes[3]) };
+            let shift = 6 - (pixel % 4) * 2;
+            ((low >> shift) & 0b11) | (((high >> shift) & 0b11) << 2)
+          } else {
+            (0..4).fold(0, |color, plane| color | (((planes[plane] >> (7 - pixel)) & 1) << plane))
+          };
+          image.set_pixel(column * 8 + pixel, y, self.color(color));
+        }
+      }
+    }
+    image
+  }
+}
+
+impl MemoryDevice for VGAMemory {
+  fn read_byte(&mut self, addr: usize) -> u8 {
+    self.card.lock().unwrap().read_byte(addr)
+  }
+
+  fn write_byte(&mut self, addr: usize, value: u8) {
+    self.card.lock().unwrap().write_byte(addr, value);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MISCELLANEOUS_OUTPUT: u16 = 0x3C2;
+  const ENABLE_RAM: u8 = 0b10;
+  const NEGATIVE_VERTICAL_SYNC: u8 = 0x80;
+
+  #[test]
+  fn ega_switch_sense() {
+    let mut ega = start(Model::Ega, 256);
+    let switches: Vec<bool> = (0..4).map(|switch| {
+      ega.out_byte(MISCELLANEOUS_OUTPUT, switch << 2);
+      ega.in_byte(MISCELLANEOUS_OUTPUT, 0) & 0b1_0000 != 0
+    }).collect();
+    assert_eq!(switches, [true, false, false, true]);
+  }
+
+  #[test]
+  fn ega_has_no_dac() {
+    let ega = start(Model::Ega, 256);
+    let vga = start(Model::Vga, 256);
+    assert!(!ega.handles_port(0x3C8));
+    assert!(vga.handles_port(0x3C8));
+    assert!(ega.handles_port(0x3C0));
+  }
+
+  #[test]
+  fn ega_registers_are_write_only() {
+    let mut ega = start(Model::Ega, 256);
+    ega.out_byte(0x3CE, GRAPHICS_MODE as u8);
+    assert_eq!(ega.in_byte(0x3CE, 0), 0xFF);
+    let mut vga = start(Model::Vga, 256);
+    vga.out_byte(0x3CE, GRAPHICS_MODE as u8);
+    assert_eq!(vga.in_byte(0x3CE, 0), GRAPHICS_MODE as u8);
+  }
+
+  #[test]
+  fn ega_memory_wraps_at_its_size() {
+    let mut ega = start(Model::Ega, 64);
+    ega.out_byte(MISCELLANEOUS_OUTPUT, ENABLE_RAM);
+    ega.out_byte(0x3C4, MAP_MASK as u8);
+    ega.out_byte(0x3C5, 0b1111);
+    ega.out_byte(0x3C4, MEMORY_MODE as u8);
+    ega.out_byte(0x3C5, 0b100);  //Planar, not odd/even.
+    ega.out_byte(0x3CE, BIT_MASK as u8);
+    ega.out_byte(0x3CF, 0xFF);
+    let mut memory = ega.video_memory();
+    memory.write_byte(MEMORY_START, 0x12);
+    assert_eq!(memory.read_byte(MEMORY_START + 0x4000), 0x12, "64KB is 4 planes of 16KB.");
+  }
+
+  #[test]
+  fn ega_colors() {
+    let ega = start(Model::Ega, 256);
+    let mut card = ega.card();
+    card.miscellaneous_output = NEGATIVE_VERTICAL_SYNC;
+    assert_eq!(card.ega_color(0b000_100), [0xAA, 0x00, 0x00]);
+    assert_eq!(card.ega_color(0b100_100), [0xFF, 0x00, 0x00]);
+    assert_eq!(card.ega_color(0b010_000), [0x00, 0x55, 0x00]);
+    card.miscellaneous_output = 0;
+    assert_eq!(card.ega_color(0b000_110), [0xAA, 0x55, 0x00], "In 200 line modes, color 6 is brown.");
+    assert_eq!(card.ega_color(0b010_001), [0x55, 0x55, 0xFF], "And bit 4 is intensity.");
+  }
+
+  fn graphics(vga: &mut Vga, index: usize, value: u8) {
+    vga.out_byte(0x3CE, index as u8);
+    vga.out_byte(0x3CF, value);
+  }
+
+  fn sequencer(vga: &mut Vga, index: usize, value: u8) {
+    vga.out_byte(0x3C4, index as u8);
+    vga.out_byte(0x3C5, value);
+  }
+
+  //A VGA with all 4 planes enabled for writing, no odd/even, and the window at A0000.
+  fn planar_vga() -> (Vga, VGAMemory) {
+    let mut vga = start(Model::Vga, 256);
+    vga.out_byte(MISCELLANEOUS_OUTPUT, ENABLE_RAM);
+    sequencer(&mut vga, MAP_MASK, 0b1111);
+    sequencer(&mut vga, MEMORY_MODE, 0b100);
+    graphics(&mut vga, BIT_MASK, 0xFF);
+    let memory = vga.video_memory();
+    (vga, memory)
+  }
+
+  #[test]
+  fn write_mode_0_writes_the_rotated_byte_to_enabled_planes() {
+    let (mut vga, mut memory) = planar_vga();
+    sequencer(&mut vga, MAP_MASK, 0b0101);
+    graphics(&mut vga, DATA_ROTATE, 1);
+    memory.write_byte(MEMORY_START, 0x81);
+    assert_eq!(vga.card().planes[0], [0xC0, 0x00, 0xC0, 0x00]);
+  }
+
+  #[test]
+  fn write_mode_0_set_reset_fills_its_planes() {
+    let (mut vga, mut memory) = planar_vga();
+    graphics(&mut vga, ENABLE_SET_RESET, 0b0011);
+    graphics(&mut vga, SET_RESET, 0b0001);
+    memory.write_byte(MEMORY_START, 0x3C);
+    assert_eq!(vga.card().planes[0], [0xFF, 0x00, 0x3C, 0x3C]);
+  }
+
+  #[test]
+  fn write_mode_1_copies_the_latches() {
+    let (mut vga, mut memory) = planar_vga();
+    vga.card().planes[0] = [1, 2, 3, 4];
+    memory.read_byte(MEMORY_START);
+    graphics(&mut vga, GRAPHICS_MODE, 1);
+    memory.write_byte(MEMORY_START + 1, 0xFF);
+    assert_eq!(vga.card().planes[1], [1, 2, 3, 4]);
+  }
+
+  #[test]
+  fn write_mode_2_spreads_a_color_across_the_planes() {
+    let (mut vga, mut memory) = planar_vga();
+    graphics(&mut vga, GRAPHICS_MODE, 2);
+    graphics(&mut vga, BIT_MASK, 0x80);
+    memory.write_byte(MEMORY_START, 0b0101);
+    assert_eq!(vga.card().planes[0], [0x80, 0x00, 0x80, 0x00]);
+  }
+
+  #[test]
+  fn write_mode_3_masks_the_set_reset_color_with_the_byte() {
+    let (mut vga, mut memory) = planar_vga();
+    graphics(&mut vga, GRAPHICS_MODE, 3);
+    graphics(&mut vga, SET_RESET, 0b0010);
+    graphics(&mut vga, BIT_MASK, 0xF0);
+    memory.write_byte(MEMORY_START, 0x3C);
+    assert_eq!(vga.card().planes[0], [0x00, 0x30, 0x00, 0x00]);
+  }
+
+  #[test]
+  fn bit_mask_keeps_the_latched_bits() {
+    let (mut vga, mut memory) = planar_vga();
+    vga.card().planes[0] = [0xF0; 4];
+    memory.read_byte(MEMORY_START);
+    graphics(&mut vga, BIT_MASK, 0x0F);
+    memory.write_byte(MEMORY_START, 0x55);
+    assert_eq!(vga.card().planes[0], [0xF5; 4]);
+
+    //The logical function combines the byte with the latches before the bit mask.
+    graphics(&mut vga, BIT_MASK, 0xFF);
+    graphics(&mut vga, DATA_ROTATE, 0b11_000);  //XOR
+    memory.read_byte(MEMORY_START);
+    memory.write_byte(MEMORY_START, 0xFF);
+    assert_eq!(vga.card().planes[0], [0x0A; 4]);
+  }
+
+  #[test]
+  fn read_mode_0_reads_the_selected_plane() {
+    let (mut vga, mut memory) = planar_vga();
+    vga.card().planes[5] = [1, 2, 3, 4];
+    graphics(&mut vga, READ_MAP_SELECT, 2);
+    assert_eq!(memory.read_byte(MEMORY_START + 5), 3);
+    assert_eq!(vga.card().latches, [1, 2, 3, 4], "Every read loads all 4 latches.");
+  }
+
+  #[test]
+  fn read_mode_1_compares_colors() {
+    let (mut vga, mut memory) = planar_vga();
+    vga.card().planes[0] = [0xFF, 0x0F, 0x00, 0xF0];
+    graphics(&mut vga, GRAPHICS_MODE, 0b1000);
+    graphics(&mut vga, COLOR_COMPARE, 0b0011);
+    graphics(&mut vga, COLOR_DONT_CARE, 0b1111);
+    assert_eq!(memory.read_byte(MEMORY_START), 0x0F);
+    graphics(&mut vga, COLOR_DONT_CARE, 0b0001);
+    assert_eq!(memory.read_byte(MEMORY_START), 0xFF, "Only plane 0 is compared.");
+  }
+
+  #[test]
+  fn chain_4_uses_the_low_address_bits_as_the_plane() {
+    let (mut vga, mut memory) = planar_vga();
+    sequencer(&mut vga, MEMORY_MODE, 0b1000);
+    memory.write_byte(MEMORY_START + 5, 0x12);
+    memory.write_byte(MEMORY_START + 7, 0x34);
+    assert_eq!(vga.card().planes[4], [0x00, 0x12, 0x00, 0x34]);
+    assert_eq!(memory.read_byte(MEMORY_START + 5), 0x12);
+    assert_eq!(memory.read_byte(MEMORY_START + 7), 0x34);
+  }
+
+  #[test]
+  fn odd_even_sends_odd_addresses_to_the_odd_planes() {
+    let (mut vga, mut memory) = planar_vga();
+    sequencer(&mut vga, MEMORY_MODE, 0);
+    graphics(&mut vga, GRAPHICS_MODE, 0b1_0000);
+    memory.write_byte(MEMORY_START + 2, 0x41);  //A character
+    memory.write_byte(MEMORY_START + 3, 0x07);  //And its attribute
+    assert_eq!(vga.card().planes[2], [0x41, 0x07, 0x41, 0x07]);
+    assert_eq!(memory.read_byte(MEMORY_START + 2), 0x41);
+    assert_eq!(memory.read_byte(MEMORY_START + 3), 0x07);
+  }
+
+  #[test]
+  fn dac_is_written_and_read_3_components_at_a_time() {
+    let mut vga = start(Model::Vga, 256);
+    vga.out_byte(0x3C8, 5);
+    for value in [0x3F, 0x20, 0x40, 0x01, 0x02, 0x03] {
+      vga.out_byte(0x3C9, value);
+    }
+    assert_eq!(vga.in_byte(0x3C8, 0), 7);
+    vga.out_byte(0x3C7, 5);
+    let read: Vec<u8> = (0..6).map(|_| vga.in_byte(0x3C9, 0)).collect();
+    assert_eq!(read, [0x3F, 0x20, 0x00, 0x01, 0x02, 0x03], "Components are 6 bits.");
+    assert_eq!(vga.card().dac_color(5), [0xFF, 0x81, 0x00]);
+    vga.out_byte(0x3C6, 0x00);
+    let card = vga.card();
+    assert_eq!(card.dac_color(5), card.dac_color(0), "The mask hides the index.");
+  }
+}
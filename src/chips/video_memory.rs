@@ -0,0 +1,38 @@
+//Memory on a video adapter. The CPU reaches it through the memory map, and the adapter reads it to draw the screen.
+//Adapters only decode as many address lines as they have memory for, so it repeats through the rest of their window.
+
+use super::memory1mb::MemoryDevice;
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Clone)]
+pub struct VideoMemory {
+  data: Arc<Mutex<Vec<u8>>>,
+}
+
+/// The size must be a power of 2.
+pub fn new(size: usize) -> VideoMemory {
+  debug_assert!(size.is_power_of_two());
+  VideoMemory {
+    data: Arc::new(Mutex::new(vec![0u8; size])),
+  }
+}
+
+impl VideoMemory {
+  pub fn lock(&self) -> MutexGuard<'_, Vec<u8>> {
+    self.data.lock().unwrap()
+  }
+}
+
+impl MemoryDevice for VideoMemory {
+  fn read_byte(&mut self, addr: usize) -> u8 {
+    let data = self.lock();
+    data[addr & (data.len() - 1)]
+  }
+
+  fn write_byte(&mut self, addr: usize, value: u8) {
+    let mut data = self.lock();
+    let mask = data.len() - 1;
+    data[addr & mask] = value;
+  }
+}
@@ -1,51 +1,266 @@
-use std::sync::mpsc;
+//Master clock timeline.
+//Everything on the board is derived from the 14.31818 MHz crystal: the CPU runs at /3 (or faster with turbo) and the PIT at /12.
+//Time is counted in master clock cycles. Devices register the cycle of their next event,
+//and the CPU runs freely until it reaches the earliest one, then waits for the motherboard to catch up.
+
+use crate::MotherboardMsg;
+
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicU64,Ordering};
 use std::{time, thread};
 
-pub struct Clock {
-  counts: Vec<Count>,
-  base_delay: u128,
+use log::debug;
+
+pub const MASTER_HZ: u64 = 14_318_180;
+pub const PIT_DIVISOR: u64 = 12;
+const CPU_HALF_CYCLES: u64 = 6;  //4.77 MHz. In half master clock cycles, since 9.54 MHz is /1.5.
+
+const THROTTLE_SLICE: u64 = MASTER_HZ / 100;  //Compare against the wall clock every 10ms of emulated time.
+const MAX_LAG: time::Duration = time::Duration::from_millis(100);  //Further behind than this, and we give up catching up.
+
+#[derive(Debug, Clone, Copy)]
+pub enum Event {
+  Pit,
+  Speaker,
+  Keyboard,
+  Display,
+  Capture,
+  Check,
+  Fault,
+  Throttle,
+}
+const EVENTS: [Event; 8] = [Event::Pit, Event::Speaker, Event::Keyboard, Event::Display, Event::Capture, Event::Check, Event::Fault, Event::Throttle];
+
+/// Owned by the motherboard. One slot per event source, so rescheduling simply replaces the old time.
+pub struct Scheduler {
+  now: u64,
+  events: [Option<u64>; EVENTS.len()],
+  cpu_time: Arc<AtomicU64>,   //Written by the CPU after every instruction.
+  next_event: Arc<AtomicU64>, //Read by the CPU after every instruction.
+  cpu_half_cycles: Arc<AtomicU64>,  //Length of a CPU clock, in half master clock cycles.
+  throttle_enabled: bool,
+  throttle_start: time::Instant,
+  throttle_start_cycles: u64,
+}
+
+/// Owned by the CPU thread.
+pub struct CPUClock {
+  half_cycles: u64,
+  cpu_half_cycles: Arc<AtomicU64>,
+  cpu_time: Arc<AtomicU64>,
+  next_event: Arc<AtomicU64>,
+  messenger: mpsc::Sender<crate::Msg>,
 }
 
-struct Count {
-  current: usize,
-  max: usize,
-  signal: mpsc::Sender<()>
+pub fn init(messenger: mpsc::Sender<crate::Msg>) -> (Scheduler, CPUClock) {
+  let cpu_time_arc = Arc::new(AtomicU64::new(0));
+  let next_event_arc = Arc::new(AtomicU64::new(0));
+  let cpu_half_cycles_arc = Arc::new(AtomicU64::new(CPU_HALF_CYCLES));
+  let mut scheduler = Scheduler {
+    now: 0,
+    events: [None; EVENTS.len()],
+    cpu_time: Arc::clone(&cpu_time_arc),
+    next_event: Arc::clone(&next_event_arc),
+    cpu_half_cycles: Arc::clone(&cpu_half_cycles_arc),
+    throttle_enabled: true,
+    throttle_start: time::Instant::now(),
+    throttle_start_cycles: 0,
+  };
+  scheduler.schedule(Event::Throttle, THROTTLE_SLICE);
+  let cpu_clock = CPUClock {
+    half_cycles: 0,
+    cpu_half_cycles: cpu_half_cycles_arc,
+    cpu_time: cpu_time_arc,
+    next_event: next_event_arc,
+    messenger,
+  };
+  (scheduler, cpu_clock)
 }
 
-pub fn init(base_delay: u128) -> Clock {
-  Clock {
-    counts: Vec::new(),
-    base_delay,
+impl Scheduler {
+  pub fn now(&self) -> u64 {
+    self.now
+  }
+
+  /// Where the CPU has got to. Devices are caught up to this before the CPU talks to them.
+  pub fn cpu_time(&self) -> u64 {
+    self.cpu_time.load(Ordering::Relaxed)
+  }
+
+  pub fn schedule(&mut self, event: Event, at: u64) {
+    self.events[event as usize] = Some(at);
+  }
+
+  pub fn cancel(&mut self, event: Event) {
+    self.events[event as usize] = None;
+  }
+
+  /// Take the earliest event due at or before `until`, and move time forward to it.
+  /// Once there are none left, time moves forward to `until`.
+  pub fn next_due(&mut self, until: u64) -> Option<Event> {
+    let due = self.events.iter().enumerate()
+      .filter_map(|(index, at)| at.map(|at| (index, at)))
+      .filter(|(_, at)| *at <= until)
+      .min_by_key(|(_, at)| *at);
+    match due {
+      Some((index, at)) => {
+        self.events[index] = None;
+        self.now = self.now.max(at);
+        Some(EVENTS[index])
+      },
+      None => {
+        self.now = self.now.max(until);
+        None
+      },
+    }
+  }
+
+  /// Let the CPU run until the next event.
+  pub fn publish(&self) {
+    let next_event = self.events.iter().flatten().min().copied().unwrap_or(u64::MAX);
+    self.next_event.store(next_event, Ordering::Relaxed);
+  }
+
+  /// Retime the CPU. Takes effect from its next instruction.
+  pub fn set_cpu_half_cycles(&mut self, half_cycles: u64) {
+    debug!("CPU clock set to {:.2} MHz", (MASTER_HZ * 2) as f64 / half_cycles as f64 / 1_000_000.0);
+    self.cpu_half_cycles.store(half_cycles, Ordering::Relaxed);
+  }
+
+  /// With the throttle off, the machine runs as fast as the host allows.
+  /// The CPU and devices still share the one timeline, so their speeds relative to each other are unchanged.
+  pub fn set_throttle(&mut self, enabled: bool) {
+    self.throttle_enabled = enabled;
+    self.throttle_start = time::Instant::now();
+    self.throttle_start_cycles = self.now;
+  }
+
+  /// Sleep off however far emulated time has got ahead of the wall clock.
+  pub fn throttle(&mut self) {
+    self.schedule(Event::Throttle, self.now + THROTTLE_SLICE);
+    if !self.throttle_enabled {
+      return;
+    }
+    let emulated = time::Duration::from_nanos((self.now - self.throttle_start_cycles) * 1_000_000_000 / MASTER_HZ);
+    let wall = self.throttle_start.elapsed();
+    if emulated > wall {
+      thread::sleep(emulated - wall);
+    } else if wall - emulated > MAX_LAG {
+      debug!("Emulation is running {:?} behind. Resetting the throttle.", wall - emulated);
+      self.throttle_start = time::Instant::now();
+      self.throttle_start_cycles = self.now;
+    }
   }
 }
 
-impl Clock {
-  pub fn add(&mut self, cycles: usize) -> mpsc::Receiver<()> {
-    let (tx, rx) = mpsc::channel();
-    self.counts.push(Count {
-      current: cycles,
-      max: cycles,
-      signal: tx,
-    });
-    rx
-  }
-  
-  pub fn start(mut self) {
-    thread::spawn(move || {
-      let mut old_time = time::SystemTime::now();
-      loop {
-        while old_time.elapsed().unwrap().as_nanos() < self.base_delay {
-          thread::yield_now();
-        }
-        old_time = time::SystemTime::now();
-        for mut count in &mut self.counts {
-          count.current -= 1;
-          if count.current == 0 {
-            count.current = count.max;
-            count.signal.send(()).unwrap();
-          }
-        }
-      }
+impl CPUClock {
+  pub fn add_cycles(&mut self, cycles: usize) {
+    self.half_cycles += cycles as u64 * self.cpu_half_cycles.load(Ordering::Relaxed);
+    let now = self.half_cycles / 2;
+    self.cpu_time.store(now, Ordering::Relaxed);
+    if now >= self.next_event.load(Ordering::Relaxed) {
+      let (socket, rx) = mpsc::channel();
+      let msg = crate::Msg::Motherboard(MotherboardMsg::Sync{socket});
+      self.messenger.send(msg).unwrap();
+      rx.recv().unwrap();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn scheduler() -> (Scheduler, CPUClock, mpsc::Receiver<crate::Msg>) {
+    let (sender, receiver) = mpsc::channel();
+    let (mut scheduler, cpu_clock) = init(sender);
+    scheduler.cancel(Event::Throttle);
+    (scheduler, cpu_clock, receiver)
+  }
+
+  #[test]
+  fn events_come_out_in_time_order() {
+    let (mut scheduler, _cpu_clock, _receiver) = scheduler();
+    scheduler.schedule(Event::Keyboard, 300);
+    scheduler.schedule(Event::Pit, 100);
+    scheduler.schedule(Event::Display, 200);
+    assert!(matches!(scheduler.next_due(1000), Some(Event::Pit)));
+    assert_eq!(scheduler.now(), 100);
+    assert!(matches!(scheduler.next_due(1000), Some(Event::Display)));
+    assert!(matches!(scheduler.next_due(1000), Some(Event::Keyboard)));
+    assert_eq!(scheduler.now(), 300);
+    assert!(scheduler.next_due(1000).is_none());
+    assert_eq!(scheduler.now(), 1000, "With nothing left, time moves on to the limit.");
+  }
+
+  #[test]
+  fn events_after_the_limit_wait() {
+    let (mut scheduler, _cpu_clock, _receiver) = scheduler();
+    scheduler.schedule(Event::Pit, 500);
+    assert!(scheduler.next_due(499).is_none());
+    assert_eq!(scheduler.now(), 499);
+    assert!(matches!(scheduler.next_due(500), Some(Event::Pit)));
+  }
+
+  #[test]
+  fn rescheduling_replaces_the_old_time() {
+    let (mut scheduler, _cpu_clock, _receiver) = scheduler();
+    scheduler.schedule(Event::Speaker, 100);
+    scheduler.schedule(Event::Speaker, 400);
+    assert!(scheduler.next_due(300).is_none());
+    assert!(matches!(scheduler.next_due(400), Some(Event::Speaker)));
+    assert!(scheduler.next_due(1000).is_none(), "Each event source has one slot.");
+  }
+
+  #[test]
+  fn cancelled_events_never_fire() {
+    let (mut scheduler, _cpu_clock, _receiver) = scheduler();
+    scheduler.schedule(Event::Fault, 100);
+    scheduler.cancel(Event::Fault);
+    assert!(scheduler.next_due(1000).is_none());
+  }
+
+  #[test]
+  fn time_never_goes_backwards() {
+    let (mut scheduler, _cpu_clock, _receiver) = scheduler();
+    scheduler.next_due(1000);
+    scheduler.schedule(Event::Check, 50);
+    assert!(matches!(scheduler.next_due(2000), Some(Event::Check)), "An event already in the past fires straight away.");
+    assert_eq!(scheduler.now(), 1000);
+  }
+
+  #[test]
+  fn cpu_time_follows_the_cpu_speed() {
+    let (mut scheduler, mut cpu_clock, _receiver) = scheduler();
+    scheduler.schedule(Event::Pit, u64::MAX - 1);
+    scheduler.publish();
+    cpu_clock.add_cycles(4);
+    assert_eq!(scheduler.cpu_time(), 12, "4.77 MHz is 3 master clock cycles per CPU clock.");
+    scheduler.set_cpu_half_cycles(3);
+    cpu_clock.add_cycles(3);
+    assert_eq!(scheduler.cpu_time(), 16, "9.54 MHz is 1.5 master clock cycles per CPU clock. Half cycles carry over.");
+    cpu_clock.add_cycles(1);
+    assert_eq!(scheduler.cpu_time(), 18);
+  }
+
+  #[test]
+  fn cpu_waits_at_the_next_event() {
+    let (mut scheduler, mut cpu_clock, receiver) = scheduler();
+    scheduler.schedule(Event::Pit, 30);
+    scheduler.publish();
+    let cpu = thread::spawn(move || {
+      cpu_clock.add_cycles(5);
+      cpu_clock.add_cycles(5);
+      cpu_clock
     });
+    match receiver.recv().unwrap() {
+      crate::Msg::Motherboard(MotherboardMsg::Sync{socket}) => {
+        assert_eq!(scheduler.cpu_time(), 30);
+        socket.send(()).unwrap();
+      },
+      _ => panic!("Expected a Sync"),
+    }
+    cpu.join().unwrap();
+    assert!(receiver.try_recv().is_err(), "Only the second instruction reached the event, so there was one Sync.");
   }
-}
\ No newline at end of file
+}
@@ -6,6 +6,8 @@ use std::io;
 mod clock;
 mod chips;
 mod motherboards;
+mod terminal;
+mod capture;
 
 use std::fs::File;
 
@@ -14,12 +16,116 @@ use simplelog::*;
 fn main() -> io::Result<()> {
 //  TermLogger::init(LevelFilter::Debug, Config::default(), TerminalMode::Mixed, ColorChoice::Auto).unwrap();
 
-  CombinedLogger::init(vec![
-      TermLogger::new(LevelFilter::Debug, Config::default(), TerminalMode::Mixed, ColorChoice::Auto),
-      WriteLogger::new(LevelFilter::Trace, Config::default(), File::create("trace.log").unwrap()),
-  ]).unwrap();
+  let mut config = motherboards::ibm_xt::Config::default();
+  let mut args = std::env::args().skip(1);
+  while let Some(arg) = args.next() {
+    match arg.as_str() {
+      //--option-rom C8000=roms/ibm-mfm-1985-10-28.rom
+      "--option-rom" => {
+        let value = args.next().unwrap_or_default();
+        let (address, path) = value.split_once('=').ok_or_else(|| invalid_arg(&arg, &value))?;
+        let address = usize::from_str_radix(address, 16).map_err(|_| invalid_arg(&arg, &value))?;
+        config.option_roms.push(motherboards::ibm_xt::OptionROM{path: path.to_string(), address});
+      },
+      //--ram 512
+      "--ram" => {
+        let value = args.next().unwrap_or_default();
+        config.ram_kb = value.parse().map_err(|_| invalid_arg(&arg, &value))?;
+      },
+      //--ems 2048,E0000
+      "--ems" => {
+        let value = args.next().unwrap_or_default();
+        let (size_kb, frame) = value.split_once(',').unwrap_or((&value, "D0000"));
+        let size_kb = size_kb.parse().map_err(|_| invalid_arg(&arg, &value))?;
+        let frame = usize::from_str_radix(frame, 16).map_err(|_| invalid_arg(&arg, &value))?;
+        config.ems = Some(motherboards::ibm_xt::EmsConfig{size_kb, frame});
+      },
+      //--speaker-wav speaker.wav
+      "--speaker-wav" => {
+        config.speaker_wav = Some(args.next().ok_or_else(|| invalid_arg(&arg, ""))?);
+      },
+      //--sample-rate 22050
+      "--sample-rate" => {
+        let value = args.next().unwrap_or_default();
+        config.sample_rate = value.parse().map_err(|_| invalid_arg(&arg, &value))?;
+      },
+      "--dma-cycle-stealing" => config.dma_cycle_stealing = true,
+      "--turbo" => config.turbo = true,
+      "--terminal" => config.terminal = true,
+      "--composite" => config.composite = true,
+      //--type "dir\n"
+      "--type" => {
+        config.type_text = Some(args.next().ok_or_else(|| invalid_arg(&arg, ""))?);
+      },
+      "--max-speed" => config.max_speed = true,
+      //--video mda|cga40|cga80|ega|vga
+      "--video" => {
+        let value = args.next().unwrap_or_default();
+        config.video = match value.as_str() {
+          "mda" => motherboards::ibm_xt::Video::Mda,
+          "cga40" => motherboards::ibm_xt::Video::Cga40x25,
+          "cga80" => motherboards::ibm_xt::Video::Cga80x25,
+          "ega" => motherboards::ibm_xt::Video::Ega,
+          "vga" => motherboards::ibm_xt::Video::Vga,
+          _ => return Err(invalid_arg(&arg, &value)),
+        };
+      },
+      "--dual-monitor" => config.dual_monitor = true,
+      "--hercules" => config.hercules = true,
+      //--ega-memory 64|128|256
+      "--ega-memory" => {
+        let value = args.next().unwrap_or_default();
+        config.ega_memory_kb = value.parse().map_err(|_| invalid_arg(&arg, &value))?;
+      },
+      //--record screen.y4m, or --record frames/ for numbered PNG files
+      "--record" => {
+        config.record = Some(args.next().ok_or_else(|| invalid_arg(&arg, ""))?);
+      },
+      //Headless runs for regression tests: --cycles 14318180 --until "KB OK" --golden tests/golden/post.txt
+      "--cycles" => {
+        let value = args.next().unwrap_or_default();
+        config.check.get_or_insert_with(Default::default).cycles = value.parse().map_err(|_| invalid_arg(&arg, &value))?;
+      },
+      "--until" => {
+        config.check.get_or_insert_with(Default::default).until_text = Some(args.next().ok_or_else(|| invalid_arg(&arg, ""))?);
+      },
+      "--golden" => {
+        config.check.get_or_insert_with(Default::default).golden = Some(args.next().ok_or_else(|| invalid_arg(&arg, ""))?);
+      },
+      "--update-golden" => config.check.get_or_insert_with(Default::default).update_golden = true,
+      //Fault injection at a master clock cycle: --parity-error 5FFFE@143181800 --io-check 143181800
+      "--parity-error" => {
+        let value = args.next().unwrap_or_default();
+        let (addr, at) = value.split_once('@').ok_or_else(|| invalid_arg(&arg, &value))?;
+        let addr = usize::from_str_radix(addr, 16).map_err(|_| invalid_arg(&arg, &value))?;
+        let at = at.parse().map_err(|_| invalid_arg(&arg, &value))?;
+        config.faults.push(motherboards::ibm_xt::Fault::Parity{addr, at});
+      },
+      "--io-check" => {
+        let value = args.next().unwrap_or_default();
+        let at = value.parse().map_err(|_| invalid_arg(&arg, &value))?;
+        config.faults.push(motherboards::ibm_xt::Fault::IOCheck{at});
+      },
+      _ => return Err(invalid_arg(&arg, "")),
+    }
+  }
 
-  motherboards::ibm_xt::run()
+  //Log messages would scribble over the terminal front end, so they only go to the file then.
+  //Tracing every instruction would make a headless check take hours, so checks only log warnings.
+  let (file_level, term_level) = if config.check.is_some() { (LevelFilter::Warn, LevelFilter::Warn) } else { (LevelFilter::Trace, LevelFilter::Debug) };
+  let mut loggers: Vec<Box<dyn SharedLogger>> = vec![
+    WriteLogger::new(file_level, Config::default(), File::create("trace.log").unwrap()),
+  ];
+  if !config.terminal {
+    loggers.push(TermLogger::new(term_level, Config::default(), TerminalMode::Mixed, ColorChoice::Auto));
+  }
+  CombinedLogger::init(loggers).unwrap();
+
+  motherboards::ibm_xt::run(config)
+}
+
+fn invalid_arg(arg: &str, value: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid argument: {} {}", arg, value))
 }
 
 pub enum Msg {
@@ -27,6 +133,7 @@ pub enum Msg {
   Memory(MemoryMsg),
   PIC(PICMsg),
   CPU(CPUMsg),
+  Keyboard(KeyboardMsg),
 }
 
 pub enum MotherboardMsg {
@@ -34,6 +141,10 @@ pub enum MotherboardMsg {
   OutWord {port: u16, value: u16},
   InByte {port: u16, socket: mpsc::Sender<u8>},
   InWord {port: u16, socket: mpsc::Sender<u16>},
+  IOCheck,  //A device asserted I/O CHCK on the bus.
+  Sync {socket: mpsc::Sender<()>},  //The CPU reached the next scheduled event. Reply once the devices have caught up.
+  Screenshot,  //Save the screen to the next free screenshot-N.png, and screenshot-N.txt if it is text.
+  ToggleTurbo, //Flip the turbo switch.
 }
 pub enum MemoryMsg {
   SetByte{addr: usize, value: u8},
@@ -41,12 +152,24 @@ pub enum MemoryMsg {
   GetByte{addr: usize, socket: mpsc::Sender<u8>},
   GetWord{addr: usize, socket: mpsc::Sender<u16>},
   GetBytes8{addr: usize, socket: mpsc::Sender<u64>},
+  InjectParityError{addr: usize},
 }
 
 pub enum PICMsg {
-  PIT{select_counter: u8},
+  RaiseIRQ(u8),
+  LowerIRQ(u8),
+  Acknowledge{socket: mpsc::Sender<u8>},  //INTA. Replies with the interrupt vector.
 }
 
 pub enum CPUMsg {
-  Interrupt(u8),
+  INTR(bool),
+  NMI,
+  StealCycles(usize), //Another bus master, such as DMA, held the bus.
+}
+
+/// Input from the host, or from a script.
+pub enum KeyboardMsg {
+  KeyDown(u8),  //Scancode set 1 make code.
+  KeyUp(u8),
+  Type(String),
 }
\ No newline at end of file
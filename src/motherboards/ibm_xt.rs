@@ -1,15 +1,25 @@
 use crate::MotherboardMsg;
 use crate::clock;
+use crate::terminal;
+use crate::capture;
 
 use std::sync::mpsc;
 
 use std::io;
 use std::io::prelude::*;
 use std::fs::File;
+use std::path::Path;
 
 use crate::chips::*;
 
-use log::debug;
+use log::{debug, error};
+
+const DMA_CYCLES: usize = 4;  //CPU clocks lost to each DMA transfer.
+const DISPLAY_TIME: u64 = clock::MASTER_HZ / 30;  //How often front ends get to draw the screen.
+//Until the BIOS programs the CRTC, its frame length means nothing. Keep recordings between 10 and 100 frames a second.
+const MIN_FRAME_TIME: u64 = clock::MASTER_HZ / 100;
+const MAX_FRAME_TIME: u64 = clock::MASTER_HZ / 10;
+const CHECK_TIME: u64 = DISPLAY_TIME;  //How often a headless run looks at the screen.
 
 /*
 BIOS Memory changes:
@@ -20,105 +30,753 @@ BIOS Memory changes:
 [0410] = 0000  Bit flags for detected hardware, set based on the PPI.
 */
 
-pub fn run() -> io::Result<()> {
+/// An expansion card ROM, such as roms/ibm-mfm-1985-10-28.rom at C8000 or roms/ibm-vga-1986-10-27.rom at C0000.
+pub struct OptionROM {
+  pub path: String,
+  pub address: usize,
+}
+
+/// Expanded memory board.
+pub struct EmsConfig {
+  pub size_kb: usize,
+  pub frame: usize, //D0000 or E0000
+}
+
+/// The display the BIOS starts up on. This is what the PPI video switches are set to.
+#[derive(Debug, Clone, Copy)]
+pub enum Video {
+  Mda,
+  Cga40x25,
+  Cga80x25,
+  Ega,  //Sets itself up from its own ROM at C0000.
+  Vga,  //Sets itself up from its own ROM at C0000.
+}
+
+/// Fault injection, to exercise the NMI handlers. The POST memory test rewrites every byte,
+/// which clears parity faults, so faults are injected at a master clock cycle rather than at power on.
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+  Parity{addr: usize, at: u64},  //The next read of this RAM byte fails its parity check.
+  IOCheck{at: u64},  //A device asserts I/O CHCK.
+}
+
+impl Fault {
+  fn at(&self) -> u64 {
+    match self {
+      Fault::Parity{at, ..} | Fault::IOCheck{at} => *at,
+    }
+  }
+}
+
+/// A headless run, for regression tests. Stops once the text shows up on screen, or after a number of master clock cycles,
+/// then compares the screen against a golden file.
+pub struct Check {
+  pub cycles: u64,
+  pub until_text: Option<String>,  //Fail unless this shows up on the text screen in time.
+  pub golden: Option<String>,  //A .txt file compares the text screen. A .png or .ppm file compares the rendered frame.
+  pub update_golden: bool,  //Write the golden file from this run, instead of comparing against it.
+}
+
+impl Default for Check {
+  fn default() -> Self {
+    Check {
+      cycles: clock::MASTER_HZ * 60,
+      until_text: None,
+      golden: None,
+      update_golden: false,
+    }
+  }
+}
+
+pub struct Config {
+  pub option_roms: Vec<OptionROM>,
+  pub ram_kb: usize,
+  pub ems: Option<EmsConfig>,
+  pub speaker_wav: Option<String>,
+  pub sample_rate: u32,
+  pub dma_cycle_stealing: bool,  //Slow the CPU down by the bus cycles DMA (mostly DRAM refresh) takes.
+  pub turbo: bool,  //Let the FE2010A configuration register pick the CPU speed, instead of holding it at 4.77 MHz.
+  pub max_speed: bool,  //Run as fast as the host allows.
+  pub type_text: Option<String>,  //Typed on the keyboard once it has passed its self test.
+  pub terminal: bool,  //Show the text screen in the host terminal, and take keys from it.
+  pub composite: bool,  //Render CGA graphics as a composite monitor would, with artifact colors.
+  pub video: Video,
+  pub dual_monitor: bool,  //Also install the MDA, or the CGA if the MDA is primary, as the secondary display.
+  pub hercules: bool,  //The monochrome adapter is a Hercules Graphics Card.
+  pub ega_memory_kb: usize,  //64, 128 or 256.
+  pub record: Option<String>,  //Record every frame of the primary screen, to a .y4m file or a directory of PNG files.
+  pub check: Option<Check>,  //Run headlessly and check the screen, instead of running forever.
+  pub faults: Vec<Fault>,
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      option_roms: Vec::new(),
+      ram_kb: 640,
+      ems: None,
+      speaker_wav: None,
+      sample_rate: 44100,
+      dma_cycle_stealing: false,
+      turbo: false,
+      max_speed: false,
+      type_text: None,
+      terminal: false,
+      composite: false,
+      video: Video::Cga80x25,
+      dual_monitor: false,
+      hercules: false,
+      ega_memory_kb: 256,
+      record: None,
+      check: None,
+      faults: Vec::new(),
+    }
+  }
+}
+
+/// The DRAM refresh flip-flop on DMA channel 0. PIT counter 1 sets it, and DACK0 clears it, so each request gets one read cycle.
+struct RefreshRequest;
+
+impl dma::DMADevice for RefreshRequest {
+  fn read(&mut self) -> u8 {
+    0xFF
+  }
+  fn write(&mut self, _value: u8) {}
+  fn dreq(&self) -> bool {
+    false
+  }
+}
+
+/// Where a headless run stops.
+struct Stop {
+  at: u64,
+  text: Option<String>,
+  text_seen: bool,
+  stopped: bool,
+}
+
+pub struct Machine {
+  scheduler: clock::Scheduler,
+  to_bus: mpsc::Sender<crate::Msg>,
+  from_chip: mpsc::Receiver<crate::Msg>,
+  memory: memory1mb::Memory,
+  ems: Option<ems::Ems>,
+  pic: pic::Cascade,
+  dma: dma::DMA,
+  pit: pit::PIT,
+  faraday: faraday::PPI,
+  keyboard: keyboard::Keyboard,
+  speaker: Option<speaker::Speaker>,
+  mda: Option<mda::Mda>,
+  cga: Option<cga::Cga>,
+  vga: Option<vga::Vga>,
+  video: Video,
+  cpu: cpu8086::CPUController,
+  terminal: Option<terminal::Terminal>,
+  recorder: Option<capture::Recorder>,
+  screenshots: usize,  //Taken with the hotkey so far, to number the files.
+  stop: Option<Stop>,
+  faults: Vec<Fault>,  //Still to be injected, latest first.
+  dma_cycle_stealing: bool,
+  turbo: bool,
+}
+
+pub fn run(mut config: Config) -> io::Result<()> {
+  let check = config.check.take();
+  let mut machine = start(config)?;
+  if let Some(check) = check {
+    return machine.run_check(&check);
+  }
+  loop {
+    machine.step();
+  }
+}
+
+pub fn start(config: Config) -> io::Result<Machine> {
+  check_ram(&config)?;
   let mut f = File::open("roms/ibm-xt-1986-05-09.rom")?;
   let mut bios_rom = Vec::new();
   f.read_to_end(&mut bios_rom)?;
   
-//  f = File::open("roms/ibm-mfm-1985-10-28.rom")?;
-//  let mut video_rom = Vec::new();
-//  f.read_to_end(&mut video_rom)?;
-  let mut video_rom = Vec::new();
-  
   let (to_bus, from_chip) = mpsc::channel();
   
-  let mut clock = clock::init(210); //4.77 Mhz = 210 nanosecond delay.
-  let mut memory = memory1mb::start(&mut bios_rom, &mut video_rom);
-  let mut pic = pic::start(to_bus.clone());
+  let (mut scheduler, cpu_clock) = clock::init(to_bus.clone());
+  scheduler.set_throttle(!config.max_speed);
+  let mut memory = memory1mb::start(&bios_rom, config.ram_kb);
+  for option_rom in &config.option_roms {
+    let mut rom = Vec::new();
+    File::open(&option_rom.path)?.read_to_end(&mut rom)?;
+    memory.map_option_rom(option_rom.address, &rom)?;
+    debug!("Loaded option ROM {} at {:05X}", option_rom.path, option_rom.address);
+  }
+  let ems = config.ems.map(|ems_config| ems::start(ems_config.size_kb, ems_config.frame)).transpose()?;
+  if let Some(ems) = &ems {
+    let (frame, page_frame) = ems.page_frame();
+    if memory.has_rom(frame, 0x1_0000) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The EMS page frame at {:05X} overlaps an option ROM.", frame)));
+    }
+    memory.map_device(frame, 0x1_0000, Box::new(page_frame));
+  }
+  let pic = pic::start(to_bus.clone(), false);  //The XT has no slave PIC.
   let mut dma = dma::start();
-  let mut pit = pit::start(to_bus.clone(), (clock.add(4), clock.add(4), clock.add(4)));
-  let mut faraday = faraday::start();
-  let mut graphics = graphics::start();
-  let mut cpu = cpu8086::start(to_bus.clone(), clock.add(1));
-  clock.start();
+  dma.connect(0, Box::new(RefreshRequest));
+  let mut pit = pit::start();
+  let mut faraday = faraday::start(config.ram_kb);
+  faraday.set_initial_video(match config.video {
+    Video::Mda => faraday::InitialVideo::Mda,
+    Video::Cga40x25 => faraday::InitialVideo::Cga40x25,
+    Video::Cga80x25 => faraday::InitialVideo::Cga80x25,
+    Video::Ega | Video::Vga => faraday::InitialVideo::Ega,
+  });
+  pit.2.set_gate(faraday.get_timer_2_gate());
+  let mut keyboard = keyboard::start();
+  if let Some(text) = &config.type_text {
+    keyboard.type_string(text);
+  }
+  let speaker = match &config.speaker_wav {
+    Some(path) => {
+      let sink = Box::new(speaker::wav(path, config.sample_rate)?);
+      let speaker = speaker::start(sink, config.sample_rate);
+      scheduler.schedule(clock::Event::Speaker, speaker.next_sample_time());
+      Some(speaker)
+    },
+    None => None,
+  };
+  //The EGA and VGA answer in A0000-BFFFF, wherever the graphics controller puts the window, so they are mapped first for an MDA to take B0000.
+  let vga = if let Some((model, rom_path, memory_kb)) = match config.video {
+    Video::Ega => Some((vga::Model::Ega, vga::EGA_ROM_PATH, config.ega_memory_kb)),
+    Video::Vga => Some((vga::Model::Vga, vga::ROM_PATH, 256)),
+    _ => None,
+  } {
+    if !matches!(memory_kb, 64 | 128 | 256) {
+      return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported EGA memory size: {}KB. It must be 64KB, 128KB or 256KB.", memory_kb)));
+    }
+    let mut rom = Vec::new();
+    File::open(rom_path)?.read_to_end(&mut rom)?;
+    memory.map_option_rom(vga::ROM_ADDRESS, &rom)?;
+    let vga = vga::start(model, memory_kb);
+    memory.map_device(vga::MEMORY_START, vga::MEMORY_WINDOW, Box::new(vga.video_memory()));
+    Some(vga)
+  } else {
+    None
+  };
+  let mda = if config.dual_monitor || matches!(config.video, Video::Mda) {
+    let mda = mda::start(config.hercules);
+    memory.map_device(mda::MEMORY_START, mda.memory_window(), mda.video_memory());
+    Some(mda)
+  } else {
+    None
+  };
+  let cga = if matches!(config.video, Video::Cga40x25 | Video::Cga80x25) || (config.dual_monitor && matches!(config.video, Video::Mda)) {
+    //Mapped after the monochrome card, so it takes B8000 even from a Hercules.
+    let mut cga = cga::start();
+    cga.set_composite(config.composite);
+    memory.map_device(cga::MEMORY_START, cga::MEMORY_WINDOW, Box::new(cga.video_memory()));
+    Some(cga)
+  } else {
+    None
+  };
+  let cpu = cpu8086::start(to_bus.clone(), cpu_clock);
 
-  loop {
-    match from_chip.recv().unwrap() {
-      crate::Msg::Memory(sub_msg) => memory.process_msg(sub_msg),
-      crate::Msg::PIC(sub_msg) => pic.process_msg(sub_msg),
-      crate::Msg::CPU(sub_msg) => cpu.process_msg(sub_msg),
-      crate::Msg::Motherboard(sub_msg) => match sub_msg {
-        MotherboardMsg::OutByte{port, value} => match port {
-          0x00 => dma.set_address(0, value),
-          0x01 => dma.set_count(0, value),
-          0x02 => dma.set_address(1, value),
-          0x03 => dma.set_count(1, value),
-          0x04 => dma.set_address(2, value),
-          0x05 => dma.set_count(2, value),
-          0x06 => dma.set_address(3, value),
-          0x07 => dma.set_count(3, value),
-          0x08 => dma.set_status(value),
-          0x0A => dma.set_mask(value),
-          0x0B => dma.set_mode(value),
-          0x0C => dma.reset_flip_flop(),
-          0x0D => dma.reset_master(),
-          0x0E => dma.reset_mask(),
-          0x0F => dma.set_masks(value),
-          0x20 => pic.out_port_1(value),
-          0x21 => pic.out_port_2(value),
-          0x40 => pit.0.set_count(value),
-          0x41 => pit.1.set_count(value),
-          0x42 => pit.2.set_count(value),
-          0x43 => pit.set_control_word(value),
-          0x60 => faraday.write_port_a(value),
-          0x61 => faraday.write_port_b(value),
-          0x63 => faraday.set_configuration(value),
-          0x83 => debug!("083 - High order 4 bits of DMA channel 1 address {:X}", value),
-          0xA0 => faraday.set_nmi(value),
-          0x210 => debug!("OUT Expansion Card Port - {:X}", value),
-          0x3B4 => graphics.choose_register(value),
-          0x3B5 => graphics.set_register_data(value),
-          0x3B8 => graphics.set_mode_bw(value),
-          0x3B9 => debug!("Port 3B9 got {}. Don't know what this means!", value),
-          0x3D4 => graphics.choose_register(value),
-          0x3D5 => graphics.set_register_data(value),
-          0x3D8 => graphics.set_mode_color(value),
-          0x3D9 => debug!("Port 3D9 got {}. Don't know what this means!", value),
-          _ => unimplemented!("OUT {:X}, {:X}", port, value),
+  let mut machine = Machine {
+    scheduler,
+    to_bus,
+    from_chip,
+    memory,
+    ems,
+    pic,
+    dma,
+    pit,
+    faraday,
+    keyboard,
+    speaker,
+    mda,
+    cga,
+    vga,
+    video: config.video,
+    cpu,
+    terminal: None,
+    recorder: None,
+    screenshots: 0,
+    stop: None,
+    faults: config.faults,
+    dma_cycle_stealing: config.dma_cycle_stealing,
+    turbo: false,
+  };
+  machine.set_turbo(config.turbo);
+  machine.faults.sort_by_key(|fault| std::cmp::Reverse(fault.at()));
+  machine.schedule_fault();
+  if config.terminal {
+    machine.terminal = Some(terminal::start(machine.messenger())?);
+    machine.scheduler.schedule(clock::Event::Display, DISPLAY_TIME);
+  }
+  if let Some(path) = &config.record {
+    machine.recorder = Some(capture::start(path)?);
+    machine.scheduler.schedule(clock::Event::Capture, machine.frame_time());
+  }
+  Ok(machine)
+}
+
+/// Installed RAM is 64KB to 640KB in 64KB banks, or 704KB/736KB for boards which fill upper memory.
+/// 704KB and 736KB reach B0000 and B8000, so they can't sit under a video adapter's memory.
+fn check_ram(config: &Config) -> io::Result<()> {
+  let invalid = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+  if !(matches!(config.ram_kb, 64..=640) && config.ram_kb.is_multiple_of(64) || matches!(config.ram_kb, 704 | 736)) {
+    return invalid(format!("Unsupported RAM size: {}KB. It must be 64KB to 640KB in 64KB steps, 704KB or 736KB.", config.ram_kb));
+  }
+  let mda = config.dual_monitor || matches!(config.video, Video::Mda);
+  let video_start = match config.video {
+    Video::Ega | Video::Vga => vga::MEMORY_START,
+    _ if mda => mda::MEMORY_START,
+    _ => cga::MEMORY_START,
+  };
+  if config.ram_kb * 1024 > video_start {
+    return invalid(format!("{}KB of RAM runs into the video memory at {:05X}.", config.ram_kb, video_start));
+  }
+  Ok(())
+}
+
+impl Machine {
+  /// Handle one message from the CPU or a device.
+  pub fn step(&mut self) {
+    match self.from_chip.recv().unwrap() {
+      crate::Msg::Memory(sub_msg) => self.memory.process_msg(sub_msg),
+      crate::Msg::PIC(sub_msg) => self.pic.process_msg(sub_msg),
+      crate::Msg::CPU(sub_msg) => self.cpu.process_msg(sub_msg),
+      crate::Msg::Motherboard(sub_msg) => self.process_msg(sub_msg),
+      crate::Msg::Keyboard(sub_msg) => {
+        self.catch_up();
+        let now = self.scheduler.now();
+        match sub_msg {
+          crate::KeyboardMsg::KeyDown(scancode) => self.keyboard.key_down(now, scancode),
+          crate::KeyboardMsg::KeyUp(scancode) => self.keyboard.key_up(scancode),
+          crate::KeyboardMsg::Type(text) => self.keyboard.type_string(&text),
+        }
+        self.update_keyboard();
+      },
+    }
+    //Devices take the bus between CPU cycles.
+    if self.dma.has_request() {
+      let transfers = self.dma.service(&mut self.memory);
+      if self.dma_cycle_stealing {
+        self.cpu.process_msg(crate::CPUMsg::StealCycles(transfers * DMA_CYCLES));
+      }
+    }
+    if self.memory.take_parity_error() && self.faraday.parity_error() {
+      self.cpu.process_msg(crate::CPUMsg::NMI);
+    }
+    self.scheduler.publish();
+  }
+
+  /// Run until the text shows up on the primary screen, or for this many master clock cycles.
+  /// Returns whether the text was seen. The screen is looked at every CHECK_TIME.
+  pub fn run_until(&mut self, cycles: u64, text: Option<&str>) -> bool {
+    let at = self.scheduler.now() + cycles;
+    self.stop = Some(Stop {
+      at,
+      text: text.map(str::to_string),
+      text_seen: false,
+      stopped: false,
+    });
+    self.scheduler.schedule(clock::Event::Check, (self.scheduler.now() + CHECK_TIME).min(at));
+    while self.stop.as_ref().is_some_and(|stop| !stop.stopped) {
+      self.step();
+    }
+    self.scheduler.cancel(clock::Event::Check);
+    self.stop.take().is_some_and(|stop| stop.text_seen)
+  }
+
+  /// A headless run. Fails if the text never showed up, or the screen doesn't match the golden file.
+  pub fn run_check(&mut self, check: &Check) -> io::Result<()> {
+    let text_seen = self.run_until(check.cycles, check.until_text.as_deref());
+    if let Some(text) = &check.until_text {
+      if !text_seen {
+        return Err(io::Error::other(format!("{:?} did not show up on screen within {} cycles", text, check.cycles)));
+      }
+    }
+    if let Some(golden) = &check.golden {
+      let path = Path::new(golden);
+      let actual = if path.extension().is_some_and(|extension| extension == "txt") {
+        self.screen_text().ok_or_else(|| io::Error::other("The screen is in a graphics mode"))?.into_bytes()
+      } else {
+        capture::encode_frame(&self.render_screen(), path)?
+      };
+      capture::check_golden(&actual, path, check.update_golden)?;
+    }
+    Ok(())
+  }
+
+  /// For host front ends and scripts to send keyboard input from another thread.
+  pub fn messenger(&self) -> mpsc::Sender<crate::Msg> {
+    self.to_bus.clone()
+  }
+
+  /// The primary screen as the monitor shows it right now.
+  pub fn render_screen(&self) -> framebuffer::Framebuffer {
+    let now = self.scheduler.now();
+    match (self.video, &self.mda, &self.cga, &self.vga) {
+      (Video::Mda, Some(mda), _, _) => mda.render(now),
+      (Video::Ega | Video::Vga, _, _, Some(vga)) => vga.render(now),
+      (_, _, Some(cga), _) => cga.render(now),
+      _ => unreachable!("The primary video adapter is always installed."),
+    }
+  }
+
+  /// The primary screen, if it is showing text.
+  fn text_screen(&self) -> Option<crtc::TextScreen> {
+    match (self.video, &self.mda, &self.cga, &self.vga) {
+      (Video::Mda, Some(mda), _, _) => mda.text_screen(),
+      (Video::Ega | Video::Vga, _, _, Some(vga)) => vga.text_screen(),
+      (_, _, Some(cga), _) => cga.text_screen(),
+      _ => None,
+    }
+  }
+
+  /// The characters on the primary screen, one line per row. None in graphics modes.
+  pub fn screen_text(&self) -> Option<String> {
+    self.text_screen().map(|screen| capture::screen_text(&screen))
+  }
+
+  /// Save the primary screen as PNG, or as PPM if the path ends in .ppm.
+  pub fn save_screenshot(&self, path: &Path) -> io::Result<()> {
+    capture::save_frame(&self.render_screen(), path)
+  }
+
+  /// Save the characters on the primary screen as UTF-8 text. Fails in graphics modes.
+  pub fn save_screen_text(&self, path: &Path) -> io::Result<()> {
+    let text = self.screen_text().ok_or_else(|| io::Error::other("The screen is in a graphics mode"))?;
+    std::fs::write(path, text)
+  }
+
+  /// Master clock cycles between frames on the primary screen.
+  fn frame_time(&self) -> u64 {
+    let frame_time = match (self.video, &self.mda, &self.cga, &self.vga) {
+      (Video::Mda, Some(mda), _, _) => mda.frame_time(),
+      (Video::Ega | Video::Vga, _, _, Some(vga)) => vga.frame_time(),
+      (_, _, Some(cga), _) => cga.frame_time(),
+      _ => unreachable!("The primary video adapter is always installed."),
+    };
+    frame_time.clamp(MIN_FRAME_TIME, MAX_FRAME_TIME)
+  }
+
+  /// The screenshot hotkey. Saves screenshot-N.png, and screenshot-N.txt when the screen is text.
+  fn take_screenshot(&mut self) {
+    self.screenshots += 1;
+    let name = format!("screenshot-{}", self.screenshots);
+    let result = self.save_screenshot(Path::new(&format!("{}.png", name)))
+      .and_then(|_| match self.text_screen() {
+        Some(_) => self.save_screen_text(Path::new(&format!("{}.txt", name))),
+        None => Ok(()),
+      });
+    match result {
+      Ok(()) => debug!("Saved {}", name),
+      Err(err) => error!("Could not save {}: {}", name, err),
+    }
+  }
+
+  fn io_check(&mut self) {
+    if self.faraday.io_check() {
+      self.cpu.process_msg(crate::CPUMsg::NMI);
+    }
+  }
+
+  /// Inject the faults which are due, and schedule the next one.
+  fn inject_faults(&mut self) {
+    while let Some(fault) = self.faults.last().copied().filter(|fault| fault.at() <= self.scheduler.now()) {
+      self.faults.pop();
+      debug!("Injecting {:?}", fault);
+      match fault {
+        Fault::Parity{addr, ..} => self.memory.inject_parity_error(addr),
+        Fault::IOCheck{..} => self.io_check(),
+      }
+    }
+    self.schedule_fault();
+  }
+
+  fn schedule_fault(&mut self) {
+    match self.faults.last() {
+      Some(fault) => self.scheduler.schedule(clock::Event::Fault, fault.at()),
+      None => self.scheduler.cancel(clock::Event::Fault),
+    }
+  }
+
+  /// Stop a headless run once its text is on screen, or its time is up.
+  fn check_stop(&mut self) {
+    let now = self.scheduler.now();
+    let screen_text = self.screen_text();
+    let Some(stop) = &mut self.stop else { return; };
+    stop.text_seen = match (&stop.text, &screen_text) {
+      (Some(text), Some(screen_text)) => screen_text.contains(text.as_str()),
+      _ => false,
+    };
+    if stop.text_seen || now >= stop.at {
+      stop.stopped = true;
+      return;
+    }
+    self.scheduler.schedule(clock::Event::Check, (now + CHECK_TIME).min(stop.at));
+  }
+
+  /// Add the current frame to the recording, and schedule the next one a frame later.
+  fn capture_frame(&mut self) {
+    let frame_time = self.frame_time();
+    let frame = self.render_screen();
+    let Some(recorder) = &mut self.recorder else { return; };
+    if let Err(err) = recorder.add_frame(&frame, frame_time) {
+      error!("Stopped recording: {}", err);
+      self.recorder = None;
+      return;
+    }
+    self.scheduler.schedule(clock::Event::Capture, self.scheduler.now() + frame_time);
+  }
+
+  /// The turbo switch. Off holds the CPU at 4.77 MHz, whatever speed the configuration register asks for.
+  pub fn set_turbo(&mut self, turbo: bool) {
+    self.turbo = turbo;
+    self.update_cpu_speed();
+  }
+
+  fn update_cpu_speed(&mut self) {
+    let speed = if self.turbo {
+      self.faraday.get_cpu_speed()
+    } else {
+      faraday::CPUSpeed::MHz477
+    };
+    self.scheduler.set_cpu_half_cycles(speed.half_cycles());
+  }
+
+  fn process_msg(&mut self, msg: MotherboardMsg) {
+    match msg {
+      MotherboardMsg::OutByte{port, value} => {
+        self.catch_up();
+        self.out_byte(port, value);
+        self.update_pit();  //The write may have changed when the PIT next needs attention.
+      },
+      //The 8088 has an 8 bit bus, so word I/O is two byte cycles: the low byte to port, then the high byte to port + 1.
+      MotherboardMsg::OutWord{port, value} => {
+        self.catch_up();
+        self.out_byte(port, value as u8);
+        self.out_byte(port.wrapping_add(1), (value >> 8) as u8);
+        self.update_pit();
+      },
+      MotherboardMsg::InByte{port, socket} => {
+        self.catch_up();
+        let response = self.in_byte(port);
+        socket.send(response).unwrap();
+      },
+      MotherboardMsg::InWord{port, socket} => {
+        self.catch_up();
+        let low = self.in_byte(port);
+        let high = self.in_byte(port.wrapping_add(1));
+        socket.send(u16::from_le_bytes([low, high])).unwrap();
+      },
+      MotherboardMsg::IOCheck => self.io_check(),
+      MotherboardMsg::Sync{socket} => {
+        self.catch_up();
+        self.scheduler.publish();
+        socket.send(()).unwrap();
+      },
+      MotherboardMsg::Screenshot => {
+        self.catch_up();
+        self.take_screenshot();
+      },
+      MotherboardMsg::ToggleTurbo => {
+        self.catch_up();  //Everything up to now ran at the old speed.
+        self.set_turbo(!self.turbo);
+        debug!("Turbo {}", if self.turbo { "on" } else { "off" });
+      },
+    }
+  }
+
+  /// Run the scheduled device events up to where the CPU has got to.
+  fn catch_up(&mut self) {
+    let until = self.scheduler.cpu_time();
+    while let Some(event) = self.scheduler.next_due(until) {
+      match event {
+        clock::Event::Pit => self.update_pit(),
+        clock::Event::Speaker => if let Some(speaker) = &mut self.speaker {
+          speaker.sample(self.scheduler.now());
+          self.scheduler.schedule(clock::Event::Speaker, speaker.next_sample_time());
         },
-        MotherboardMsg::OutWord{port, value} => {
-          unimplemented!("OUT {:X}, {:X}", port, value);
+        clock::Event::Keyboard => self.update_keyboard(),
+        clock::Event::Display => {
+          let screen = self.text_screen();
+          if let Some(terminal) = &mut self.terminal {
+            terminal.draw(screen);
+          }
+          self.scheduler.schedule(clock::Event::Display, self.scheduler.now() + DISPLAY_TIME);
         },
-        MotherboardMsg::InByte{port, socket} => {
-          let response = match port {
-            0x00 => dma.get_address(0),
-            0x01 => dma.get_count(0),
-            0x02 => dma.get_address(1),
-            0x03 => dma.get_count(1),
-            0x04 => dma.get_address(2),
-            0x05 => dma.get_count(2),
-            0x06 => dma.get_address(3),
-            0x07 => dma.get_count(3),
-            0x08 => dma.get_status(),
-            0x20 => pic.in_port_1(),
-            0x21 => pic.get_irqs_enabled(),
-            0x40 => pit.0.get_count(),
-            0x41 => pit.1.get_count(),
-            0x42 => pit.2.get_count(),
-            0x60 => faraday.read_port_a(),
-            0x61 => faraday.read_port_b(),
-            0x62 => faraday.read_port_c(),
-            0x210 => {debug!("IN Expansion Card Port"); 0},
-            0x3B8 => graphics.get_mode_bw(),
-            _ => unimplemented!("IN {:X}", port),
-          };
-          socket.send(response).unwrap();
+        clock::Event::Capture => self.capture_frame(),
+        clock::Event::Check => self.check_stop(),
+        clock::Event::Fault => self.inject_faults(),
+        clock::Event::Throttle => self.scheduler.throttle(),
+      }
+    }
+    self.update_pit();
+  }
+
+  /// Bring the PIT up to date, route its OUT changes, and schedule when it next needs attention.
+  fn update_pit(&mut self) {
+    let tick = self.scheduler.now() / clock::PIT_DIVISOR;
+    for change in self.pit.advance(tick) {
+      match change.select_counter {
+        //IRQ0
+        0 => if change.output {
+          self.pic.raise_irq(0);
+        } else {
+          self.pic.lower_irq(0);
         },
-        MotherboardMsg::InWord{port, socket:_} => {
-          unimplemented!("IN {:X}", port);
+        //Counter 1 OUT requests a DRAM refresh cycle.
+        1 => if change.output {
+          self.dma.set_dreq(0, true);
         },
+        _ => if let Some(speaker) = &mut self.speaker {
+          speaker.set_input(change.tick * clock::PIT_DIVISOR, change.output && self.faraday.get_speaker_enable());
+        },
+      }
+    }
+    match self.pit.ticks_to_next_event() {
+      Some(ticks) => self.scheduler.schedule(clock::Event::Pit, (tick + ticks) * clock::PIT_DIVISOR),
+      None => self.scheduler.cancel(clock::Event::Pit),
+    }
+  }
 
+  /// Move the next scancode into the PPI if it can take it, and schedule when the keyboard next needs attention.
+  fn update_keyboard(&mut self) {
+    let now = self.scheduler.now();
+    self.keyboard.update(now);
+    if self.faraday.keyboard_ready() {
+      if let Some(scancode) = self.keyboard.take(now) {
+        self.faraday.receive_scancode(scancode);
+        self.pic.raise_irq(1);
       }
     }
+    match self.keyboard.next_event_time(self.faraday.keyboard_ready()) {
+      Some(at) => self.scheduler.schedule(clock::Event::Keyboard, at),
+      None => self.scheduler.cancel(clock::Event::Keyboard),
+    }
+  }
+
+  fn out_byte(&mut self, port: u16, value: u8) {
+    match port {
+      0x00 => self.dma.set_address(0, value),
+      0x01 => self.dma.set_count(0, value),
+      0x02 => self.dma.set_address(1, value),
+      0x03 => self.dma.set_count(1, value),
+      0x04 => self.dma.set_address(2, value),
+      0x05 => self.dma.set_count(2, value),
+      0x06 => self.dma.set_address(3, value),
+      0x07 => self.dma.set_count(3, value),
+      0x08 => self.dma.set_status(value),
+      0x09 => self.dma.set_request(value),
+      0x0A => self.dma.set_mask(value),
+      0x0B => self.dma.set_mode(value),
+      0x0C => self.dma.reset_flip_flop(),
+      0x0D => self.dma.reset_master(),
+      0x0E => self.dma.reset_mask(),
+      0x0F => self.dma.set_masks(value),
+      0x20 => self.pic.out_port_1(0, value),
+      0x21 => self.pic.out_port_2(0, value),
+      0x40 => self.pit.0.set_count(value),
+      0x41 => self.pit.1.set_count(value),
+      0x42 => self.pit.2.set_count(value),
+      0x43 => self.pit.set_control_word(value),
+      0x60 => self.faraday.write_port_a(value),
+      0x61 => {
+        self.faraday.write_port_b(value);
+        self.pit.2.set_gate(self.faraday.get_timer_2_gate());
+        if let Some(speaker) = &mut self.speaker {
+          speaker.set_input(self.scheduler.now(), self.faraday.get_speaker_enable() && self.pit.2.get_output());
+        }
+        self.keyboard.set_clock(self.scheduler.now(), self.faraday.get_keyboard_clock());
+        if self.faraday.get_keyboard_clear() {
+          self.pic.lower_irq(1);
+        }
+        self.update_keyboard();
+      },
+      0x63 => {
+        self.faraday.set_configuration(value);
+        self.update_cpu_speed();
+      },
+      0x81 => self.dma.set_page(2, value),
+      0x82 => self.dma.set_page(3, value),
+      0x83 => self.dma.set_page(1, value),
+      0x87 => self.dma.set_page(0, value),
+      0xA0 => self.faraday.set_nmi(value),
+      0x210 => debug!("OUT Expansion Card Port - {:X}", value),
+      port if self.vga.as_ref().is_some_and(|vga| vga.handles_port(port)) => self.vga.as_mut().unwrap().out_byte(port, value),
+      0x3B0..=0x3BF if self.mda.is_some() => self.mda.as_mut().unwrap().out_byte(port, value),
+      0x3D0..=0x3DF if self.cga.is_some() => self.cga.as_mut().unwrap().out_byte(port, value, self.scheduler.now()),
+      port if self.ems.as_ref().is_some_and(|ems| ems.handles_port(port)) => self.ems.as_mut().unwrap().out_byte(port, value),
+      _ => debug!("OUT {:X}, {:X} with nothing there", port, value),
+    }
+  }
+
+  fn in_byte(&mut self, port: u16) -> u8 {
+    match port {
+      0x00 => self.dma.get_address(0),
+      0x01 => self.dma.get_count(0),
+      0x02 => self.dma.get_address(1),
+      0x03 => self.dma.get_count(1),
+      0x04 => self.dma.get_address(2),
+      0x05 => self.dma.get_count(2),
+      0x06 => self.dma.get_address(3),
+      0x07 => self.dma.get_count(3),
+      0x08 => self.dma.get_status(),
+      0x20 => self.pic.in_port_1(0),
+      0x21 => self.pic.get_irqs_enabled(0),
+      0x40 => self.pit.0.get_count(),
+      0x41 => self.pit.1.get_count(),
+      0x42 => self.pit.2.get_count(),
+      0x60 => self.faraday.read_port_a(),
+      0x61 => self.faraday.read_port_b(),
+      0x62 => self.faraday.read_port_c(self.pit.2.get_output()),
+      0x210 => {debug!("IN Expansion Card Port"); 0},
+      port if self.vga.as_ref().is_some_and(|vga| vga.handles_port(port)) => self.vga.as_mut().unwrap().in_byte(port, self.scheduler.now()),
+      0x3B0..=0x3BF if self.mda.is_some() => self.mda.as_mut().unwrap().in_byte(port, self.scheduler.now()),
+      0x3D0..=0x3DF if self.cga.is_some() => self.cga.as_mut().unwrap().in_byte(port, self.scheduler.now()),
+      port if self.ems.as_ref().is_some_and(|ems| ems.handles_port(port)) => self.ems.as_ref().unwrap().in_byte(port),
+      _ => {
+        debug!("IN {:X} with nothing there", port);
+        0xFF  //Nothing drives the data bus, so it floats high.
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn ram_ok(ram_kb: usize, video: Video) -> bool {
+    check_ram(&Config { ram_kb, video, ..Default::default() }).is_ok()
+  }
+
+  #[test]
+  fn ram_is_64kb_banks_up_to_640kb() {
+    for ram_kb in (64..=640).step_by(64) {
+      assert!(ram_ok(ram_kb, Video::Cga80x25), "{}KB", ram_kb);
+    }
+    for ram_kb in [0, 32, 96, 600, 768, 1024] {
+      assert!(!ram_ok(ram_kb, Video::Cga80x25), "{}KB", ram_kb);
+    }
+  }
+
+  #[test]
+  fn upper_memory_stops_at_the_video_memory() {
+    assert!(ram_ok(736, Video::Cga80x25));
+    assert!(ram_ok(704, Video::Mda));
+    assert!(!ram_ok(736, Video::Mda));
+    assert!(check_ram(&Config { ram_kb: 736, dual_monitor: true, ..Default::default() }).is_err());
+    assert!(!ram_ok(704, Video::Vga));
+    assert!(ram_ok(640, Video::Vga));
+  }
+
+  #[test]
+  fn ports_with_nothing_there_read_ff_and_ignore_writes() {
+    let mut machine = start(Config { video: Video::Cga80x25, ..Default::default() }).unwrap();
+    for port in [0x09, 0x0F, 0x3B8, 0x278, 0x3F8, 0xFFFF] {
+      machine.out_byte(port, 0x00);
+      assert_eq!(machine.in_byte(port), 0xFF, "Port {:X}", port);
+    }
   }
 }
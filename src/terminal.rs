@@ -0,0 +1,299 @@
+//Text mode front end for a host terminal, such as over SSH.
+//The screen is drawn with ANSI escape codes, and host key presses are sent to the keyboard as XT scancodes.
+//Ctrl+] quits, since Ctrl+C goes to the emulated machine. Ctrl+\ saves a screenshot. Ctrl+^ flips the turbo switch.
+//
+//Raw mode is set with stty, so this only works on Unix-like hosts.
+
+use crate::{KeyboardMsg, MotherboardMsg, chips::keyboard};
+use crate::chips::crtc::TextScreen;
+
+use std::sync::mpsc;
+use std::{io, process, thread, time};
+use std::io::prelude::*;
+use std::fmt::Write as _;
+
+use log::debug;
+
+const QUIT: u8 = 0x1D;  //Ctrl+]
+const SCREENSHOT: u8 = 0x1C;  //Ctrl+\
+const TURBO: u8 = 0x1E;  //Ctrl+^
+const MIN_FRAME_TIME: time::Duration = time::Duration::from_millis(20);
+
+//Scancode set 1
+const ESCAPE: u8 = 0x01;
+const CTRL: u8 = 0x1D;
+const ALT: u8 = 0x38;
+
+const LOW_CP437: [char; 0x20] = [
+  ' ', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+  '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+const HIGH_CP437: [char; 0x80] = [
+  'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+  'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+  'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+  '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+  '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+  '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+  'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+  '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', ' ',
+];
+
+//CGA color number to ANSI color number. CGA goes blue, green, red. ANSI goes red, green, blue.
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+pub struct Terminal {
+  last_frame: Vec<(u8, u8)>,  //(character, attribute) as drawn, to only send what changed.
+  last_columns: usize,
+  last_cursor: Option<(usize, usize, bool)>,
+  last_draw: time::Instant,
+}
+
+pub fn cp437_to_char(character: u8) -> char {
+  match character {
+    0x00..=0x1F => LOW_CP437[character as usize],
+    0x7F => '⌂',
+    0x80..=0xFF => HIGH_CP437[character as usize - 0x80],
+    _ => character as char,
+  }
+}
+
+/// Puts the host terminal in raw mode on the alternate screen, and starts reading keys.
+pub fn start(messenger: mpsc::Sender<crate::Msg>) -> io::Result<Terminal> {
+  stty(&["raw", "-echo"])?;
+  let default_hook = std::panic::take_hook();
+  std::panic::set_hook(Box::new(move |info| {
+    restore();
+    default_hook(info);
+  }));
+  print!("\x1b[?1049h\x1b[2J");
+  io::stdout().flush()?;
+
+  thread::spawn(move || {
+    let mut stdin = io::stdin();
+    let mut buffer = [0; 64];
+    loop {
+      let len = match stdin.read(&mut buffer) {
+        Ok(0) | Err(_) => break,
+        Ok(len) => len,
+      };
+      if buffer[..len].contains(&QUIT) {
+        restore();
+        process::exit(0);
+      }
+      if buffer[..len].contains(&SCREENSHOT) {
+        messenger.send(crate::Msg::Motherboard(MotherboardMsg::Screenshot)).unwrap();
+      }
+      if buffer[..len].contains(&TURBO) {
+        messenger.send(crate::Msg::Motherboard(MotherboardMsg::ToggleTurbo)).unwrap();
+      }
+      let keys: Vec<u8> = buffer[..len].iter().copied().filter(|&byte| byte != SCREENSHOT && byte != TURBO).collect();
+      for (modifier, scancode) in translate_keys(&keys) {
+        press(&messenger, modifier, scancode);
+      }
+    }
+  });
+
+  Ok(Terminal {
+    last_frame: Vec::new(),
+    last_columns: 0,
+    last_cursor: None,
+    last_draw: time::Instant::now(),
+  })
+}
+
+fn stty(args: &[&str]) -> io::Result<()> {
+  let status = process::Command::new("stty").args(args).stdin(process::Stdio::inherit()).status()?;
+  if !status.success() {
+    return Err(io::Error::other("stty failed. Is stdin a terminal?"));
+  }
+  Ok(())
+}
+
+/// Leave the alternate screen and give the host terminal back.
+fn restore() {
+  print!("\x1b[0m\x1b[?25h\x1b[0 q\x1b[?1049l");
+  io::stdout().flush().ok();
+  stty(&["sane"]).ok();
+}
+
+/// The host terminal only tells us about key presses, so every key is pressed and released straight away.
+fn press(messenger: &mpsc::Sender<crate::Msg>, modifier: Option<u8>, scancode: u8) {
+  let send = |msg| messenger.send(crate::Msg::Keyboard(msg)).unwrap();
+  if let Some(modifier) = modifier { send(KeyboardMsg::KeyDown(modifier)); }
+  send(KeyboardMsg::KeyDown(scancode));
+  send(KeyboardMsg::KeyUp(scancode));
+  if let Some(modifier) = modifier { send(KeyboardMsg::KeyUp(modifier)); }
+}
+
+/// Turn what the host terminal sent into (modifier, scancode) key presses.
+fn translate_keys(input: &[u8]) -> Vec<(Option<u8>, u8)> {
+  //A lone escape arrives by itself. Escape sequences arrive all at once.
+  if input == [0x1B] {
+    return vec![(None, ESCAPE)];
+  }
+  let mut keys = Vec::new();
+  let mut index = 0;
+  while index < input.len() {
+    let byte = input[index];
+    index += 1;
+    if byte == 0x1B {
+      let (key, len) = translate_escape(&input[index..]);
+      index += len;
+      keys.extend(key);
+      continue;
+    }
+    keys.extend(translate_byte(byte));
+  }
+  keys
+}
+
+fn translate_byte(byte: u8) -> Option<(Option<u8>, u8)> {
+  match byte {
+    0x7F => Some((None, 0x0E)),  //Most terminals send DEL for backspace.
+    0x08 | 0x09 | 0x0D => keyboard::scancode_for_char(byte as char).map(|(scancode, _)| (None, scancode)),
+    0x01..=0x1A => keyboard::scancode_for_char((b'a' + byte - 1) as char).map(|(scancode, _)| (Some(CTRL), scancode)),
+    _ => {
+      let (scancode, shift) = keyboard::scancode_for_char(byte as char)?;
+      Some((shift.then_some(keyboard::LEFT_SHIFT), scancode))
+    },
+  }
+}
+
+/// Decode what follows an escape. Returns the key, and how many bytes it used.
+fn translate_escape(input: &[u8]) -> (Option<(Option<u8>, u8)>, usize) {
+  match input {
+    [b'[' | b'O', rest @ ..] => {
+      //Parameters, then a final byte.
+      let Some(end) = rest.iter().position(|byte| (0x40..=0x7E).contains(byte)) else {
+        return (None, input.len());
+      };
+      let parameter = std::str::from_utf8(&rest[..end]).ok().and_then(|text| text.split(';').next()?.parse::<u8>().ok());
+      let scancode = match (rest[end], parameter) {
+        (b'A', _) => Some(0x48),  //Up
+        (b'B', _) => Some(0x50),  //Down
+        (b'C', _) => Some(0x4D),  //Right
+        (b'D', _) => Some(0x4B),  //Left
+        (b'H', _) | (b'~', Some(1 | 7)) => Some(0x47),  //Home
+        (b'F', _) | (b'~', Some(4 | 8)) => Some(0x4F),  //End
+        (b'~', Some(2)) => Some(0x52),  //Insert
+        (b'~', Some(3)) => Some(0x53),  //Delete
+        (b'~', Some(5)) => Some(0x49),  //Page Up
+        (b'~', Some(6)) => Some(0x51),  //Page Down
+        (b'P'..=b'S', _) => Some(0x3B + rest[end] - b'P'),  //F1 - F4
+        (b'~', Some(code @ 11..=15)) => Some(0x3B + code - 11),  //F1 - F5
+        (b'~', Some(code @ 17..=21)) => Some(0x40 + code - 17),  //F6 - F10
+        _ => None,
+      };
+      if scancode.is_none() {
+        debug!("Unknown escape sequence {:?}", &input[..=end + 1]);
+      }
+      (scancode.map(|scancode| (None, scancode)), end + 2)
+    },
+    //Alt+key
+    [byte, ..] => (translate_byte(*byte).map(|(_, scancode)| (Some(ALT), scancode)), 1),
+    [] => (None, 0),
+  }
+}
+
+impl Terminal {
+  /// Draw whatever changed since the last frame.
+  pub fn draw(&mut self, screen: Option<TextScreen>) {
+    if self.last_draw.elapsed() < MIN_FRAME_TIME {
+      return;
+    }
+    self.last_draw = time::Instant::now();
+
+    let mut output = String::new();
+    let Some(mut screen) = screen else {
+      //Graphics modes aren't drawn. Leave a blank screen.
+      if !self.last_frame.is_empty() {
+        self.last_frame.clear();
+        self.last_cursor = None;
+        output.push_str("\x1b[0m\x1b[2J\x1b[?25l");
+        self.write(&output);
+      }
+      return;
+    };
+
+    let frame = std::mem::take(&mut screen.cells);
+    if frame.len() != self.last_frame.len() || screen.columns != self.last_columns {
+      output.push_str("\x1b[0m\x1b[2J");
+      self.last_frame = vec![(0, 0xFF); frame.len()];  //Impossible to match, so everything is drawn.
+      self.last_columns = screen.columns;
+    }
+
+    let mut last_attribute = None;
+    let mut cursor_at = None;
+    for (index, &(character, attribute)) in frame.iter().enumerate() {
+      if self.last_frame[index] == (character, attribute) {
+        continue;
+      }
+      let (column, row) = (index % screen.columns, index / screen.columns);
+      if cursor_at != Some(index) {
+        write!(output, "\x1b[{};{}H", row + 1, column + 1).unwrap();
+      }
+      if last_attribute != Some(attribute) {
+        output.push_str(&sgr(attribute, &screen));
+        last_attribute = Some(attribute);
+      }
+      output.push(cp437_to_char(character));
+      cursor_at = Some(index + 1);
+    }
+    self.last_frame = frame;
+
+    let cursor = screen.cursor.as_ref().map(|cursor| {
+      //A cursor taller than half the character is drawn as a block.
+      let block = cursor.end.saturating_sub(cursor.start) * 2 >= screen.character_height;
+      (cursor.column, cursor.row, block)
+    });
+    if cursor_at.is_some() || cursor != self.last_cursor {
+      match cursor {
+        Some((column, row, block)) => {
+          write!(output, "\x1b[{};{}H\x1b[{} q\x1b[?25h", row + 1, column + 1, if block { 1 } else { 3 }).unwrap();
+        },
+        None => output.push_str("\x1b[?25l"),
+      }
+      self.last_cursor = cursor;
+    }
+    if !output.is_empty() {
+      self.write(&output);
+    }
+  }
+
+  fn write(&self, output: &str) {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(output.as_bytes()).unwrap();
+    stdout.flush().unwrap();
+  }
+}
+
+/// The Select Graphic Rendition escape code for a character attribute.
+fn sgr(attribute: u8, screen: &TextScreen) -> String {
+  let blink = screen.blink && attribute & 0x80 != 0;
+  if !screen.color {
+    //Monochrome: 0x00, 0x08, 0x80 and 0x88 are invisible, 0x70 is reverse video, and foreground 1 is underlined.
+    let mut codes = vec!["0"];
+    match attribute & 0x77 {
+      0x00 => codes.push("8"),
+      0x70 => codes.push("7"),
+      foreground => {
+        if foreground & 0x07 == 0x01 { codes.push("4"); }
+        if attribute & 0x08 != 0 { codes.push("1"); }
+      },
+    }
+    if blink { codes.push("5"); }
+    return format!("\x1b[{}m", codes.join(";"));
+  }
+  let foreground = attribute & 0x0F;
+  let background = if screen.blink { (attribute >> 4) & 0x07 } else { attribute >> 4 };
+  let foreground = match foreground {
+    0..=7 => 30 + ANSI_COLORS[foreground as usize],
+    _ => 90 + ANSI_COLORS[foreground as usize - 8],
+  };
+  let background = match background {
+    0..=7 => 40 + ANSI_COLORS[background as usize],
+    _ => 100 + ANSI_COLORS[background as usize - 8],
+  };
+  format!("\x1b[0;{};{}{}m", foreground, background, if blink { ";5" } else { "" })
+}
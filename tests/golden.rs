@@ -0,0 +1,21 @@
+//Boot the emulator headlessly, and compare what ends up on screen against golden files in tests/golden.
+//After a change that is meant to alter the screen, rerun the command with --update-golden and check the new file.
+//A missing golden file is written by the first run, which still fails so it gets looked at.
+
+use std::process::Command;
+
+const MASTER_HZ: u64 = 14_318_180;
+
+fn remu(args: &[&str]) {
+  let output = Command::new(env!("CARGO_BIN_EXE_remu")).arg("--max-speed").args(args).output().unwrap();
+  assert!(output.status.success(), "remu {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr));
+}
+
+/// The whole POST screen of a machine with no floppy controller: the memory count, error 601 for the missing
+/// diskette adapter, and the prompt the BIOS stops at. 128KB keeps the memory count short.
+/// The POST takes about 14 emulated seconds, so a run that goes wrong gives up soon after.
+#[test]
+fn xt_bios_post() {
+  let cycles = (MASTER_HZ * 20).to_string();
+  remu(&["--video", "cga80", "--ram", "128", "--cycles", &cycles, "--until", "(RESUME = \"F1\" KEY)", "--golden", "tests/golden/xt-bios-post.txt"]);
+}